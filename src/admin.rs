@@ -0,0 +1,207 @@
+//! Routes for admins to manage other users, their sessions and their
+//! storage.
+use crate::audit::{AuditAction, AuditEvent};
+use crate::auth::{
+    check_password, check_username, result_buffer, AdminKey, Auth, AuthError, AuthErrorKind,
+    ClientInfo, LoginInfo, Role, Secret, UserInfo,
+};
+use crate::auth_backends::AuthState;
+use crate::boxchecker::BoxCheckerError;
+use crate::config::OkraConfig;
+use crate::storage::BoxPaths;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::{delete, get, post, put, State};
+
+/// Bytes used by a user's box database.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct StorageUsage {
+    pub username: String,
+    pub user_id: String,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewUser<'a> {
+    pub username: &'a str,
+    #[serde(borrow)]
+    pub password: Secret<&'a str>,
+    #[serde(default)]
+    pub role: Role,
+}
+
+/// Changes to a user, leaving out what stays the same.
+#[derive(Debug, Deserialize)]
+pub struct UserUpdate {
+    pub role: Option<Role>,
+    pub disabled: Option<bool>,
+}
+
+fn audit_admin<T>(
+    action: AuditAction,
+    username: &str,
+    admin: &AdminKey,
+    client: &ClientInfo,
+    result: &Result<T, AuthError>,
+) {
+    AuditEvent::new(action, username, client, result)
+        .by(&admin.0)
+        .log();
+}
+
+fn storage_error(e: BoxCheckerError) -> AuthError {
+    AuthError::new(AuthErrorKind::Storage, &e.to_string())
+}
+
+/// Admins may not lock themselves out, leaving no one to let them back in.
+fn check_not_self(username: &str, admin: &AdminKey) -> Result<(), AuthError> {
+    if username == admin.0 {
+        Err(AuthError::new(
+            AuthErrorKind::Conflict,
+            "admins cannot demote, disable or delete themselves",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// List users by username, starting after the username given as a cursor.
+#[get("/admin/users/<max_results>?<after>")]
+pub fn list_users(
+    max_results: usize,
+    after: Option<&str>,
+    _admin: AdminKey,
+    auth_state: &State<AuthState>,
+) -> Result<Json<Vec<UserInfo>>, AuthError> {
+    let mut dest = result_buffer(max_results);
+    let num_results = auth_state
+        .users()
+        .get_users(after.unwrap_or_default(), &mut dest)?;
+    dest.truncate(num_results);
+    Ok(Json(dest))
+}
+
+/// Add a user with the given password and role, by default the user role.
+#[post("/admin/users", format = "application/json", data = "<new_user>")]
+pub fn create_user(
+    new_user: Json<NewUser>,
+    admin: AdminKey,
+    client: ClientInfo,
+    auth_state: &State<AuthState>,
+    config: &State<OkraConfig>,
+) -> Result<Json<UserInfo>, AuthError> {
+    let login = LoginInfo {
+        username: new_user.username,
+        password: new_user.password.expose(),
+    };
+    let created = auth_state
+        .manages_passwords()
+        .and_then(|_| check_username(login.username))
+        .and_then(|_| check_password(&login, config.min_password_length))
+        .and_then(|_| {
+            let mut auth = auth_state.users();
+            auth.add_user_with_role(&login, new_user.role)?;
+            auth.get_user(login.username)
+        });
+    audit_admin(
+        AuditAction::UserCreate,
+        login.username,
+        &admin,
+        &client,
+        &created,
+    );
+    Ok(Json(created?))
+}
+
+/// Change a user's role, or disable or re-enable their logins, e.g. with
+/// {"disabled": true}. Disabling also ends their sessions and revokes their
+/// tokens.
+#[put(
+    "/admin/users/<username>",
+    format = "application/json",
+    data = "<update>"
+)]
+pub fn update_user(
+    username: &str,
+    update: Json<UserUpdate>,
+    admin: AdminKey,
+    client: ClientInfo,
+    auth_state: &State<AuthState>,
+) -> Result<Json<UserInfo>, AuthError> {
+    let updated = check_not_self(username, &admin).and_then(|_| {
+        let mut auth = auth_state.users();
+        if let Some(role) = update.role {
+            auth.set_role(username, role)?;
+        }
+        if let Some(disabled) = update.disabled {
+            auth.set_disabled(username, disabled)?;
+        }
+        auth.get_user(username)
+    });
+    audit_admin(AuditAction::UserUpdate, username, &admin, &client, &updated);
+    Ok(Json(updated?))
+}
+
+/// Delete a user's credentials, sessions, tokens and boxes.
+#[delete("/admin/users/<username>")]
+pub fn remove_user(
+    username: &str,
+    admin: AdminKey,
+    client: ClientInfo,
+    auth_state: &State<AuthState>,
+    config: &State<OkraConfig>,
+) -> Result<String, AuthError> {
+    let removed = check_not_self(username, &admin).and_then(|_| {
+        BoxPaths::new(&config.data_dir).delete_account(&mut *auth_state.users(), username)
+    });
+    audit_admin(AuditAction::UserDelete, username, &admin, &client, &removed);
+    removed?;
+    Ok("OK".to_string())
+}
+
+/// End all of a user's sessions, logging them out everywhere.
+#[delete("/admin/users/<username>/sessions")]
+pub fn end_user_sessions(
+    username: &str,
+    admin: AdminKey,
+    client: ClientInfo,
+    auth_state: &State<AuthState>,
+) -> Result<String, AuthError> {
+    let ended = {
+        let mut auth = auth_state.users();
+        auth.get_user(username)
+            .and_then(|_| auth.revoke_sessions(username))
+    };
+    audit_admin(AuditAction::SessionsEnd, username, &admin, &client, &ended);
+    ended?;
+    Ok("OK".to_string())
+}
+
+/// List the bytes used by each user's boxes, by username, starting after
+/// the username given as a cursor.
+#[get("/admin/storage/<max_results>?<after>")]
+pub fn get_storage_usage(
+    max_results: usize,
+    after: Option<&str>,
+    _admin: AdminKey,
+    auth_state: &State<AuthState>,
+    config: &State<OkraConfig>,
+) -> Result<Json<Vec<StorageUsage>>, AuthError> {
+    let mut users = result_buffer(max_results);
+    let num_results = auth_state
+        .users()
+        .get_users(after.unwrap_or_default(), &mut users)?;
+    users.truncate(num_results);
+    let paths = BoxPaths::new(&config.data_dir);
+    users
+        .into_iter()
+        .map(|user| {
+            Ok(StorageUsage {
+                bytes: paths.size(&user.user_id).map_err(storage_error)?,
+                username: user.username,
+                user_id: user.user_id,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(Json)
+}
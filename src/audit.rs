@@ -0,0 +1,100 @@
+//! A stream of authentication and admin outcomes, logged as JSON lines
+//! under the "audit" target, e.g. to keep with `RUST_LOG=audit=info`.
+//! Events name the user, client address, outcome and time, but never
+//! passwords, codes or tokens.
+use crate::auth::{AuthError, AuthErrorKind, ClientInfo};
+use rocket::serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub static AUDIT_TARGET: &str = "audit";
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Login,
+    LoginTotp,
+    LoginOidc,
+    Logout,
+    Register,
+    PasswordChange,
+    PasswordReset,
+    TotpEnable,
+    TotpDisable,
+    TokenCreate,
+    TokenRevoke,
+    UserCreate,
+    UserUpdate,
+    UserDelete,
+    SessionsEnd,
+    AccountDelete,
+    AccountRestore,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    Failure(AuthErrorKind),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AuditEvent {
+    pub time: i64,
+    pub action: AuditAction,
+    pub username: String,
+    pub ip: String,
+    pub outcome: AuditOutcome,
+    /// The admin acting on the user, if not the user themselves.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub by: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new<T>(
+        action: AuditAction,
+        username: &str,
+        client: &ClientInfo,
+        result: &Result<T, AuthError>,
+    ) -> Self {
+        AuditEvent {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as i64,
+            action,
+            username: username.to_string(),
+            ip: client.ip.clone(),
+            outcome: match result {
+                Ok(_) => AuditOutcome::Success,
+                Err(e) => AuditOutcome::Failure(e.kind),
+            },
+            by: None,
+        }
+    }
+
+    pub fn by(mut self, admin: &str) -> Self {
+        self.by = Some(admin.to_string());
+        self
+    }
+
+    pub fn log(&self) {
+        match serde_json::to_string(self) {
+            Ok(line) => log::info!(target: AUDIT_TARGET, "{}", line),
+            Err(e) => log::error!("cannot serialize audit event: {}", e),
+        }
+    }
+}
+
+/// Log the outcome of the action by or for the user.
+pub fn audit<T>(
+    action: AuditAction,
+    username: &str,
+    client: &ClientInfo,
+    result: &Result<T, AuthError>,
+) {
+    AuditEvent::new(action, username, client, result).log();
+}
+
+#[cfg(test)]
+#[path = "./audit_test.rs"]
+mod audit_test;
@@ -0,0 +1,140 @@
+use super::*;
+use crate::auth::{bad_credentials, Auth, LoginInfo, Secret, SqliteAuth};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::sync::{Mutex, Once};
+
+static CAPTURED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static INSTALL: Once = Once::new();
+
+struct CaptureLogger;
+
+impl Log for CaptureLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        CAPTURED.lock().unwrap().push(format!(
+            "{} {} {}",
+            record.level(),
+            record.target(),
+            record.args()
+        ));
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CaptureLogger = CaptureLogger;
+
+fn capture_logs() {
+    INSTALL.call_once(|| {
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(LevelFilter::Trace);
+    });
+}
+
+fn captured() -> String {
+    CAPTURED.lock().unwrap().join("\n")
+}
+
+fn client() -> ClientInfo {
+    ClientInfo {
+        user_agent: "curl".to_string(),
+        ip: "10.9.8.7".to_string(),
+    }
+}
+
+#[test]
+fn audits_outcomes() {
+    capture_logs();
+    audit(
+        AuditAction::Login,
+        "audit-carol",
+        &client(),
+        &Err::<(), _>(bad_credentials()),
+    );
+    audit(AuditAction::Logout, "audit-carol", &client(), &Ok(()));
+    AuditEvent::new(AuditAction::PasswordReset, "audit-dave", &client(), &Ok(()))
+        .by("audit-admin")
+        .log();
+
+    let logs = captured();
+    let events: Vec<serde_json::Value> = logs
+        .lines()
+        .filter_map(|line| line.strip_prefix(&format!("{} {} ", Level::Info, AUDIT_TARGET)))
+        .map(|json| serde_json::from_str(json).unwrap())
+        .filter(|event: &serde_json::Value| {
+            event["username"] == "audit-carol" || event["username"] == "audit-dave"
+        })
+        .collect();
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[0]["action"], "login");
+    assert_eq!(events[0]["ip"], "10.9.8.7");
+    assert_eq!(events[0]["outcome"]["failure"], "unauthorized");
+    assert!(events[0]["time"].as_i64().unwrap() > 0);
+    assert!(events[0].get("by").is_none());
+    assert_eq!(events[1]["outcome"], "success");
+    assert_eq!(events[2]["by"], "audit-admin");
+}
+
+#[test]
+fn never_logs_passwords() {
+    capture_logs();
+    let password = "s3cret-audit-pw";
+    let wrong_password = "s3cret-wrong-pw";
+    let mut auth = SqliteAuth::new(":memory:").unwrap();
+    auth.add_user(&LoginInfo {
+        username: "audit-bob",
+        password,
+    })
+    .unwrap();
+    let good = LoginInfo {
+        username: "audit-bob",
+        password,
+    };
+    let bad = LoginInfo {
+        username: "audit-bob",
+        password: wrong_password,
+    };
+    let nobody = LoginInfo {
+        username: "audit-nobody",
+        password: wrong_password,
+    };
+    let verified = auth.auth_user(&good);
+    audit(AuditAction::Login, "audit-bob", &client(), &verified);
+    let failed = auth.auth_user(&bad);
+    audit(AuditAction::Login, "audit-bob", &client(), &failed);
+    let missing = auth.auth_user(&nobody);
+    audit(AuditAction::Login, "audit-nobody", &client(), &missing);
+    assert!(verified.is_ok());
+    assert!(failed.is_err());
+    assert!(missing.is_err());
+    log::debug!("{:?} {:?}", good, Secret::new(password));
+
+    let logs = captured();
+    assert!(logs.contains("audit-bob"));
+    assert!(logs.contains("audit-nobody"));
+    assert!(!logs.contains(password));
+    assert!(!logs.contains(wrong_password));
+    assert!(!logs.contains("$2b$"), "bcrypt hash logged");
+}
+
+#[test]
+fn redacts_debug_output() {
+    let login = LoginInfo {
+        username: "bob",
+        password: "hunter2hunter2",
+    };
+    let debugged = format!("{:?}", login);
+    assert!(debugged.contains("bob"));
+    assert!(!debugged.contains("hunter2"));
+
+    let secret = Secret::new("hunter2hunter2".to_string());
+    assert_eq!(format!("{:?}", secret), "[redacted]");
+    assert_eq!(secret.expose(), "hunter2hunter2");
+    assert_eq!(
+        serde_json::to_string(&secret).unwrap(),
+        "\"hunter2hunter2\""
+    );
+}
@@ -1,259 +1,2562 @@
-use bcrypt::{hash, verify, DEFAULT_COST};
+use crate::audit::{audit, AuditAction, AuditEvent};
+use crate::auth_backends::AuthState;
+use crate::config::{OkraConfig, RegistrationPolicy};
+use crate::passwords::{self, PasswordPolicy};
+use crate::storage::BoxPaths;
+use crate::totp;
+use rand::rngs::OsRng;
+use rand::RngCore;
 use rocket::http::{Cookie, CookieJar, Status};
-use rocket::outcome::Outcome;
+use rocket::outcome::{try_outcome, Outcome};
 use rocket::request::{FromRequest, Request};
-use rocket::serde::json::Json;
-use rocket::serde::Deserialize;
-use rocket::{get, post, request};
-use sqlite::{Connection, State};
+use rocket::response::{self, Responder};
+use rocket::serde::json::{self, Json};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::{catch, delete, get, post, request, State as RocketState};
+use sha2::{Digest, Sha256};
+use sqlite::{Connection, OpenFlags, State};
+use std::fmt;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 static AUTH_COOKIE: &str = "auth";
-static USERS_DB_NAME: &str = "data/users.sqlite";
 static USERS_COL_NAME: &str = "username";
 static USERS_TABLE_NAME: &str = "users";
 static SECRET_COL_NAME: &str = "secret";
-static SESSION_DURATION: Duration = Duration::new(7 * 24 * 60 * 69, 0);
+static USER_ID_COL_NAME: &str = "userId";
+static ROLE_COL_NAME: &str = "role";
+static DISABLED_COL_NAME: &str = "disabled";
+static SESSIONS_TABLE_NAME: &str = "sessions";
+static TOKENS_TABLE_NAME: &str = "apiTokens";
+static INVITES_TABLE_NAME: &str = "redeemedInvites";
+static ATTEMPTS_TABLE_NAME: &str = "loginFailures";
+static TOTP_TABLE_NAME: &str = "totp";
+static RECOVERY_TABLE_NAME: &str = "recoveryCodes";
+static CHALLENGES_TABLE_NAME: &str = "loginChallenges";
+static DELETIONS_TABLE_NAME: &str = "accountDeletions";
+static OIDC_LOGINS_TABLE_NAME: &str = "oidcLogins";
+static OIDC_LINKS_TABLE_NAME: &str = "oidcLinks";
+static BEARER_PREFIX: &str = "Bearer ";
+static SESSION_BYTES: usize = 32;
+static USER_ID_BYTES: usize = 16;
+static RECOVERY_CODES: usize = 10;
+static RECOVERY_CODE_BYTES: usize = 5;
+static CHALLENGE_MILLIS: i64 = 5 * 60 * 1000;
+pub(crate) static OIDC_LOGIN_MILLIS: i64 = 10 * 60 * 1000;
+static MAX_USERNAME_LENGTH: usize = 32;
+/// Most results returned by a route, however many are asked for.
+pub static MAX_RESULTS: usize = 1000;
+static SQLITE_CONSTRAINT: isize = 19;
 
-#[derive(Debug, Deserialize)]
+#[derive(Deserialize)]
 pub struct LoginInfo<'a> {
     pub username: &'a str,
     pub password: &'a str,
 }
 
-#[derive(Debug)]
+impl fmt::Debug for LoginInfo<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoginInfo")
+            .field("username", &self.username)
+            .field("password", &REDACTED)
+            .finish()
+    }
+}
+
+static REDACTED: &str = "[redacted]";
+
+/// A password, code or token, kept out of Debug output and so out of logs.
+/// It (de)serializes as the bare value.
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(secret: T) -> Self {
+        Secret(secret)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthErrorKind {
+    Unauthorized,
+    SessionExpired,
+    Forbidden,
+    NotFound,
+    Conflict,
+    InvalidInput,
+    TooManyAttempts,
+    Storage,
+}
+
+/// Authentication failures, serialized for clients as
+/// {"error": "session_expired", "msg": "..."}.
+#[derive(Clone, Debug, Serialize)]
 pub struct AuthError {
+    #[serde(rename = "error")]
+    pub kind: AuthErrorKind,
     pub msg: String,
 }
 
+impl AuthError {
+    pub fn new(kind: AuthErrorKind, msg: &str) -> Self {
+        AuthError {
+            kind,
+            msg: msg.to_string(),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for AuthError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let status = match self.kind {
+            AuthErrorKind::Unauthorized | AuthErrorKind::SessionExpired => Status::Unauthorized,
+            AuthErrorKind::Forbidden => Status::Forbidden,
+            AuthErrorKind::NotFound => Status::NotFound,
+            AuthErrorKind::Conflict => Status::Conflict,
+            AuthErrorKind::InvalidInput => Status::BadRequest,
+            AuthErrorKind::TooManyAttempts => Status::TooManyRequests,
+            AuthErrorKind::Storage => Status::InternalServerError,
+        };
+        if status == Status::InternalServerError {
+            log::error!("{} {}: {}", request.method(), request.uri(), self.msg);
+        }
+        (status, Json(self)).respond_to(request)
+    }
+}
+
+/// Make room for a route's results, up to MAX_RESULTS.
+pub fn result_buffer<T: Clone + Default>(max_results: usize) -> Vec<T> {
+    vec![T::default(); max_results.min(MAX_RESULTS)]
+}
+
+/// Usernames become part of the user's database file name, so keep them to
+/// letters, digits, '-' and '_', starting with a letter or digit.
+pub fn check_username(username: &str) -> Result<(), AuthError> {
+    let mut chars = username.chars();
+    let valid = username.len() <= MAX_USERNAME_LENGTH
+        && matches!(chars.next(), Some(c) if c.is_ascii_alphanumeric())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(AuthError::new(
+            AuthErrorKind::InvalidInput,
+            &format!(
+                "usernames must be 1 to {} letters, digits, '-' or '_', starting with a letter or digit",
+                MAX_USERNAME_LENGTH
+            ),
+        ))
+    }
+}
+
+/// Require passwords to be at least the minimum length, to mix at least two
+/// of letters, digits and other characters, and not to contain the username.
+pub fn check_password(login: &LoginInfo, min_length: usize) -> Result<(), AuthError> {
+    let password = login.password;
+    let weak = |msg: &str| Err(AuthError::new(AuthErrorKind::InvalidInput, msg));
+    if password.chars().count() < min_length {
+        return weak(&format!(
+            "passwords must be at least {} characters",
+            min_length
+        ));
+    }
+    let classes = [
+        password.chars().any(|c| c.is_alphabetic()),
+        password.chars().any(|c| c.is_numeric()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ];
+    if classes.iter().filter(|has| **has).count() < 2 {
+        return weak("passwords must mix letters, digits or other characters");
+    }
+    if !login.username.is_empty()
+        && password
+            .to_lowercase()
+            .contains(&login.username.to_lowercase())
+    {
+        return weak("passwords must not contain the username");
+    }
+    Ok(())
+}
+
+/// Sessions end after the idle timeout without a refresh, and after the
+/// absolute timeout regardless of refreshes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SessionTimeouts {
+    pub idle: Duration,
+    pub absolute: Duration,
+}
+
+impl Default for SessionTimeouts {
+    fn default() -> Self {
+        SessionTimeouts {
+            idle: Duration::from_secs(7 * 24 * 60 * 60),
+            absolute: Duration::from_secs(30 * 24 * 60 * 60),
+        }
+    }
+}
+
+/// Failed logins for a username or client address delay the next attempt,
+/// doubling from the backoff with each failure, until the maximum number of
+/// failures locks logins out for the lockout duration.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoginLimits {
+    pub backoff: Duration,
+    pub max_failures: u32,
+    pub lockout: Duration,
+}
+
+impl Default for LoginLimits {
+    fn default() -> Self {
+        LoginLimits {
+            backoff: Duration::from_secs(1),
+            max_failures: 10,
+            lockout: Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+/// Recent failed logins for a username or client address, as listed for
+/// admins.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct LoginFailures {
+    /// Either "username" or "ip".
+    pub kind: String,
+    pub key: String,
+    pub failures: i64,
+    pub last_failure: i64,
+    pub locked_until: i64,
+}
+
+/// A new TOTP secret to load into an authenticator app, either by scanning
+/// the URI as a QR code or typing in the secret.
+#[derive(Clone, Debug, Serialize)]
+pub struct TotpEnrollment {
+    pub secret: Secret<String>,
+    pub uri: Secret<String>,
+}
+
 macro_rules! unwrap_msg {
     ($sql_err:expr) => {
         $sql_err.message.unwrap_or("???".to_string())
     };
 }
 
+/// A signed-in client as listed for its user.
+/// The id is a handle for revoking the session, distinct from the secret
+/// session token kept in the client's cookie.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Session {
+    pub id: i64,
+    pub created: i64,
+    pub last_seen: i64,
+    pub expires: i64,
+    pub user_agent: String,
+    pub ip: String,
+    pub current: bool,
+}
+
+/// What an API token may be used for: everything, reading only, or logging
+/// activities and notes only.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenScope {
+    #[default]
+    Full,
+    ReadOnly,
+    LogOnly,
+}
+
+impl TokenScope {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TokenScope::Full => "full",
+            TokenScope::ReadOnly => "read_only",
+            TokenScope::LogOnly => "log_only",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "full" => Some(TokenScope::Full),
+            "read_only" => Some(TokenScope::ReadOnly),
+            "log_only" => Some(TokenScope::LogOnly),
+            _ => None,
+        }
+    }
+}
+
+/// What a user may do: use their own boxes, or also administer others.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[default]
+    User,
+    Admin,
+}
+
+impl Role {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Admin => "admin",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "user" => Some(Role::User),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
+/// A user as listed for admins, without their credentials.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct UserInfo {
+    pub username: String,
+    pub user_id: String,
+    pub role: Role,
+    pub disabled: bool,
+}
+
+/// A named API token as listed for its user, without its secret.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ApiToken {
+    pub id: i64,
+    pub name: String,
+    pub scope: TokenScope,
+    pub created: i64,
+    pub last_used: Option<i64>,
+}
+
+/// Request details recorded with a new session.
+#[derive(Clone, Debug, Default)]
+pub struct ClientInfo {
+    pub user_agent: String,
+    pub ip: String,
+}
+
 pub trait Auth {
     fn add_user(&mut self, login: &LoginInfo) -> Result<bool, AuthError>;
-    fn auth_cookie(&self, cookie: &str) -> Result<String, AuthError>;
+    /// Add the user with the role in a single write, so that no user is
+    /// left with the wrong role.
+    fn add_user_with_role(&mut self, login: &LoginInfo, role: Role) -> Result<bool, AuthError>;
+    /// Add the user without a password, if missing, e.g. for users
+    /// authenticated by another backend.
+    fn ensure_user(&mut self, username: &str) -> Result<(), AuthError>;
+    /// Look up the stable id naming the user's storage.
+    fn get_user_id(&self, username: &str) -> Result<String, AuthError>;
+    /// Remove the user's credentials, sessions and tokens.
+    fn delete_user(&mut self, username: &str) -> Result<(), AuthError>;
+    fn get_user(&self, username: &str) -> Result<UserInfo, AuthError>;
+    /// List users by username, starting after the username, or from the
+    /// first user if empty.
+    fn get_users(&self, after: &str, dest: &mut Vec<UserInfo>) -> Result<usize, AuthError>;
+    fn set_role(&mut self, username: &str, role: Role) -> Result<(), AuthError>;
+    /// Disable or re-enable the user's logins. Disabling also ends the
+    /// user's sessions and revokes their tokens.
+    fn set_disabled(&mut self, username: &str, disabled: bool) -> Result<(), AuthError>;
+    /// Schedule the user's account to be purged after the time, in epoch
+    /// milliseconds, ending their sessions and revoking their tokens.
+    fn schedule_deletion(&mut self, username: &str, purge_after: i64) -> Result<(), AuthError>;
+    /// Keep the user's account after all, failing if no deletion was
+    /// scheduled.
+    fn cancel_deletion(&mut self, username: &str) -> Result<(), AuthError>;
+    /// List users whose accounts are due to be purged at the time.
+    fn get_due_deletions(&self, now: i64, dest: &mut Vec<String>) -> Result<usize, AuthError>;
+    /// Mark the invite code as used by the user, failing if it was already
+    /// used.
+    fn redeem_invite(&mut self, code: &str, username: &str) -> Result<(), AuthError>;
+    /// Look up the live session for the token.
+    fn auth_cookie(&self, cookie: &str) -> Result<SessionKey, AuthError>;
+    /// Check the username and password, returning the username.
     fn auth_user(&self, login: &LoginInfo) -> Result<String, AuthError>;
+
+    /// Refuse logins for the username or from the address while they are
+    /// backing off after failures.
+    fn check_login(&self, username: &str, ip: &str) -> Result<(), AuthError>;
+    /// Count a failed login against the username and address, or clear the
+    /// username's failures on success.
+    fn record_login(&mut self, username: &str, ip: &str, success: bool) -> Result<(), AuthError>;
+    /// List usernames and addresses with recent failed logins, most recent
+    /// first.
+    fn get_login_failures(&self, dest: &mut Vec<LoginFailures>) -> Result<usize, AuthError>;
+
+    /// Start enrolling the user in TOTP, replacing any unconfirmed secret.
+    fn enroll_totp(&mut self, username: &str, issuer: &str) -> Result<TotpEnrollment, AuthError>;
+    /// Turn on TOTP once the user shows a code from the new secret,
+    /// returning fresh one-time recovery codes.
+    fn confirm_totp(&mut self, username: &str, code: &str) -> Result<Vec<String>, AuthError>;
+    fn has_totp(&self, username: &str) -> Result<bool, AuthError>;
+    /// Check a TOTP code, or use up a recovery code, for the user.
+    /// Each TOTP code is accepted at most once.
+    fn verify_totp(&mut self, username: &str, code: &str) -> Result<(), AuthError>;
+    fn disable_totp(&mut self, username: &str) -> Result<(), AuthError>;
+    /// Remember that the user passed the first login step, returning a
+    /// short-lived token to present with the second.
+    fn create_login_challenge(&mut self, username: &str) -> Result<(String, i64), AuthError>;
+    /// Use up the login challenge, returning its username.
+    fn take_login_challenge(&mut self, challenge: &str) -> Result<String, AuthError>;
+
+    /// Remember a login sent to an OpenID Connect provider until it returns
+    /// with the state, clearing out expired logins.
+    fn create_oidc_login(
+        &mut self,
+        state: &str,
+        verifier: &str,
+        nonce: &str,
+    ) -> Result<(), AuthError>;
+    /// Use up the login for the state, returning its PKCE verifier and nonce.
+    fn take_oidc_login(&mut self, state: &str) -> Result<(String, String), AuthError>;
+    /// Look up the user linked to the subject at the issuer, if any.
+    fn get_oidc_user(&self, issuer: &str, subject: &str) -> Result<Option<String>, AuthError>;
+    fn link_oidc_user(
+        &mut self,
+        issuer: &str,
+        subject: &str,
+        username: &str,
+    ) -> Result<(), AuthError>;
+
+    /// Start a session for the user, returning its secret token.
+    fn create_session(&mut self, username: &str, client: &ClientInfo) -> Result<String, AuthError>;
+    fn get_sessions(&self, username: &str, dest: &mut Vec<Session>) -> Result<usize, AuthError>;
+    /// Push back the idle expiry of the live session for the token, up to its
+    /// absolute expiry.
+    fn refresh_session(&mut self, cookie: &str) -> Result<SessionKey, AuthError>;
+    fn revoke_session(&mut self, username: &str, session: i64) -> Result<(), AuthError>;
+    fn revoke_sessions(&mut self, username: &str) -> Result<(), AuthError>;
+    /// Replace the user's password hash, leaving sessions to the caller.
+    fn set_password(&mut self, username: &str, password: &str) -> Result<(), AuthError>;
+
+    /// Look up the user and scope for a bearer token, recording its use.
+    fn auth_token(&self, token: &str) -> Result<(String, TokenScope), AuthError>;
+    /// Mint a named token for the user, returning its id and its secret,
+    /// which is only stored hashed.
+    fn create_token(
+        &mut self,
+        username: &str,
+        name: &str,
+        scope: TokenScope,
+    ) -> Result<(i64, String), AuthError>;
+    fn get_tokens(&self, username: &str, dest: &mut Vec<ApiToken>) -> Result<usize, AuthError>;
+    fn revoke_token(&mut self, username: &str, token: i64) -> Result<(), AuthError>;
+}
+
+/// Check the password against the user's stored hash, as found by
+/// SqliteAuth::password_hash, returning a new hash to store if the policy
+/// has moved on since.
+/// This needs no database, so a shared SqliteAuth needn't stay locked while
+/// hashing.
+pub fn verify_password(
+    policy: &PasswordPolicy,
+    login: &LoginInfo,
+    stored: Option<&str>,
+) -> Result<Option<String>, AuthError> {
+    let stored = match stored {
+        Some(stored) => stored,
+        None => {
+            // Spend as long as checking a password would, so response
+            // times don't reveal which usernames exist.
+            policy.hash(login.password).ok();
+            log::info!("no user: {}", login.username);
+            return Err(bad_credentials());
+        }
+    };
+    log::debug!("checking password for {}", login.username);
+    if !passwords::verify(login.password, stored)? {
+        log::info!("invalid password for {}", login.username);
+        return Err(bad_credentials());
+    }
+    if !policy.needs_rehash(stored) {
+        return Ok(None);
+    }
+    match policy.hash(login.password) {
+        Ok(hashed) => Ok(Some(hashed)),
+        Err(e) => {
+            log::warn!("cannot re-hash password for {}: {}", login.username, e.msg);
+            Ok(None)
+        }
+    }
 }
 
 pub struct SqliteAuth {
+    clock: fn() -> i64,
     conn: Connection,
+    limits: LoginLimits,
+    passwords: PasswordPolicy,
+    timeouts: SessionTimeouts,
 }
 
+// The sqlite crate leaves connections !Send, as SQLite's default threading
+// mode depends on how the library was built. SqliteAuth may still move
+// between threads, since:
+// - new() opens its connection with SQLITE_OPEN_FULLMUTEX, i.e. in SQLite's
+//   serialized mode, where a connection may be used from any thread, see
+//   https://www.sqlite.org/threadsafe.html;
+// - statements are prepared and finalized within each method, so none
+//   outlives its borrow of the connection or crosses threads on its own;
+// - the remaining fields are plain data and a fn pointer.
+// AuthState's Mutex then lends SqliteAuth to one thread at a time.
+unsafe impl Send for SqliteAuth {}
+
 impl SqliteAuth {
     pub fn new(path: &str) -> Result<Self, sqlite::Error> {
-        let conn = sqlite::open(path).unwrap();
+        let flags = OpenFlags::new()
+            .set_create()
+            .set_read_write()
+            .set_full_mutex();
+        let conn = Connection::open_with_flags(path, flags)?;
         let query = format!(
             "
-                CREATE TABLE IF NOT EXISTS {} ({} TEXT UNIQUE, {} TEXT);
+                CREATE TABLE IF NOT EXISTS {} (
+                    {} TEXT UNIQUE, {} TEXT, {} TEXT, {} TEXT NOT NULL DEFAULT 'user',
+                    {} INTEGER NOT NULL DEFAULT 0);
                 CREATE INDEX IF NOT EXISTS idx_username ON {} ({});
             ",
-            USERS_TABLE_NAME, USERS_COL_NAME, SECRET_COL_NAME, USERS_TABLE_NAME, USERS_COL_NAME
+            USERS_TABLE_NAME,
+            USERS_COL_NAME,
+            SECRET_COL_NAME,
+            USER_ID_COL_NAME,
+            ROLE_COL_NAME,
+            DISABLED_COL_NAME,
+            USERS_TABLE_NAME,
+            USERS_COL_NAME
         );
         {
             // explicit lifetime to avoid conn.drop();
             let mut stat = conn.prepare(query)?;
             stat.next()?;
         }
-        Ok(SqliteAuth { conn: conn })
+        Self::assign_user_ids(&conn)?;
+        Self::add_roles(&conn)?;
+        conn.execute(format!(
+            "
+                CREATE TABLE IF NOT EXISTS {} (
+                    hash TEXT UNIQUE, {} TEXT, created INTEGER, lastSeen INTEGER,
+                    expires INTEGER, userAgent TEXT, ip TEXT);
+                CREATE INDEX IF NOT EXISTS idx_session_username ON {} ({});
+                CREATE TABLE IF NOT EXISTS {} (
+                    hash TEXT UNIQUE, {} TEXT, name TEXT, scope TEXT, created INTEGER,
+                    lastUsed INTEGER);
+                CREATE TABLE IF NOT EXISTS {} (code TEXT UNIQUE, {} TEXT, redeemed INTEGER);
+                CREATE TABLE IF NOT EXISTS {} (
+                    kind TEXT, key TEXT, failures INTEGER, lastFailure INTEGER,
+                    lockedUntil INTEGER, UNIQUE(kind, key));
+                CREATE TABLE IF NOT EXISTS {} (
+                    {} TEXT UNIQUE, secret TEXT, enabled INTEGER, lastStep INTEGER);
+                CREATE TABLE IF NOT EXISTS {} ({} TEXT, hash TEXT);
+                CREATE TABLE IF NOT EXISTS {} (hash TEXT UNIQUE, {} TEXT, expires INTEGER);
+                CREATE TABLE IF NOT EXISTS {} ({} TEXT UNIQUE, purgeAfter INTEGER);
+                CREATE TABLE IF NOT EXISTS {} (
+                    hash TEXT UNIQUE, verifier TEXT, nonce TEXT, expires INTEGER);
+                CREATE TABLE IF NOT EXISTS {} (
+                    issuer TEXT, subject TEXT, {} TEXT, UNIQUE(issuer, subject));
+            ",
+            SESSIONS_TABLE_NAME,
+            USERS_COL_NAME,
+            SESSIONS_TABLE_NAME,
+            USERS_COL_NAME,
+            TOKENS_TABLE_NAME,
+            USERS_COL_NAME,
+            INVITES_TABLE_NAME,
+            USERS_COL_NAME,
+            ATTEMPTS_TABLE_NAME,
+            TOTP_TABLE_NAME,
+            USERS_COL_NAME,
+            RECOVERY_TABLE_NAME,
+            USERS_COL_NAME,
+            CHALLENGES_TABLE_NAME,
+            USERS_COL_NAME,
+            DELETIONS_TABLE_NAME,
+            USERS_COL_NAME,
+            OIDC_LOGINS_TABLE_NAME,
+            OIDC_LINKS_TABLE_NAME,
+            USERS_COL_NAME
+        ))?;
+        Self::hash_session_tokens(&conn)?;
+        Ok(SqliteAuth {
+            clock: get_time,
+            conn: conn,
+            limits: LoginLimits::default(),
+            passwords: PasswordPolicy::default(),
+            timeouts: SessionTimeouts::default(),
+        })
     }
-}
 
-/// Check that the plaintext/extracted authorization cookie is valid and
-/// has not yet expired.
-/// The format of the token is the epoch milliseconds followed by the (unchecked)
-/// username.
-fn auth_cookie(cookie: &str) -> Result<String, AuthError> {
-    let tokens: Vec<&str> = cookie.split(' ').collect();
-    if tokens.len() < 2 {
-        return Err(AuthError {
-            msg: format!("invalid auth cookie: '{}'", cookie),
-        });
+    /// Hash the session tokens kept in plaintext before sessions.hash
+    /// existed, so their sessions carry on.
+    fn hash_session_tokens(conn: &Connection) -> Result<(), sqlite::Error> {
+        let mut has_plaintext = false;
+        let mut stat = conn.prepare(format!("PRAGMA table_info({});", SESSIONS_TABLE_NAME))?;
+        while let State::Row = stat.next()? {
+            has_plaintext |= stat.read::<String>(1)? == "token";
+        }
+        if !has_plaintext {
+            return Ok(());
+        }
+
+        let mut tokens = Vec::new();
+        let mut stat =
+            conn.prepare(format!("SELECT rowid, token FROM {};", SESSIONS_TABLE_NAME))?;
+        while let State::Row = stat.next()? {
+            tokens.push((stat.read::<i64>(0)?, stat.read::<String>(1)?));
+        }
+        conn.execute(format!(
+            "ALTER TABLE {} RENAME COLUMN token TO hash;",
+            SESSIONS_TABLE_NAME
+        ))?;
+        for (session, token) in tokens {
+            let mut stat = conn.prepare(format!(
+                "UPDATE {} SET hash = ? WHERE rowid = ?;",
+                SESSIONS_TABLE_NAME
+            ))?;
+            stat.bind(1, hash_token(&token).as_str())?;
+            stat.bind(2, session)?;
+            stat.next()?;
+        }
+        Ok(())
     }
 
-    match tokens[0].parse::<u128>() {
-        Ok(expiry) => {
-            let epochs = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_millis();
-            if epochs < expiry {
-                Ok("OK".to_string())
-            } else {
-                Err(AuthError {
-                    msg: format!("expired auth cookie: '{}'", cookie),
-                })
-            }
+    /// Give users from before user ids existed an id of their own.
+    fn assign_user_ids(conn: &Connection) -> Result<(), sqlite::Error> {
+        let mut has_ids = false;
+        let mut stat = conn.prepare(format!("PRAGMA table_info({});", USERS_TABLE_NAME))?;
+        while let State::Row = stat.next()? {
+            has_ids |= stat.read::<String>(1)? == USER_ID_COL_NAME;
+        }
+        if !has_ids {
+            conn.execute(format!(
+                "ALTER TABLE {} ADD COLUMN {} TEXT;",
+                USERS_TABLE_NAME, USER_ID_COL_NAME
+            ))?;
         }
-        Err(_) => Err(AuthError {
-            msg: format!("invalid auth cookie: '{}'", cookie),
-        }),
+        conn.execute(format!(
+            "CREATE UNIQUE INDEX IF NOT EXISTS idx_user_id ON {} ({});",
+            USERS_TABLE_NAME, USER_ID_COL_NAME
+        ))?;
+
+        let mut usernames = Vec::new();
+        let mut stat = conn.prepare(format!(
+            "SELECT {} FROM {} WHERE {} IS NULL;",
+            USERS_COL_NAME, USERS_TABLE_NAME, USER_ID_COL_NAME
+        ))?;
+        while let State::Row = stat.next()? {
+            usernames.push(stat.read::<String>(0)?);
+        }
+        for username in usernames {
+            let mut stat = conn.prepare(format!(
+                "UPDATE {} SET {} = ? WHERE {} = ?;",
+                USERS_TABLE_NAME, USER_ID_COL_NAME, USERS_COL_NAME
+            ))?;
+            stat.bind(1, random_hex(USER_ID_BYTES).as_str())?;
+            stat.bind(2, username.as_str())?;
+            stat.next()?;
+        }
+        Ok(())
     }
-}
 
-impl Auth for SqliteAuth {
-    /// Insert a new user into the users database.
-    fn add_user(&mut self, login: &LoginInfo) -> Result<bool, AuthError> {
+    /// Revoke all of the user's API tokens.
+    fn delete_tokens(&self, username: &str) -> Result<(), AuthError> {
         let query = format!(
-            "INSERT INTO {} ({}, {}) VALUES(?, ?);",
-            USERS_TABLE_NAME, USERS_COL_NAME, SECRET_COL_NAME
+            "DELETE FROM {} WHERE {} = ?;",
+            TOKENS_TABLE_NAME, USERS_COL_NAME
         );
-        let mut stat = self.conn.prepare(query).unwrap();
-        stat.bind(1, login.username).unwrap();
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, username).map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        Ok(())
+    }
 
-        let hashed = hash(login.password, DEFAULT_COST).unwrap();
-        stat.bind(2, hashed.as_str()).unwrap();
-        match stat.next() {
-            Ok(_) => Ok(true),
-            Err(e) => Err(AuthError {
-                msg: format!("cannot add user: {}", unwrap_msg!(e)),
-            }),
+    /// Give users from before roles existed the user role, enabled.
+    fn add_roles(conn: &Connection) -> Result<(), sqlite::Error> {
+        let mut columns = Vec::new();
+        let mut stat = conn.prepare(format!("PRAGMA table_info({});", USERS_TABLE_NAME))?;
+        while let State::Row = stat.next()? {
+            columns.push(stat.read::<String>(1)?);
+        }
+        if !columns.iter().any(|c| c == ROLE_COL_NAME) {
+            conn.execute(format!(
+                "ALTER TABLE {} ADD COLUMN {} TEXT NOT NULL DEFAULT 'user';",
+                USERS_TABLE_NAME, ROLE_COL_NAME
+            ))?;
         }
+        if !columns.iter().any(|c| c == DISABLED_COL_NAME) {
+            conn.execute(format!(
+                "ALTER TABLE {} ADD COLUMN {} INTEGER NOT NULL DEFAULT 0;",
+                USERS_TABLE_NAME, DISABLED_COL_NAME
+            ))?;
+        }
+        Ok(())
     }
 
-    /// Check that the plaintext/extracted authorization cookie is valid and
-    /// has not yet expired.
-    /// The format of the token is the epoch milliseconds followed by the (unchecked)
-    /// username.
-    fn auth_cookie(&self, cookie: &str) -> Result<String, AuthError> {
-        auth_cookie(cookie)
+    pub fn with_timeouts(mut self, timeouts: SessionTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
     }
 
-    /// Query the database for username and password match, returning a
-    /// plaintext authorization cookie to be privately recorded.
-    /// TODO: edit configuration to return non 404 error response.
-    fn auth_user(&self, login: &LoginInfo) -> Result<String, AuthError> {
+    /// Read the time in milliseconds from the clock for TOTP codes, login
+    /// challenges and failed login lockouts, e.g. to fix the time in tests.
+    pub fn with_clock(mut self, clock: fn() -> i64) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Look up the user's TOTP secret and whether it was confirmed.
+    fn get_totp(&self, username: &str) -> Result<Option<(Vec<u8>, bool, i64)>, AuthError> {
         let query = format!(
-            "SELECT {} FROM {} WHERE {} = ?",
-            SECRET_COL_NAME, USERS_TABLE_NAME, USERS_COL_NAME
+            "SELECT secret, enabled, lastStep FROM {} WHERE {} = ?;",
+            TOTP_TABLE_NAME, USERS_COL_NAME
         );
-        let mut stat = self.conn.prepare(query).unwrap();
-        stat.bind(1, login.username).unwrap();
-        match stat.next() {
-            Ok(State::Row) => {
-                let stored_secret = stat.read::<String>(0).unwrap();
-
-                log::debug!(
-                    "checking {} {} {}",
-                    login.username,
-                    login.password,
-                    stored_secret
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, username).map_err(storage_error)?;
+        if let State::Done = stat.next().map_err(storage_error)? {
+            return Ok(None);
+        }
+        let secret = totp::decode_secret(&stat.read::<String>(0).map_err(storage_error)?)
+            .ok_or_else(|| AuthError::new(AuthErrorKind::Storage, "corrupt TOTP secret"))?;
+        Ok(Some((
+            secret,
+            stat.read::<i64>(1).map_err(storage_error)? != 0,
+            stat.read::<i64>(2).map_err(storage_error)?,
+        )))
+    }
+
+    /// Accept a TOTP code from a step after the last accepted one.
+    fn use_totp_code(&self, username: &str, secret: &[u8], last_step: i64, code: &str) -> bool {
+        match totp::verify(secret, code, (self.clock)()) {
+            Some(step) if step > last_step => {
+                let query = format!(
+                    "UPDATE {} SET lastStep = ? WHERE {} = ?;",
+                    TOTP_TABLE_NAME, USERS_COL_NAME
                 );
-                match verify(login.password, &stored_secret) {
-                    Ok(verified) => {
-                        if verified {
-                            Ok(get_auth_str(login))
-                        } else {
-                            log::error!("invalid user/pass {} {}", login.username, login.password);
-                            Err(AuthError {
-                                msg: "invalid password".to_string(),
-                            })
-                        }
-                    }
-                    Err(e) => {
-                        log::error!("bcrypt error: {}", e);
-                        Err(AuthError { msg: e.to_string() })
-                    }
-                }
+                self.conn
+                    .prepare(query)
+                    .and_then(|mut stat| {
+                        stat.bind(1, step)?;
+                        stat.bind(2, username)?;
+                        stat.next()
+                    })
+                    .is_ok()
             }
-            Ok(State::Done) => Err(AuthError {
-                msg: format!("no user: {}", login.username),
-            }),
-            Err(e) => Err(AuthError {
-                msg: format!(
-                    "failed to lookup user {}: {}",
-                    login.username,
-                    unwrap_msg!(e)
-                ),
-            }),
+            _ => false,
+        }
+    }
+
+    /// Delete the matching recovery code, if the user has one.
+    fn use_recovery_code(&self, username: &str, code: &str) -> Result<bool, AuthError> {
+        let query = format!(
+            "DELETE FROM {} WHERE {} = ? AND hash = ?;",
+            RECOVERY_TABLE_NAME, USERS_COL_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, username).map_err(storage_error)?;
+        stat.bind(2, hash_token(&code.trim().to_lowercase()).as_str())
+            .map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        let mut stat = self
+            .conn
+            .prepare("SELECT changes();")
+            .map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        Ok(stat.read::<i64>(0).map_err(storage_error)? > 0)
+    }
+
+    pub fn with_login_limits(mut self, limits: LoginLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Hash new passwords under the policy, re-hashing older hashes as their
+    /// users log in.
+    pub fn with_password_policy(mut self, passwords: PasswordPolicy) -> Self {
+        self.passwords = passwords;
+        self
+    }
+
+    /// The policy new password hashes are made under.
+    pub fn password_policy(&self) -> PasswordPolicy {
+        self.passwords
+    }
+
+    /// The user's password hash, or None for unknown users and those without
+    /// a password, e.g. users of another backend.
+    pub fn password_hash(&self, username: &str) -> Result<Option<String>, AuthError> {
+        let query = format!(
+            "SELECT {} FROM {} WHERE {} = ? AND {} IS NOT NULL",
+            SECRET_COL_NAME, USERS_TABLE_NAME, USERS_COL_NAME, SECRET_COL_NAME
+        );
+        let lookup_error = |e: sqlite::Error| {
+            AuthError::new(
+                AuthErrorKind::Storage,
+                &format!("failed to lookup user {}: {}", username, unwrap_msg!(e)),
+            )
+        };
+        let mut stat = self.conn.prepare(query).map_err(lookup_error)?;
+        stat.bind(1, username).map_err(lookup_error)?;
+        match stat.next().map_err(lookup_error)? {
+            State::Row => Ok(Some(stat.read::<String>(0).map_err(lookup_error)?)),
+            State::Done => Ok(None),
+        }
+    }
+
+    /// Store a hash from verify_password for an outdated one.
+    /// The login succeeded anyway, so failures are only logged, and the
+    /// next login tries again.
+    pub fn store_rehashed_password(&self, username: &str, hashed: &str) {
+        match self.store_password_hash(username, hashed) {
+            Ok(()) => log::info!("re-hashed password for {}", username),
+            Err(e) => log::warn!("cannot re-hash password for {}: {}", username, e.msg),
+        }
+    }
+
+    /// Replace the user's password hash, failing for unknown users.
+    pub fn store_password_hash(&self, username: &str, hashed: &str) -> Result<(), AuthError> {
+        let query = format!(
+            "UPDATE {} SET {} = ? WHERE {} = ?;",
+            USERS_TABLE_NAME, SECRET_COL_NAME, USERS_COL_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, hashed).map_err(storage_error)?;
+        stat.bind(2, username).map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+
+        let mut stat = self
+            .conn
+            .prepare("SELECT changes();")
+            .map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        match stat.read::<i64>(0).map_err(storage_error)? {
+            0 => Err(AuthError::new(
+                AuthErrorKind::NotFound,
+                &format!("no user {}", username),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count another failed login for the username or address, starting
+    /// over once the previous failure is older than the lockout.
+    fn record_failure(&self, kind: &str, key: &str) -> Result<(), AuthError> {
+        let now = (self.clock)();
+        let lockout = self.limits.lockout.as_millis() as i64;
+        let query = format!(
+            "SELECT failures, lastFailure FROM {} WHERE kind = ? AND key = ?;",
+            ATTEMPTS_TABLE_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, kind).map_err(storage_error)?;
+        stat.bind(2, key).map_err(storage_error)?;
+        let failures = match stat.next().map_err(storage_error)? {
+            State::Row if now - stat.read::<i64>(1).map_err(storage_error)? < lockout => {
+                stat.read::<i64>(0).map_err(storage_error)? + 1
+            }
+            _ => 1,
+        };
+
+        let delay = if failures >= self.limits.max_failures as i64 {
+            lockout
+        } else {
+            (self.limits.backoff.as_millis() as i64)
+                .saturating_mul(1 << (failures - 1).min(32))
+                .min(lockout)
+        };
+        if failures == self.limits.max_failures as i64 {
+            log::warn!(
+                "locking out logins for {} {} after {} failures",
+                kind,
+                key,
+                failures
+            );
+        }
+
+        let query = format!(
+            "INSERT OR REPLACE INTO {} (kind, key, failures, lastFailure, lockedUntil)
+                VALUES (?, ?, ?, ?, ?);",
+            ATTEMPTS_TABLE_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, kind).map_err(storage_error)?;
+        stat.bind(2, key).map_err(storage_error)?;
+        stat.bind(3, failures).map_err(storage_error)?;
+        stat.bind(4, now).map_err(storage_error)?;
+        stat.bind(5, now + delay).map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn idle_millis(&self) -> i64 {
+        self.timeouts.idle.as_millis() as i64
+    }
+
+    fn absolute_millis(&self) -> i64 {
+        self.timeouts.absolute.as_millis() as i64
+    }
+
+    /// Delete the user's sessions, or just the one with the handle, returning
+    /// how many were deleted.
+    fn delete_sessions(&self, username: &str, session: Option<i64>) -> Result<i64, AuthError> {
+        let query = match session {
+            Some(_) => format!(
+                "DELETE FROM {} WHERE {} = ? AND rowid = ?;",
+                SESSIONS_TABLE_NAME, USERS_COL_NAME
+            ),
+            None => format!(
+                "DELETE FROM {} WHERE {} = ?;",
+                SESSIONS_TABLE_NAME, USERS_COL_NAME
+            ),
+        };
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, username).map_err(storage_error)?;
+        if let Some(session) = session {
+            stat.bind(2, session).map_err(storage_error)?;
         }
+        stat.next().map_err(storage_error)?;
+        let mut stat = self
+            .conn
+            .prepare("SELECT changes();")
+            .map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        stat.read::<i64>(0).map_err(storage_error)
     }
 }
 
-/// Construct an authorization string valid for the default duration.
-fn get_auth_str(login: &LoginInfo) -> String {
-    let expiry = SystemTime::now()
-        .checked_add(SESSION_DURATION)
-        .unwrap()
+fn storage_error(e: sqlite::Error) -> AuthError {
+    AuthError::new(
+        AuthErrorKind::Storage,
+        &format!("users database failure: {}", unwrap_msg!(e)),
+    )
+}
+
+/// Read a users row selected as username, user id, role and disabled.
+fn read_user(stat: &sqlite::Statement) -> Result<UserInfo, AuthError> {
+    Ok(UserInfo {
+        username: stat.read::<String>(0).map_err(storage_error)?,
+        user_id: stat.read::<String>(1).map_err(storage_error)?,
+        role: Role::parse(&stat.read::<String>(2).map_err(storage_error)?).unwrap_or_default(),
+        disabled: stat.read::<i64>(3).map_err(storage_error)? != 0,
+    })
+}
+
+fn get_time() -> i64 {
+    SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
-        .as_millis();
-    format!("{} {}", expiry, login.username)
+        .as_millis() as i64
 }
 
-#[derive(Debug)]
-pub struct AuthKey(pub String);
+/// Hash an API or session token for storage.
+/// Tokens are long and random, so a fast unsalted hash suffices to keep
+/// them out of the database in plaintext.
+fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
 
-#[rocket::async_trait]
-impl<'r> FromRequest<'r> for AuthKey {
-    type Error = AuthError;
+/// The same failure for unknown users as for wrong passwords, so clients
+/// can't tell which usernames exist.
+pub(crate) fn bad_credentials() -> AuthError {
+    AuthError::new(AuthErrorKind::Unauthorized, "invalid username or password")
+}
 
-    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
-        match request.cookies().get_private(AUTH_COOKIE) {
-            Some(cookie) => {
-                log::debug!("cookie {}", cookie.value());
-                let tokens: Vec<&str> = cookie.value().split(' ').collect();
-                let username = tokens[1];
-                let expiry_str = tokens[0];
-                match expiry_str.parse::<u128>() {
-                    Ok(expiry) => {
-                        let epochs = SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap()
-                            .as_millis();
-                        if epochs < expiry {
-                            Outcome::Success(AuthKey(username.to_string()))
-                        } else {
-                            Outcome::Failure((
-                                Status::NotExtended,
-                                AuthError {
-                                    msg: "Expired".to_string(),
-                                },
-                            ))
-                        }
-                    }
-                    Err(_) => Outcome::Failure((
-                        Status::BadRequest,
-                        AuthError {
-                            msg: "InvalidAuth".to_string(),
-                        },
-                    )),
-                }
-            }
-            None => Outcome::Failure((
-                Status::Unauthorized,
-                AuthError {
-                    msg: "Unauthorized".to_string(),
-                },
+pub(crate) fn random_hex(num_bytes: usize) -> String {
+    let mut bytes = vec![0u8; num_bytes];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Generate an unguessable session token.
+fn new_token() -> String {
+    random_hex(SESSION_BYTES)
+}
+
+impl Auth for SqliteAuth {
+    /// Insert a new user into the users database.
+    fn add_user(&mut self, login: &LoginInfo) -> Result<bool, AuthError> {
+        self.add_user_with_role(login, Role::User)
+    }
+
+    fn add_user_with_role(&mut self, login: &LoginInfo, role: Role) -> Result<bool, AuthError> {
+        let query = format!(
+            "INSERT INTO {} ({}, {}, {}, {}) VALUES(?, ?, ?, ?);",
+            USERS_TABLE_NAME, USERS_COL_NAME, SECRET_COL_NAME, USER_ID_COL_NAME, ROLE_COL_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, login.username).map_err(storage_error)?;
+
+        let hashed = self.passwords.hash(login.password)?;
+        stat.bind(2, hashed.as_str()).map_err(storage_error)?;
+        stat.bind(3, random_hex(USER_ID_BYTES).as_str())
+            .map_err(storage_error)?;
+        stat.bind(4, role.as_str()).map_err(storage_error)?;
+        match stat.next() {
+            Ok(_) => Ok(true),
+            Err(e) if e.code == Some(SQLITE_CONSTRAINT) => Err(AuthError::new(
+                AuthErrorKind::Conflict,
+                &format!("user {} already exists", login.username),
+            )),
+            Err(e) => Err(AuthError::new(
+                AuthErrorKind::Storage,
+                &format!("cannot add user: {}", unwrap_msg!(e)),
             )),
         }
     }
-}
 
-fn get_auth() -> SqliteAuth {
-    SqliteAuth::new(USERS_DB_NAME).unwrap()
-}
+    fn ensure_user(&mut self, username: &str) -> Result<(), AuthError> {
+        let query = format!(
+            "INSERT OR IGNORE INTO {} ({}, {}) VALUES (?, ?);",
+            USERS_TABLE_NAME, USERS_COL_NAME, USER_ID_COL_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, username).map_err(storage_error)?;
+        stat.bind(2, random_hex(USER_ID_BYTES).as_str())
+            .map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn get_user_id(&self, username: &str) -> Result<String, AuthError> {
+        let query = format!(
+            "SELECT {} FROM {} WHERE {} = ?;",
+            USER_ID_COL_NAME, USERS_TABLE_NAME, USERS_COL_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, username).map_err(storage_error)?;
+        match stat.next().map_err(storage_error)? {
+            State::Row => stat.read::<String>(0).map_err(storage_error),
+            State::Done => Err(AuthError::new(
+                AuthErrorKind::NotFound,
+                &format!("no user {}", username),
+            )),
+        }
+    }
 
-#[post("/users/login", format = "application/json", data = "<login_info>")]
-pub fn login(login_info: Json<LoginInfo>, cookies: &CookieJar<'_>) -> Option<String> {
-    match get_auth().auth_user(&login_info) {
-        Ok(token) => {
-            let cookie = Cookie::build(AUTH_COOKIE, token).finish();
-            cookies.add_private(cookie);
-            Some(format!("hello {}", login_info.username))
+    fn delete_user(&mut self, username: &str) -> Result<(), AuthError> {
+        for table in &[
+            USERS_TABLE_NAME,
+            SESSIONS_TABLE_NAME,
+            TOKENS_TABLE_NAME,
+            TOTP_TABLE_NAME,
+            RECOVERY_TABLE_NAME,
+            CHALLENGES_TABLE_NAME,
+            DELETIONS_TABLE_NAME,
+            OIDC_LINKS_TABLE_NAME,
+        ] {
+            let query = format!("DELETE FROM {} WHERE {} = ?;", table, USERS_COL_NAME);
+            let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+            stat.bind(1, username).map_err(storage_error)?;
+            stat.next().map_err(storage_error)?;
         }
-        Err(_) => {
-            None // XXX TODO redirect
+        Ok(())
+    }
+
+    fn get_user(&self, username: &str) -> Result<UserInfo, AuthError> {
+        let query = format!(
+            "SELECT {}, {}, {}, {} FROM {} WHERE {} = ?;",
+            USERS_COL_NAME,
+            USER_ID_COL_NAME,
+            ROLE_COL_NAME,
+            DISABLED_COL_NAME,
+            USERS_TABLE_NAME,
+            USERS_COL_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, username).map_err(storage_error)?;
+        match stat.next().map_err(storage_error)? {
+            State::Row => read_user(&stat),
+            State::Done => Err(AuthError::new(
+                AuthErrorKind::NotFound,
+                &format!("no user {}", username),
+            )),
         }
     }
-}
 
-#[get("/users/logout")]
-pub fn logout(cookies: &CookieJar<'_>) -> Option<String> {
-    cookies.remove_private(Cookie::named(AUTH_COOKIE));
-    Some("OK".to_string())
+    fn get_users(&self, after: &str, dest: &mut Vec<UserInfo>) -> Result<usize, AuthError> {
+        let query = format!(
+            "SELECT {}, {}, {}, {} FROM {} WHERE {} > ? ORDER BY {} LIMIT ?;",
+            USERS_COL_NAME,
+            USER_ID_COL_NAME,
+            ROLE_COL_NAME,
+            DISABLED_COL_NAME,
+            USERS_TABLE_NAME,
+            USERS_COL_NAME,
+            USERS_COL_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, after).map_err(storage_error)?;
+        stat.bind(2, dest.len() as i64).map_err(storage_error)?;
+        let mut count = 0;
+        while let State::Row = stat.next().map_err(storage_error)? {
+            dest[count] = read_user(&stat)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn set_role(&mut self, username: &str, role: Role) -> Result<(), AuthError> {
+        self.get_user(username)?;
+        let query = format!(
+            "UPDATE {} SET {} = ? WHERE {} = ?;",
+            USERS_TABLE_NAME, ROLE_COL_NAME, USERS_COL_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, role.as_str()).map_err(storage_error)?;
+        stat.bind(2, username).map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn set_disabled(&mut self, username: &str, disabled: bool) -> Result<(), AuthError> {
+        self.get_user(username)?;
+        let query = format!(
+            "UPDATE {} SET {} = ? WHERE {} = ?;",
+            USERS_TABLE_NAME, DISABLED_COL_NAME, USERS_COL_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, disabled as i64).map_err(storage_error)?;
+        stat.bind(2, username).map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        drop(stat);
+        if disabled {
+            self.revoke_sessions(username)?;
+            self.delete_tokens(username)?;
+        }
+        Ok(())
+    }
+
+    fn schedule_deletion(&mut self, username: &str, purge_after: i64) -> Result<(), AuthError> {
+        self.get_user(username)?;
+        let query = format!(
+            "INSERT OR REPLACE INTO {} ({}, purgeAfter) VALUES (?, ?);",
+            DELETIONS_TABLE_NAME, USERS_COL_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, username).map_err(storage_error)?;
+        stat.bind(2, purge_after).map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        drop(stat);
+        self.revoke_sessions(username)?;
+        self.delete_tokens(username)
+    }
+
+    fn cancel_deletion(&mut self, username: &str) -> Result<(), AuthError> {
+        let query = format!(
+            "DELETE FROM {} WHERE {} = ?;",
+            DELETIONS_TABLE_NAME, USERS_COL_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, username).map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        let mut stat = self
+            .conn
+            .prepare("SELECT changes();")
+            .map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        if stat.read::<i64>(0).map_err(storage_error)? == 0 {
+            return Err(AuthError::new(
+                AuthErrorKind::NotFound,
+                &format!("no deletion scheduled for {}", username),
+            ));
+        }
+        Ok(())
+    }
+
+    fn get_due_deletions(&self, now: i64, dest: &mut Vec<String>) -> Result<usize, AuthError> {
+        let query = format!(
+            "SELECT {} FROM {} WHERE purgeAfter <= ? ORDER BY purgeAfter LIMIT ?;",
+            USERS_COL_NAME, DELETIONS_TABLE_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, now).map_err(storage_error)?;
+        stat.bind(2, dest.len() as i64).map_err(storage_error)?;
+        let mut count = 0;
+        while let State::Row = stat.next().map_err(storage_error)? {
+            dest[count] = stat.read::<String>(0).map_err(storage_error)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn redeem_invite(&mut self, code: &str, username: &str) -> Result<(), AuthError> {
+        let query = format!(
+            "INSERT INTO {} (code, {}, redeemed) VALUES (?, ?, ?);",
+            INVITES_TABLE_NAME, USERS_COL_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, code).map_err(storage_error)?;
+        stat.bind(2, username).map_err(storage_error)?;
+        stat.bind(3, get_time()).map_err(storage_error)?;
+        match stat.next() {
+            Ok(_) => Ok(()),
+            Err(e) if e.code == Some(SQLITE_CONSTRAINT) => Err(AuthError::new(
+                AuthErrorKind::Forbidden,
+                "invite code already used",
+            )),
+            Err(e) => Err(storage_error(e)),
+        }
+    }
+
+    /// Look up the session for the token, dropping it once expired, and
+    /// record that it was seen.
+    /// Sessions past half of their idle timeout are flagged for renewal,
+    /// unless they are already at their absolute expiry.
+    fn auth_cookie(&self, cookie: &str) -> Result<SessionKey, AuthError> {
+        let query = format!(
+            "SELECT rowid, {}, created, expires FROM {} WHERE hash = ?;",
+            USERS_COL_NAME, SESSIONS_TABLE_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, hash_token(cookie).as_str())
+            .map_err(storage_error)?;
+        if let State::Done = stat.next().map_err(storage_error)? {
+            return Err(AuthError::new(
+                AuthErrorKind::Unauthorized,
+                "unknown session",
+            ));
+        }
+        let session = stat.read::<i64>(0).map_err(storage_error)?;
+        let username = stat.read::<String>(1).map_err(storage_error)?;
+        let created = stat.read::<i64>(2).map_err(storage_error)?;
+        let expires = stat.read::<i64>(3).map_err(storage_error)?;
+
+        let now = get_time();
+        let deadline = created + self.absolute_millis();
+        if expires <= now || deadline <= now {
+            self.delete_sessions(&username, Some(session))?;
+            return Err(AuthError::new(
+                AuthErrorKind::SessionExpired,
+                "session expired, please log in again",
+            ));
+        }
+        let query = format!(
+            "UPDATE {} SET lastSeen = ? WHERE rowid = ?;",
+            SESSIONS_TABLE_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, now).map_err(storage_error)?;
+        stat.bind(2, session).map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        Ok(SessionKey {
+            session,
+            username,
+            expires,
+            renew: expires - now < self.idle_millis() / 2 && expires < deadline,
+        })
+    }
+
+    /// Query the database for username and password match.
+    fn auth_user(&self, login: &LoginInfo) -> Result<String, AuthError> {
+        let stored = self.password_hash(login.username)?;
+        if let Some(hashed) = verify_password(&self.passwords, login, stored.as_deref())? {
+            self.store_rehashed_password(login.username, &hashed);
+        }
+        Ok(login.username.to_string())
+    }
+
+    fn check_login(&self, username: &str, ip: &str) -> Result<(), AuthError> {
+        let query = format!(
+            "SELECT MAX(lockedUntil) FROM {}
+                WHERE (kind = 'username' AND key = ?) OR (kind = 'ip' AND key = ?);",
+            ATTEMPTS_TABLE_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, username).map_err(storage_error)?;
+        stat.bind(2, ip).map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        let locked_until = stat
+            .read::<Option<i64>>(0)
+            .map_err(storage_error)?
+            .unwrap_or(0);
+        let wait = locked_until - (self.clock)();
+        if wait > 0 {
+            return Err(AuthError::new(
+                AuthErrorKind::TooManyAttempts,
+                &format!(
+                    "too many failed logins, retry in {} seconds",
+                    (wait + 999) / 1000
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn record_login(&mut self, username: &str, ip: &str, success: bool) -> Result<(), AuthError> {
+        if success {
+            let query = format!(
+                "DELETE FROM {} WHERE kind = 'username' AND key = ?;",
+                ATTEMPTS_TABLE_NAME
+            );
+            let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+            stat.bind(1, username).map_err(storage_error)?;
+            stat.next().map_err(storage_error)?;
+            return Ok(());
+        }
+        self.record_failure("username", username)?;
+        self.record_failure("ip", ip)
+    }
+
+    fn get_login_failures(&self, dest: &mut Vec<LoginFailures>) -> Result<usize, AuthError> {
+        let query = format!(
+            "SELECT kind, key, failures, lastFailure, lockedUntil FROM {}
+                WHERE lastFailure > ? ORDER BY lastFailure DESC LIMIT ?;",
+            ATTEMPTS_TABLE_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, (self.clock)() - self.limits.lockout.as_millis() as i64)
+            .map_err(storage_error)?;
+        stat.bind(2, dest.len() as i64).map_err(storage_error)?;
+        let mut count = 0;
+        while let State::Row = stat.next().map_err(storage_error)? {
+            dest[count] = LoginFailures {
+                kind: stat.read::<String>(0).map_err(storage_error)?,
+                key: stat.read::<String>(1).map_err(storage_error)?,
+                failures: stat.read::<i64>(2).map_err(storage_error)?,
+                last_failure: stat.read::<i64>(3).map_err(storage_error)?,
+                locked_until: stat.read::<i64>(4).map_err(storage_error)?,
+            };
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn enroll_totp(&mut self, username: &str, issuer: &str) -> Result<TotpEnrollment, AuthError> {
+        if let Some((_, true, _)) = self.get_totp(username)? {
+            return Err(AuthError::new(
+                AuthErrorKind::Conflict,
+                "TOTP is already enabled",
+            ));
+        }
+        let secret = totp::new_secret();
+        let query = format!(
+            "INSERT OR REPLACE INTO {} ({}, secret, enabled, lastStep) VALUES (?, ?, 0, 0);",
+            TOTP_TABLE_NAME, USERS_COL_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, username).map_err(storage_error)?;
+        stat.bind(2, totp::encode_secret(&secret).as_str())
+            .map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        Ok(TotpEnrollment {
+            secret: Secret(totp::encode_secret(&secret)),
+            uri: Secret(totp::provisioning_uri(issuer, username, &secret)),
+        })
+    }
+
+    fn confirm_totp(&mut self, username: &str, code: &str) -> Result<Vec<String>, AuthError> {
+        let (secret, last_step) = match self.get_totp(username)? {
+            Some((secret, false, last_step)) => (secret, last_step),
+            Some((_, true, _)) => {
+                return Err(AuthError::new(
+                    AuthErrorKind::Conflict,
+                    "TOTP is already enabled",
+                ))
+            }
+            None => {
+                return Err(AuthError::new(
+                    AuthErrorKind::NotFound,
+                    "no TOTP enrollment to confirm",
+                ))
+            }
+        };
+        if !self.use_totp_code(username, &secret, last_step, code) {
+            return Err(AuthError::new(
+                AuthErrorKind::Unauthorized,
+                "invalid TOTP code",
+            ));
+        }
+
+        let query = format!(
+            "UPDATE {} SET enabled = 1 WHERE {} = ?;",
+            TOTP_TABLE_NAME, USERS_COL_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, username).map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+
+        let query = format!(
+            "DELETE FROM {} WHERE {} = ?;",
+            RECOVERY_TABLE_NAME, USERS_COL_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, username).map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+
+        let codes: Vec<String> = (0..RECOVERY_CODES)
+            .map(|_| random_hex(RECOVERY_CODE_BYTES))
+            .collect();
+        let query = format!(
+            "INSERT INTO {} ({}, hash) VALUES (?, ?);",
+            RECOVERY_TABLE_NAME, USERS_COL_NAME
+        );
+        for code in codes.iter() {
+            let mut stat = self.conn.prepare(&query).map_err(storage_error)?;
+            stat.bind(1, username).map_err(storage_error)?;
+            stat.bind(2, hash_token(code).as_str())
+                .map_err(storage_error)?;
+            stat.next().map_err(storage_error)?;
+        }
+        Ok(codes)
+    }
+
+    fn has_totp(&self, username: &str) -> Result<bool, AuthError> {
+        Ok(matches!(self.get_totp(username)?, Some((_, true, _))))
+    }
+
+    fn verify_totp(&mut self, username: &str, code: &str) -> Result<(), AuthError> {
+        let (secret, last_step) = match self.get_totp(username)? {
+            Some((secret, true, last_step)) => (secret, last_step),
+            _ => {
+                return Err(AuthError::new(
+                    AuthErrorKind::NotFound,
+                    "TOTP is not enabled",
+                ))
+            }
+        };
+        if self.use_totp_code(username, &secret, last_step, code)
+            || self.use_recovery_code(username, code)?
+        {
+            Ok(())
+        } else {
+            Err(AuthError::new(
+                AuthErrorKind::Unauthorized,
+                "invalid TOTP or recovery code",
+            ))
+        }
+    }
+
+    fn disable_totp(&mut self, username: &str) -> Result<(), AuthError> {
+        for table in &[TOTP_TABLE_NAME, RECOVERY_TABLE_NAME] {
+            let query = format!("DELETE FROM {} WHERE {} = ?;", table, USERS_COL_NAME);
+            let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+            stat.bind(1, username).map_err(storage_error)?;
+            stat.next().map_err(storage_error)?;
+        }
+        Ok(())
+    }
+
+    fn create_login_challenge(&mut self, username: &str) -> Result<(String, i64), AuthError> {
+        let now = (self.clock)();
+        let query = format!("DELETE FROM {} WHERE expires <= ?;", CHALLENGES_TABLE_NAME);
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, now).map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+
+        let challenge = new_token();
+        let expires = now + CHALLENGE_MILLIS;
+        let query = format!(
+            "INSERT INTO {} (hash, {}, expires) VALUES (?, ?, ?);",
+            CHALLENGES_TABLE_NAME, USERS_COL_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, hash_token(&challenge).as_str())
+            .map_err(storage_error)?;
+        stat.bind(2, username).map_err(storage_error)?;
+        stat.bind(3, expires).map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        Ok((challenge, expires))
+    }
+
+    fn take_login_challenge(&mut self, challenge: &str) -> Result<String, AuthError> {
+        let hashed = hash_token(challenge);
+        let query = format!(
+            "SELECT {}, expires FROM {} WHERE hash = ?;",
+            USERS_COL_NAME, CHALLENGES_TABLE_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, hashed.as_str()).map_err(storage_error)?;
+        let found = match stat.next().map_err(storage_error)? {
+            State::Row => Some((
+                stat.read::<String>(0).map_err(storage_error)?,
+                stat.read::<i64>(1).map_err(storage_error)?,
+            )),
+            State::Done => None,
+        };
+
+        let query = format!("DELETE FROM {} WHERE hash = ?;", CHALLENGES_TABLE_NAME);
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, hashed.as_str()).map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        match found {
+            Some((username, expires)) if expires > (self.clock)() => Ok(username),
+            Some(_) => Err(AuthError::new(
+                AuthErrorKind::SessionExpired,
+                "login challenge expired, log in again",
+            )),
+            None => Err(AuthError::new(
+                AuthErrorKind::Unauthorized,
+                "unknown login challenge",
+            )),
+        }
+    }
+
+    fn create_oidc_login(
+        &mut self,
+        state: &str,
+        verifier: &str,
+        nonce: &str,
+    ) -> Result<(), AuthError> {
+        let now = (self.clock)();
+        let query = format!("DELETE FROM {} WHERE expires <= ?;", OIDC_LOGINS_TABLE_NAME);
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, now).map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+
+        let query = format!(
+            "INSERT INTO {} (hash, verifier, nonce, expires) VALUES (?, ?, ?, ?);",
+            OIDC_LOGINS_TABLE_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, hash_token(state).as_str())
+            .map_err(storage_error)?;
+        stat.bind(2, verifier).map_err(storage_error)?;
+        stat.bind(3, nonce).map_err(storage_error)?;
+        stat.bind(4, now + OIDC_LOGIN_MILLIS)
+            .map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn take_oidc_login(&mut self, state: &str) -> Result<(String, String), AuthError> {
+        let hashed = hash_token(state);
+        let query = format!(
+            "SELECT verifier, nonce, expires FROM {} WHERE hash = ?;",
+            OIDC_LOGINS_TABLE_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, hashed.as_str()).map_err(storage_error)?;
+        let found = match stat.next().map_err(storage_error)? {
+            State::Row => Some((
+                stat.read::<String>(0).map_err(storage_error)?,
+                stat.read::<String>(1).map_err(storage_error)?,
+                stat.read::<i64>(2).map_err(storage_error)?,
+            )),
+            State::Done => None,
+        };
+
+        let query = format!("DELETE FROM {} WHERE hash = ?;", OIDC_LOGINS_TABLE_NAME);
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, hashed.as_str()).map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        match found {
+            Some((verifier, nonce, expires)) if expires > (self.clock)() => Ok((verifier, nonce)),
+            Some(_) => Err(AuthError::new(
+                AuthErrorKind::SessionExpired,
+                "identity provider login expired, log in again",
+            )),
+            None => Err(AuthError::new(
+                AuthErrorKind::Unauthorized,
+                "unknown identity provider login",
+            )),
+        }
+    }
+
+    fn get_oidc_user(&self, issuer: &str, subject: &str) -> Result<Option<String>, AuthError> {
+        let query = format!(
+            "SELECT {} FROM {} WHERE issuer = ? AND subject = ?;",
+            USERS_COL_NAME, OIDC_LINKS_TABLE_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, issuer).map_err(storage_error)?;
+        stat.bind(2, subject).map_err(storage_error)?;
+        match stat.next().map_err(storage_error)? {
+            State::Row => Ok(Some(stat.read::<String>(0).map_err(storage_error)?)),
+            State::Done => Ok(None),
+        }
+    }
+
+    fn link_oidc_user(
+        &mut self,
+        issuer: &str,
+        subject: &str,
+        username: &str,
+    ) -> Result<(), AuthError> {
+        let query = format!(
+            "INSERT INTO {} (issuer, subject, {}) VALUES (?, ?, ?);",
+            OIDC_LINKS_TABLE_NAME, USERS_COL_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, issuer).map_err(storage_error)?;
+        stat.bind(2, subject).map_err(storage_error)?;
+        stat.bind(3, username).map_err(storage_error)?;
+        match stat.next() {
+            Ok(_) => Ok(()),
+            Err(e) if e.code == Some(SQLITE_CONSTRAINT) => Err(AuthError::new(
+                AuthErrorKind::Conflict,
+                &format!("{} at {} is already linked", subject, issuer),
+            )),
+            Err(e) => Err(storage_error(e)),
+        }
+    }
+
+    /// Record a new session, clearing out the user's expired sessions.
+    fn create_session(&mut self, username: &str, client: &ClientInfo) -> Result<String, AuthError> {
+        let now = get_time();
+        let query = format!(
+            "DELETE FROM {} WHERE {} = ? AND expires <= ?;",
+            SESSIONS_TABLE_NAME, USERS_COL_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, username).map_err(storage_error)?;
+        stat.bind(2, now).map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+
+        let token = new_token();
+        let query = format!(
+            "INSERT INTO {} (hash, {}, created, lastSeen, expires, userAgent, ip)
+                VALUES (?, ?, ?, ?, ?, ?, ?);",
+            SESSIONS_TABLE_NAME, USERS_COL_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, hash_token(&token).as_str())
+            .map_err(storage_error)?;
+        stat.bind(2, username).map_err(storage_error)?;
+        stat.bind(3, now).map_err(storage_error)?;
+        stat.bind(4, now).map_err(storage_error)?;
+        stat.bind(5, now + self.idle_millis().min(self.absolute_millis()))
+            .map_err(storage_error)?;
+        stat.bind(6, client.user_agent.as_str())
+            .map_err(storage_error)?;
+        stat.bind(7, client.ip.as_str()).map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        Ok(token)
+    }
+
+    /// Look up the user's live sessions, most recently seen first.
+    fn get_sessions(&self, username: &str, dest: &mut Vec<Session>) -> Result<usize, AuthError> {
+        let query = format!(
+            "SELECT rowid, created, lastSeen, expires, userAgent, ip FROM {}
+                WHERE {} = ? AND expires > ? ORDER BY lastSeen DESC LIMIT ?;",
+            SESSIONS_TABLE_NAME, USERS_COL_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, username).map_err(storage_error)?;
+        stat.bind(2, get_time()).map_err(storage_error)?;
+        stat.bind(3, dest.len() as i64).map_err(storage_error)?;
+        let mut count = 0;
+        while let State::Row = stat.next().map_err(storage_error)? {
+            dest[count] = Session {
+                id: stat.read::<i64>(0).map_err(storage_error)?,
+                created: stat.read::<i64>(1).map_err(storage_error)?,
+                last_seen: stat.read::<i64>(2).map_err(storage_error)?,
+                expires: stat.read::<i64>(3).map_err(storage_error)?,
+                user_agent: stat.read::<String>(4).map_err(storage_error)?,
+                ip: stat.read::<String>(5).map_err(storage_error)?,
+                current: false,
+            };
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn refresh_session(&mut self, cookie: &str) -> Result<SessionKey, AuthError> {
+        let mut key = self.auth_cookie(cookie)?;
+        let query = format!(
+            "UPDATE {} SET expires = MIN(? + ?, created + ?) WHERE rowid = ?;",
+            SESSIONS_TABLE_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, get_time()).map_err(storage_error)?;
+        stat.bind(2, self.idle_millis()).map_err(storage_error)?;
+        stat.bind(3, self.absolute_millis())
+            .map_err(storage_error)?;
+        stat.bind(4, key.session).map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+
+        let query = format!(
+            "SELECT expires FROM {} WHERE rowid = ?;",
+            SESSIONS_TABLE_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, key.session).map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        key.expires = stat.read::<i64>(0).map_err(storage_error)?;
+        key.renew = false;
+        Ok(key)
+    }
+
+    fn revoke_session(&mut self, username: &str, session: i64) -> Result<(), AuthError> {
+        match self.delete_sessions(username, Some(session))? {
+            0 => Err(AuthError::new(
+                AuthErrorKind::NotFound,
+                &format!("no session {}", session),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn revoke_sessions(&mut self, username: &str) -> Result<(), AuthError> {
+        self.delete_sessions(username, None)?;
+        Ok(())
+    }
+
+    fn set_password(&mut self, username: &str, password: &str) -> Result<(), AuthError> {
+        let hashed = self.passwords.hash(password)?;
+        self.store_password_hash(username, &hashed)
+    }
+
+    fn auth_token(&self, token: &str) -> Result<(String, TokenScope), AuthError> {
+        let hashed = hash_token(token);
+        let query = format!(
+            "SELECT {}, scope FROM {} WHERE hash = ?;",
+            USERS_COL_NAME, TOKENS_TABLE_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, hashed.as_str()).map_err(storage_error)?;
+        if let State::Done = stat.next().map_err(storage_error)? {
+            return Err(AuthError::new(AuthErrorKind::Unauthorized, "unknown token"));
+        }
+        let username = stat.read::<String>(0).map_err(storage_error)?;
+        let scope = TokenScope::parse(&stat.read::<String>(1).map_err(storage_error)?)
+            .unwrap_or(TokenScope::ReadOnly);
+
+        let query = format!(
+            "UPDATE {} SET lastUsed = ? WHERE hash = ?;",
+            TOKENS_TABLE_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, get_time()).map_err(storage_error)?;
+        stat.bind(2, hashed.as_str()).map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        Ok((username, scope))
+    }
+
+    fn create_token(
+        &mut self,
+        username: &str,
+        name: &str,
+        scope: TokenScope,
+    ) -> Result<(i64, String), AuthError> {
+        let token = new_token();
+        let query = format!(
+            "INSERT INTO {} (hash, {}, name, scope, created) VALUES (?, ?, ?, ?, ?);",
+            TOKENS_TABLE_NAME, USERS_COL_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, hash_token(&token).as_str())
+            .map_err(storage_error)?;
+        stat.bind(2, username).map_err(storage_error)?;
+        stat.bind(3, name).map_err(storage_error)?;
+        stat.bind(4, scope.as_str()).map_err(storage_error)?;
+        stat.bind(5, get_time()).map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+
+        let mut stat = self
+            .conn
+            .prepare("SELECT last_insert_rowid();")
+            .map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        Ok((stat.read::<i64>(0).map_err(storage_error)?, token))
+    }
+
+    /// Look up the user's tokens, oldest first.
+    fn get_tokens(&self, username: &str, dest: &mut Vec<ApiToken>) -> Result<usize, AuthError> {
+        let query = format!(
+            "SELECT rowid, name, scope, created, lastUsed FROM {}
+                WHERE {} = ? ORDER BY rowid LIMIT ?;",
+            TOKENS_TABLE_NAME, USERS_COL_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, username).map_err(storage_error)?;
+        stat.bind(2, dest.len() as i64).map_err(storage_error)?;
+        let mut count = 0;
+        while let State::Row = stat.next().map_err(storage_error)? {
+            dest[count] = ApiToken {
+                id: stat.read::<i64>(0).map_err(storage_error)?,
+                name: stat.read::<String>(1).map_err(storage_error)?,
+                scope: TokenScope::parse(&stat.read::<String>(2).map_err(storage_error)?)
+                    .unwrap_or(TokenScope::ReadOnly),
+                created: stat.read::<i64>(3).map_err(storage_error)?,
+                last_used: stat.read::<Option<i64>>(4).map_err(storage_error)?,
+            };
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn revoke_token(&mut self, username: &str, token: i64) -> Result<(), AuthError> {
+        let query = format!(
+            "DELETE FROM {} WHERE {} = ? AND rowid = ?;",
+            TOKENS_TABLE_NAME, USERS_COL_NAME
+        );
+        let mut stat = self.conn.prepare(query).map_err(storage_error)?;
+        stat.bind(1, username).map_err(storage_error)?;
+        stat.bind(2, token).map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        let mut stat = self
+            .conn
+            .prepare("SELECT changes();")
+            .map_err(storage_error)?;
+        stat.next().map_err(storage_error)?;
+        match stat.read::<i64>(0).map_err(storage_error)? {
+            0 => Err(AuthError::new(
+                AuthErrorKind::NotFound,
+                &format!("no token {}", token),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AuthKey(pub String);
+
+/// The live session behind a request, checked against the session store.
+/// The guard reissues the cookie, pushing back its expiry, once the session
+/// is flagged for renewal.
+#[derive(Clone, Debug, Serialize)]
+pub struct SessionKey {
+    pub session: i64,
+    pub username: String,
+    pub expires: i64,
+    #[serde(skip)]
+    pub renew: bool,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SessionKey {
+    type Error = AuthError;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let cookies = request.cookies();
+        let result = match (get_auth_state(request), cookies.get_private(AUTH_COOKIE)) {
+            (Err(e), _) => Err(e),
+            (Ok(auth_state), Some(cookie)) => {
+                let mut auth = auth_state.users();
+                auth.auth_cookie(cookie.value()).and_then(|key| {
+                    if key.renew {
+                        let key = auth.refresh_session(cookie.value())?;
+                        cookies.add_private(session_cookie(cookie.value(), key.expires));
+                        Ok(key)
+                    } else {
+                        Ok(key)
+                    }
+                })
+            }
+            (Ok(_), None) => Err(AuthError::new(AuthErrorKind::Unauthorized, "Unauthorized")),
+        };
+        match result {
+            Ok(key) => Outcome::Success(key),
+            Err(e) => {
+                log::debug!("rejecting session: {}", e.msg);
+                request.local_cache(|| Some(e.clone()));
+                Outcome::Failure((Status::Unauthorized, e))
+            }
+        }
+    }
+}
+
+fn get_auth_state<'r>(request: &'r Request<'_>) -> Result<&'r AuthState, AuthError> {
+    request
+        .rocket()
+        .state::<AuthState>()
+        .ok_or_else(|| AuthError::new(AuthErrorKind::Storage, "no auth backend configured"))
+}
+
+/// Authenticate the request by its bearer token, if any, or by a trusted
+/// proxy's say-so, or else by its session cookie, returning the username.
+/// Bearer tokens must have one of the allowed scopes.
+async fn authorize(
+    request: &Request<'_>,
+    allowed: &[TokenScope],
+) -> request::Outcome<String, AuthError> {
+    let auth_state = match get_auth_state(request) {
+        Ok(auth_state) => auth_state,
+        Err(e) => return Outcome::Failure((Status::InternalServerError, e)),
+    };
+    let bearer = request
+        .headers()
+        .get_one("Authorization")
+        .filter(|header| header.starts_with(BEARER_PREFIX))
+        .map(|header| header[BEARER_PREFIX.len()..].trim().to_string());
+    let token = match bearer {
+        Some(token) => token,
+        None => {
+            let remote_ip = request.remote().map(|addr| addr.ip());
+            if let Some(username) = auth_state.remote_user(remote_ip, request.headers()) {
+                return Outcome::Success(username);
+            }
+            let session = try_outcome!(request.guard::<SessionKey>().await);
+            return Outcome::Success(session.username);
+        }
+    };
+
+    let result = auth_state.users().auth_token(&token);
+    let (status, e) = match result {
+        Ok((username, scope)) if allowed.contains(&scope) => return Outcome::Success(username),
+        Ok((username, scope)) => (
+            Status::Forbidden,
+            AuthError::new(
+                AuthErrorKind::Forbidden,
+                &format!(
+                    "{} token for {} cannot be used here",
+                    scope.as_str(),
+                    username
+                ),
+            ),
+        ),
+        Err(e) => (Status::Unauthorized, e),
+    };
+    log::debug!("rejecting token: {}", e.msg);
+    request.local_cache(|| Some(e.clone()));
+    Outcome::Failure((status, e))
+}
+
+/// Grants full access to the user's boxes, by session or full-scope token.
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuthKey {
+    type Error = AuthError;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        authorize(request, &[TokenScope::Full]).await.map(AuthKey)
+    }
+}
+
+/// Grants read access to the user's boxes, also accepting read-only tokens.
+#[derive(Debug)]
+pub struct ReadKey(pub String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ReadKey {
+    type Error = AuthError;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        authorize(request, &[TokenScope::Full, TokenScope::ReadOnly])
+            .await
+            .map(ReadKey)
+    }
+}
+
+/// Grants logging activities and notes, also accepting log-only tokens.
+#[derive(Debug)]
+pub struct LogKey(pub String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LogKey {
+    type Error = AuthError;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        authorize(request, &[TokenScope::Full, TokenScope::LogOnly])
+            .await
+            .map(LogKey)
+    }
+}
+
+/// Grants administering other users, for users with the admin role.
+#[derive(Debug)]
+pub struct AdminKey(pub String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminKey {
+    type Error = AuthError;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let AuthKey(username) = try_outcome!(request.guard::<AuthKey>().await);
+        let role =
+            get_auth_state(request).and_then(|auth_state| auth_state.users().get_user(&username));
+        let e = match role {
+            Ok(user) if user.role == Role::Admin => return Outcome::Success(AdminKey(username)),
+            Err(e) if e.kind == AuthErrorKind::Storage => {
+                request.local_cache(|| Some(e.clone()));
+                return Outcome::Failure((Status::InternalServerError, e));
+            }
+            _ => AuthError::new(
+                AuthErrorKind::Forbidden,
+                &format!("{} is not an admin", username),
+            ),
+        };
+        request.local_cache(|| Some(e.clone()));
+        Outcome::Failure((Status::Forbidden, e))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientInfo {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        // Only a trusted proxy may name another address than its own, lest
+        // clients dodge per-address lockouts or forge audit records.
+        let remote_ip = request.remote().map(|addr| addr.ip());
+        let ip = match get_auth_state(request) {
+            Ok(auth_state) => auth_state.client_ip(remote_ip, request.headers()),
+            Err(_) => remote_ip,
+        };
+        Outcome::Success(ClientInfo {
+            user_agent: request
+                .headers()
+                .get_one("User-Agent")
+                .unwrap_or_default()
+                .to_string(),
+            ip: ip.map(|ip| ip.to_string()).unwrap_or_default(),
+        })
+    }
+}
+
+/// Build the private session cookie to expire along with the session.
+pub(crate) fn session_cookie(token: &str, expires: i64) -> Cookie<'static> {
+    let max_age = time::Duration::milliseconds((expires - get_time()).max(0));
+    Cookie::build(AUTH_COOKIE, token.to_string())
+        .max_age(max_age)
+        .finish()
+}
+
+/// Explain authentication failures to clients with a JSON body, e.g. so
+/// they can prompt to log in again once a session has expired.
+#[catch(401)]
+pub fn unauthorized(request: &Request) -> Json<AuthError> {
+    let cached: &Option<AuthError> = request.local_cache(|| None);
+    Json(
+        cached
+            .clone()
+            .unwrap_or_else(|| AuthError::new(AuthErrorKind::Unauthorized, "Unauthorized")),
+    )
+}
+
+#[catch(403)]
+pub fn forbidden(request: &Request) -> Json<AuthError> {
+    let cached: &Option<AuthError> = request.local_cache(|| None);
+    Json(
+        cached
+            .clone()
+            .unwrap_or_else(|| AuthError::new(AuthErrorKind::Forbidden, "Forbidden")),
+    )
+}
+
+/// The outcome of a correct username and password: either a session, or a
+/// challenge to present with a TOTP code to POST /users/login/totp.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum LoginStep {
+    Session(SessionKey),
+    Totp {
+        totp_challenge: String,
+        expires: i64,
+    },
+}
+
+/// Start a session from a username and password, setting the session
+/// cookie and responding with the session's details, or with an AuthError.
+/// Users with TOTP enabled get a challenge for the second step instead.
+#[post("/users/login", data = "<login_info>")]
+pub fn login(
+    login_info: Result<Json<LoginInfo>, json::Error>,
+    client: ClientInfo,
+    auth_state: &RocketState<AuthState>,
+    cookies: &CookieJar<'_>,
+) -> Result<Json<LoginStep>, AuthError> {
+    // Describe bad bodies without echoing them, since they may hold passwords.
+    let login_info = login_info.map_err(|e| {
+        let reason = match e {
+            json::Error::Io(e) => e.to_string(),
+            json::Error::Parse(_, e) => e.to_string(),
+        };
+        AuthError::new(
+            AuthErrorKind::InvalidInput,
+            &format!(
+                "expected {{\"username\": ..., \"password\": ...}}: {}",
+                reason
+            ),
+        )
+    })?;
+    if login_info.username.is_empty() || login_info.password.is_empty() {
+        return Err(AuthError::new(
+            AuthErrorKind::InvalidInput,
+            "username and password are required",
+        ));
+    }
+    // Passwords are checked without holding the users database, which would
+    // stall every other request while hashing.
+    let checked = auth_state
+        .users()
+        .check_login(login_info.username, &client.ip);
+    let verified = checked.and_then(|_| {
+        let verified = auth_state.auth_user(&login_info);
+        auth_state
+            .users()
+            .record_login(login_info.username, &client.ip, verified.is_ok())?;
+        verified
+    });
+    audit(AuditAction::Login, login_info.username, &client, &verified);
+    let username = verified?;
+
+    let mut auth = auth_state.users();
+    if auth.has_totp(&username)? {
+        let (totp_challenge, expires) = auth.create_login_challenge(&username)?;
+        return Ok(Json(LoginStep::Totp {
+            totp_challenge,
+            expires,
+        }));
+    }
+    let token = auth.create_session(&username, &client)?;
+    let key = auth.auth_cookie(&token)?;
+    cookies.add_private(session_cookie(&token, key.expires));
+    Ok(Json(LoginStep::Session(key)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpLogin<'a> {
+    #[serde(borrow)]
+    pub totp_challenge: Secret<&'a str>,
+    #[serde(borrow)]
+    pub code: Secret<&'a str>,
+}
+
+/// Finish logging in with a TOTP or recovery code, subject to the same
+/// throttling as passwords.
+#[post(
+    "/users/login/totp",
+    format = "application/json",
+    data = "<totp_login>"
+)]
+pub fn login_totp(
+    totp_login: Json<TotpLogin>,
+    client: ClientInfo,
+    auth_state: &RocketState<AuthState>,
+    cookies: &CookieJar<'_>,
+) -> Result<Json<SessionKey>, AuthError> {
+    let mut auth = auth_state.users();
+    let username = auth.take_login_challenge(totp_login.totp_challenge.expose())?;
+    let verified = auth.check_login(&username, &client.ip).and_then(|_| {
+        let verified = auth.verify_totp(&username, totp_login.code.expose());
+        auth.record_login(&username, &client.ip, verified.is_ok())?;
+        verified
+    });
+    audit(AuditAction::LoginTotp, &username, &client, &verified);
+    verified?;
+
+    let token = auth.create_session(&username, &client)?;
+    let key = auth.auth_cookie(&token)?;
+    cookies.add_private(session_cookie(&token, key.expires));
+    Ok(Json(key))
+}
+
+/// Start enrolling in TOTP, responding with the new secret.
+/// TOTP isn't required at login until confirmed with a code.
+#[post("/users/totp")]
+pub fn enroll_totp(
+    auth: AuthKey,
+    auth_state: &RocketState<AuthState>,
+    config: &RocketState<OkraConfig>,
+) -> Result<Json<TotpEnrollment>, AuthError> {
+    Ok(Json(
+        auth_state
+            .users()
+            .enroll_totp(&auth.0, &config.totp_issuer)?,
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpCode<'a> {
+    #[serde(borrow)]
+    pub code: Secret<&'a str>,
+}
+
+/// Turn on TOTP with a code from the new secret, responding with recovery
+/// codes, each usable once in place of a TOTP code.
+#[post("/users/totp/confirm", format = "application/json", data = "<code>")]
+pub fn confirm_totp(
+    code: Json<TotpCode>,
+    auth: AuthKey,
+    client: ClientInfo,
+    auth_state: &RocketState<AuthState>,
+) -> Result<Json<Vec<String>>, AuthError> {
+    let codes = auth_state.users().confirm_totp(&auth.0, code.code.expose());
+    audit(AuditAction::TotpEnable, &auth.0, &client, &codes);
+    Ok(Json(codes?))
+}
+
+/// Turn off TOTP, given a current TOTP or recovery code.
+#[delete("/users/totp", format = "application/json", data = "<code>")]
+pub fn disable_totp(
+    code: Json<TotpCode>,
+    auth: AuthKey,
+    client: ClientInfo,
+    auth_state: &RocketState<AuthState>,
+) -> Result<String, AuthError> {
+    let mut auth_db = auth_state.users();
+    let disabled = auth_db
+        .verify_totp(&auth.0, code.code.expose())
+        .and_then(|_| auth_db.disable_totp(&auth.0));
+    audit(AuditAction::TotpDisable, &auth.0, &client, &disabled);
+    disabled?;
+    Ok("OK".to_string())
+}
+
+/// End the current session, if any, on the server as well as the client.
+#[get("/users/logout")]
+pub fn logout(
+    client: ClientInfo,
+    auth_state: &RocketState<AuthState>,
+    cookies: &CookieJar<'_>,
+) -> Option<String> {
+    if let Some(cookie) = cookies.get_private(AUTH_COOKIE) {
+        let mut auth = auth_state.users();
+        if let Ok(key) = auth.auth_cookie(cookie.value()) {
+            let revoked = auth.revoke_session(&key.username, key.session);
+            audit(AuditAction::Logout, &key.username, &client, &revoked);
+        }
+    }
+    cookies.remove_private(Cookie::named(AUTH_COOKIE));
+    Some("OK".to_string())
+}
+
+/// List the user's live sessions, flagging the one making the request.
+#[get("/users/sessions/<max_results>")]
+pub fn get_sessions(
+    max_results: usize,
+    key: SessionKey,
+    auth_state: &RocketState<AuthState>,
+) -> Result<Json<Vec<Session>>, AuthError> {
+    let mut dest = result_buffer(max_results);
+    let num_results = auth_state.users().get_sessions(&key.username, &mut dest)?;
+    dest.truncate(num_results);
+    for session in dest.iter_mut() {
+        session.current = session.id == key.session;
+    }
+    Ok(Json(dest))
+}
+
+/// Sign out one of the user's sessions, e.g. on a lost device.
+#[delete("/users/sessions/<session>")]
+pub fn revoke_session(
+    session: i64,
+    key: SessionKey,
+    auth_state: &RocketState<AuthState>,
+) -> Result<String, AuthError> {
+    auth_state.users().revoke_session(&key.username, session)?;
+    Ok("OK".to_string())
+}
+
+/// Sign out all of the user's sessions, including this one.
+#[delete("/users/sessions")]
+pub fn revoke_sessions(
+    key: SessionKey,
+    auth_state: &RocketState<AuthState>,
+    cookies: &CookieJar<'_>,
+) -> Result<String, AuthError> {
+    auth_state.users().revoke_sessions(&key.username)?;
+    cookies.remove_private(Cookie::named(AUTH_COOKIE));
+    Ok("OK".to_string())
+}
+
+/// Push back the expiry of the current session, reissuing its cookie.
+#[post("/users/refresh")]
+pub fn refresh_session(
+    _key: SessionKey,
+    auth_state: &RocketState<AuthState>,
+    cookies: &CookieJar<'_>,
+) -> Result<Json<SessionKey>, AuthError> {
+    let token = cookies
+        .get_private(AUTH_COOKIE)
+        .ok_or_else(|| AuthError::new(AuthErrorKind::Unauthorized, "no session cookie"))?
+        .value()
+        .to_string();
+    let key = auth_state.users().refresh_session(&token)?;
+    cookies.add_private(session_cookie(&token, key.expires));
+    Ok(Json(key))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Registration<'a> {
+    pub username: &'a str,
+    #[serde(borrow)]
+    pub password: Secret<&'a str>,
+    pub invite: Option<&'a str>,
+}
+
+/// Sign up a new user, if allowed by the registration policy.
+#[post(
+    "/users/register",
+    format = "application/json",
+    data = "<registration>"
+)]
+pub fn register(
+    registration: Json<Registration>,
+    client: ClientInfo,
+    auth_state: &RocketState<AuthState>,
+    config: &RocketState<OkraConfig>,
+) -> Result<String, AuthError> {
+    let registered = register_user(&registration, auth_state, config);
+    audit(
+        AuditAction::Register,
+        registration.username,
+        &client,
+        &registered,
+    );
+    registered
+}
+
+fn register_user(
+    registration: &Registration,
+    auth_state: &AuthState,
+    config: &OkraConfig,
+) -> Result<String, AuthError> {
+    let login = LoginInfo {
+        username: registration.username,
+        password: registration.password.expose(),
+    };
+    auth_state.manages_passwords()?;
+    let invite = match config.registration {
+        RegistrationPolicy::Disabled => {
+            return Err(AuthError::new(
+                AuthErrorKind::Forbidden,
+                "registration is disabled",
+            ))
+        }
+        RegistrationPolicy::Open => None,
+        RegistrationPolicy::Invite => match registration.invite {
+            Some(code) if config.invite_codes.iter().any(|c| c == code) => Some(code),
+            _ => {
+                return Err(AuthError::new(
+                    AuthErrorKind::Forbidden,
+                    "a valid invite code is required",
+                ))
+            }
+        },
+    };
+    check_username(login.username)?;
+    check_password(&login, config.min_password_length)?;
+
+    let mut auth = auth_state.users();
+    auth.add_user(&login)?;
+    if let Some(code) = invite {
+        if let Err(e) = auth.redeem_invite(code, login.username) {
+            auth.delete_user(login.username).ok();
+            return Err(e);
+        }
+    }
+    Ok(format!("welcome {}", login.username))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordChange<'a> {
+    #[serde(borrow)]
+    pub old_password: Secret<&'a str>,
+    #[serde(borrow)]
+    pub new_password: Secret<&'a str>,
+}
+
+/// Change the user's own password, given the current one.
+/// All of the user's sessions end, so clients must log in again.
+#[post("/users/password", format = "application/json", data = "<change>")]
+pub fn change_password(
+    change: Json<PasswordChange>,
+    auth: AuthKey,
+    client: ClientInfo,
+    auth_state: &RocketState<AuthState>,
+    config: &RocketState<OkraConfig>,
+    cookies: &CookieJar<'_>,
+) -> Result<String, AuthError> {
+    let changed = change_user_password(&auth.0, &change, &client, auth_state, config);
+    audit(AuditAction::PasswordChange, &auth.0, &client, &changed);
+    changed?;
+    cookies.remove_private(Cookie::named(AUTH_COOKIE));
+    Ok("OK".to_string())
+}
+
+fn change_user_password(
+    username: &str,
+    change: &PasswordChange,
+    client: &ClientInfo,
+    auth_state: &AuthState,
+    config: &OkraConfig,
+) -> Result<(), AuthError> {
+    let old_login = LoginInfo {
+        username,
+        password: change.old_password.expose(),
+    };
+    let new_login = LoginInfo {
+        username,
+        password: change.new_password.expose(),
+    };
+    auth_state.manages_passwords()?;
+    auth_state.users().check_login(username, &client.ip)?;
+    let verified = auth_state.auth_user(&old_login);
+    auth_state
+        .users()
+        .record_login(username, &client.ip, verified.is_ok())?;
+    verified?;
+    check_password(&new_login, config.min_password_length)?;
+    auth_state.set_password(username, new_login.password)?;
+    auth_state.users().revoke_sessions(username)
+}
+
+/// When a deleted account will be, or was, purged, in epoch milliseconds.
+#[derive(Clone, Debug, Serialize)]
+pub struct AccountDeletion {
+    pub purge_after: i64,
+}
+
+/// Delete the user's account, with their sessions, tokens and boxes.
+/// With a grace period configured, the user's sessions and tokens end now,
+/// but the purge waits, and the user may log back in and cancel it.
+#[delete("/users/account")]
+pub fn delete_account(
+    auth: AuthKey,
+    client: ClientInfo,
+    auth_state: &RocketState<AuthState>,
+    config: &RocketState<OkraConfig>,
+    cookies: &CookieJar<'_>,
+) -> Result<Json<AccountDeletion>, AuthError> {
+    let deleted = schedule_account_deletion(&auth.0, auth_state, config);
+    audit(AuditAction::AccountDelete, &auth.0, &client, &deleted);
+    let purge_after = deleted?;
+    cookies.remove_private(Cookie::named(AUTH_COOKIE));
+    Ok(Json(AccountDeletion { purge_after }))
+}
+
+fn schedule_account_deletion(
+    username: &str,
+    auth_state: &AuthState,
+    config: &OkraConfig,
+) -> Result<i64, AuthError> {
+    let mut auth = auth_state.users();
+    let now = get_time();
+    if config.account_deletion_grace_secs == 0 {
+        BoxPaths::new(&config.data_dir).delete_account(&mut *auth, username)?;
+        return Ok(now);
+    }
+    let purge_after = now + config.account_deletion_grace_secs as i64 * 1000;
+    auth.schedule_deletion(username, purge_after)?;
+    Ok(purge_after)
+}
+
+/// Keep the user's account after asking to delete it, during the grace
+/// period.
+#[delete("/users/account/deletion")]
+pub fn cancel_account_deletion(
+    auth: AuthKey,
+    client: ClientInfo,
+    auth_state: &RocketState<AuthState>,
+) -> Result<String, AuthError> {
+    let cancelled = auth_state.users().cancel_deletion(&auth.0);
+    audit(AuditAction::AccountRestore, &auth.0, &client, &cancelled);
+    cancelled?;
+    Ok("OK".to_string())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordReset<'a> {
+    #[serde(borrow)]
+    pub password: Secret<&'a str>,
+}
+
+/// Set another user's password, ending all of their sessions.
+#[post(
+    "/admin/users/<username>/password",
+    format = "application/json",
+    data = "<reset>"
+)]
+pub fn reset_password(
+    username: &str,
+    reset: Json<PasswordReset>,
+    admin: AdminKey,
+    client: ClientInfo,
+    auth_state: &RocketState<AuthState>,
+    config: &RocketState<OkraConfig>,
+) -> Result<String, AuthError> {
+    let login = LoginInfo {
+        username,
+        password: reset.password.expose(),
+    };
+    let reset = auth_state
+        .manages_passwords()
+        .and_then(|_| check_password(&login, config.min_password_length))
+        .and_then(|_| {
+            auth_state.set_password(username, login.password)?;
+            auth_state.users().revoke_sessions(username)
+        });
+    AuditEvent::new(AuditAction::PasswordReset, username, &client, &reset)
+        .by(&admin.0)
+        .log();
+    reset?;
+    Ok("OK".to_string())
+}
+
+/// List usernames and addresses with recent failed logins, including those
+/// locked out.
+#[get("/admin/logins/failed/<max_results>")]
+pub fn get_login_failures(
+    max_results: usize,
+    _admin: AdminKey,
+    auth_state: &RocketState<AuthState>,
+) -> Result<Json<Vec<LoginFailures>>, AuthError> {
+    let mut dest = result_buffer(max_results);
+    let num_results = auth_state.users().get_login_failures(&mut dest)?;
+    dest.truncate(num_results);
+    Ok(Json(dest))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenRequest<'a> {
+    pub name: &'a str,
+    #[serde(default)]
+    pub scope: TokenScope,
+}
+
+/// A newly minted token; the secret cannot be retrieved again.
+#[derive(Debug, Serialize)]
+pub struct NewToken {
+    pub id: i64,
+    pub token: Secret<String>,
+}
+
+/// Mint a bearer token for scripts and devices, e.g. with
+/// {"name": "button box", "scope": "log_only"}.
+#[post("/users/tokens", format = "application/json", data = "<request>")]
+pub fn create_token(
+    request: Json<TokenRequest>,
+    auth: AuthKey,
+    client: ClientInfo,
+    auth_state: &RocketState<AuthState>,
+) -> Result<Json<NewToken>, AuthError> {
+    let created = auth_state
+        .users()
+        .create_token(&auth.0, request.name, request.scope);
+    audit(AuditAction::TokenCreate, &auth.0, &client, &created);
+    let (id, token) = created?;
+    Ok(Json(NewToken {
+        id,
+        token: Secret(token),
+    }))
+}
+
+#[get("/users/tokens/<max_results>")]
+pub fn get_tokens(
+    max_results: usize,
+    auth: AuthKey,
+    auth_state: &RocketState<AuthState>,
+) -> Result<Json<Vec<ApiToken>>, AuthError> {
+    let mut dest = result_buffer(max_results);
+    let num_results = auth_state.users().get_tokens(&auth.0, &mut dest)?;
+    dest.truncate(num_results);
+    Ok(Json(dest))
+}
+
+#[delete("/users/tokens/<token>")]
+pub fn revoke_token(
+    token: i64,
+    auth: AuthKey,
+    client: ClientInfo,
+    auth_state: &RocketState<AuthState>,
+) -> Result<String, AuthError> {
+    let revoked = auth_state.users().revoke_token(&auth.0, token);
+    audit(AuditAction::TokenRevoke, &auth.0, &client, &revoked);
+    revoked?;
+    Ok("OK".to_string())
 }
 
 #[cfg(test)]
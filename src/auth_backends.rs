@@ -0,0 +1,344 @@
+use crate::auth::{
+    bad_credentials, verify_password, Auth, AuthError, AuthErrorKind, LoginInfo, Role, SqliteAuth,
+};
+use crate::config::{AuthBackendKind, OkraConfig};
+use bcrypt::{hash, verify, DEFAULT_COST};
+use rocket::http::HeaderMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+pub static USERS_DB_NAME: &str = "users.sqlite";
+/// The header a trusted proxy names the client's address in.
+pub static REAL_IP_HEADER: &str = "X-Real-IP";
+
+/// Where users and their passwords come from.
+/// Sessions, tokens and the like are kept in SqliteAuth whatever the backend.
+pub trait AuthBackend: Send + Sync {
+    /// Check the username and password, returning the username.
+    /// Lock users only to read or record, not while hashing, which would
+    /// stall every other request.
+    fn auth_user(&self, users: &Mutex<SqliteAuth>, login: &LoginInfo) -> Result<String, AuthError>;
+
+    /// Whether users may register and change passwords through okra.
+    fn manages_passwords(&self) -> bool {
+        false
+    }
+
+    /// Name the user that an upstream proxy already authenticated, if the
+    /// request came from a trusted proxy.
+    fn remote_user(&self, _remote_ip: Option<IpAddr>, _headers: &HeaderMap) -> Option<String> {
+        None
+    }
+}
+
+/// Users with bcrypt passwords in the SQLite users database.
+pub struct SqliteBackend;
+
+impl AuthBackend for SqliteBackend {
+    fn auth_user(&self, users: &Mutex<SqliteAuth>, login: &LoginInfo) -> Result<String, AuthError> {
+        let (policy, stored) = {
+            let users = lock_users(users);
+            (
+                users.password_policy(),
+                users.password_hash(login.username)?,
+            )
+        };
+        if let Some(hashed) = verify_password(&policy, login, stored.as_deref())? {
+            lock_users(users).store_rehashed_password(login.username, &hashed);
+        }
+        Ok(login.username.to_string())
+    }
+
+    fn manages_passwords(&self) -> bool {
+        true
+    }
+}
+
+/// Users listed in an Apache-style htpasswd file, e.g. from
+/// `htpasswd -B`.
+/// The file is read on each login so edits take effect immediately.
+/// Only bcrypt entries are supported.
+pub struct HtpasswdBackend {
+    path: PathBuf,
+}
+
+impl HtpasswdBackend {
+    pub fn new(path: &Path) -> Self {
+        HtpasswdBackend {
+            path: path.to_path_buf(),
+        }
+    }
+
+    fn find_hash(&self, username: &str) -> Result<Option<String>, AuthError> {
+        let contents = fs::read_to_string(&self.path).map_err(|e| {
+            AuthError::new(
+                AuthErrorKind::Storage,
+                &format!("cannot read {}: {}", self.path.display(), e),
+            )
+        })?;
+        Ok(contents
+            .lines()
+            .filter_map(|line| line.trim().split_once(':'))
+            .find(|(name, _)| *name == username)
+            .map(|(_, hash)| hash.to_string()))
+    }
+}
+
+impl AuthBackend for HtpasswdBackend {
+    fn auth_user(
+        &self,
+        _users: &Mutex<SqliteAuth>,
+        login: &LoginInfo,
+    ) -> Result<String, AuthError> {
+        let stored = match self.find_hash(login.username)? {
+            Some(stored) if stored.starts_with("$2") => stored,
+            Some(_) => {
+                log::error!(
+                    "unsupported htpasswd hash for {}, use bcrypt (htpasswd -B)",
+                    login.username
+                );
+                return Err(bad_credentials());
+            }
+            None => {
+                // As for SqliteAuth, don't reveal which usernames exist.
+                hash(login.password, DEFAULT_COST).ok();
+                return Err(bad_credentials());
+            }
+        };
+        match verify(login.password, &stored) {
+            Ok(true) => Ok(login.username.to_string()),
+            Ok(false) => Err(bad_credentials()),
+            Err(e) => Err(AuthError::new(AuthErrorKind::Storage, &e.to_string())),
+        }
+    }
+}
+
+/// Users authenticated by a reverse proxy, e.g. an SSO gateway, which names
+/// them in a header.
+/// The header is only believed from the trusted proxy addresses.
+pub struct ProxyBackend {
+    header: String,
+    trusted: Vec<IpAddr>,
+}
+
+impl ProxyBackend {
+    pub fn new(header: &str, trusted: &[IpAddr]) -> Self {
+        ProxyBackend {
+            header: header.to_string(),
+            trusted: trusted.to_vec(),
+        }
+    }
+}
+
+impl AuthBackend for ProxyBackend {
+    fn auth_user(
+        &self,
+        _users: &Mutex<SqliteAuth>,
+        _login: &LoginInfo,
+    ) -> Result<String, AuthError> {
+        Err(AuthError::new(
+            AuthErrorKind::Forbidden,
+            "log in through the authenticating proxy",
+        ))
+    }
+
+    fn remote_user(&self, remote_ip: Option<IpAddr>, headers: &HeaderMap) -> Option<String> {
+        let username = headers.get_one(&self.header)?.trim();
+        match remote_ip {
+            Some(ip) if self.trusted.contains(&ip) && !username.is_empty() => {
+                Some(username.to_string())
+            }
+            _ => {
+                log::warn!(
+                    "ignoring {} header from untrusted address {:?}",
+                    self.header,
+                    remote_ip
+                );
+                None
+            }
+        }
+    }
+}
+
+/// Users who log in through an OpenID Connect identity provider, see
+/// crate::oidc.
+/// Passwords are left to the provider.
+pub struct OidcBackend;
+
+impl AuthBackend for OidcBackend {
+    fn auth_user(
+        &self,
+        _users: &Mutex<SqliteAuth>,
+        _login: &LoginInfo,
+    ) -> Result<String, AuthError> {
+        Err(AuthError::new(
+            AuthErrorKind::Forbidden,
+            "log in through the identity provider at /users/oidc/login",
+        ))
+    }
+}
+
+/// The configured auth backend, along with the users database holding
+/// sessions and tokens, shared across requests as Rocket state.
+pub struct AuthState {
+    backend: Box<dyn AuthBackend>,
+    trusted_proxies: Vec<IpAddr>,
+    users: Mutex<SqliteAuth>,
+}
+
+impl AuthState {
+    pub fn new(backend: Box<dyn AuthBackend>, users: SqliteAuth) -> Self {
+        AuthState {
+            backend,
+            trusted_proxies: Vec::new(),
+            users: Mutex::new(users),
+        }
+    }
+
+    /// Believe the client addresses these proxies pass on in X-Real-IP.
+    pub fn with_trusted_proxies(mut self, trusted_proxies: &[IpAddr]) -> Self {
+        self.trusted_proxies = trusted_proxies.to_vec();
+        self
+    }
+
+    pub fn from_config(config: &OkraConfig) -> Result<Self, String> {
+        let passwords = config.password_policy();
+        passwords.validate()?;
+        let path = Path::new(&config.data_dir).join(USERS_DB_NAME);
+        let users = SqliteAuth::new(&path.to_string_lossy())
+            .map_err(|e| {
+                format!(
+                    "cannot open {}: {}",
+                    path.display(),
+                    e.message.unwrap_or_default()
+                )
+            })?
+            .with_login_limits(config.login_limits())
+            .with_password_policy(passwords)
+            .with_timeouts(config.session_timeouts());
+        let trusted = config
+            .trusted_proxies
+            .iter()
+            .map(|ip| {
+                ip.parse::<IpAddr>()
+                    .map_err(|_| format!("invalid trusted proxy address '{}'", ip))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let backend: Box<dyn AuthBackend> = match config.auth_backend {
+            AuthBackendKind::Sqlite => Box::new(SqliteBackend),
+            AuthBackendKind::Htpasswd => match &config.htpasswd_file {
+                Some(file) => Box::new(HtpasswdBackend::new(Path::new(file))),
+                None => return Err("the htpasswd backend needs htpasswd_file".to_string()),
+            },
+            AuthBackendKind::Proxy => {
+                if trusted.is_empty() {
+                    return Err("the proxy backend needs trusted_proxies".to_string());
+                }
+                Box::new(ProxyBackend::new(&config.proxy_user_header, &trusted))
+            }
+            AuthBackendKind::Oidc => Box::new(OidcBackend),
+        };
+        let auth_state = AuthState::new(backend, users).with_trusted_proxies(&trusted);
+        auth_state.promote_admins(&config.admins);
+        Ok(auth_state)
+    }
+
+    /// Give the users named in the admins setting the admin role.
+    /// Users yet to log in, e.g. through a proxy, are promoted at a later
+    /// startup.
+    fn promote_admins(&self, admins: &[String]) {
+        let mut users = self.users();
+        for admin in admins {
+            if let Err(e) = users.set_role(admin, Role::Admin) {
+                log::warn!("cannot make {} an admin: {}", admin, e.msg);
+            }
+        }
+    }
+
+    /// Refuse users whose logins an admin disabled.
+    pub(crate) fn check_enabled(users: &SqliteAuth, username: &str) -> Result<(), AuthError> {
+        if users.get_user(username)?.disabled {
+            Err(AuthError::new(
+                AuthErrorKind::Forbidden,
+                &format!("{} is disabled", username),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Check the username and password with the backend, making sure the
+    /// user has a record for their sessions and storage.
+    pub fn auth_user(&self, login: &LoginInfo) -> Result<String, AuthError> {
+        let username = self.backend.auth_user(&self.users, login)?;
+        let mut users = self.users();
+        users.ensure_user(&username)?;
+        Self::check_enabled(&users, &username)?;
+        Ok(username)
+    }
+
+    /// Hash and store the user's new password, holding the users database
+    /// only to store it.
+    pub fn set_password(&self, username: &str, password: &str) -> Result<(), AuthError> {
+        let hashed = self.users().password_policy().hash(password)?;
+        self.users().store_password_hash(username, &hashed)
+    }
+
+    pub fn manages_passwords(&self) -> Result<(), AuthError> {
+        if self.backend.manages_passwords() {
+            Ok(())
+        } else {
+            Err(AuthError::new(
+                AuthErrorKind::Forbidden,
+                "passwords are managed outside of okra",
+            ))
+        }
+    }
+
+    /// The client's address: the peer's, unless the peer is a trusted proxy
+    /// passing on the client's address.
+    pub fn client_ip(&self, remote_ip: Option<IpAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        match remote_ip {
+            Some(ip) if self.trusted_proxies.contains(&ip) => headers
+                .get_one(REAL_IP_HEADER)
+                .and_then(|real_ip| real_ip.trim().parse().ok())
+                .or(remote_ip),
+            _ => remote_ip,
+        }
+    }
+
+    /// Accept a user named by a trusted proxy, unless disabled, making sure
+    /// they have a record for their sessions and storage.
+    pub fn remote_user(&self, remote_ip: Option<IpAddr>, headers: &HeaderMap) -> Option<String> {
+        let username = self.backend.remote_user(remote_ip, headers)?;
+        let mut users = self.users();
+        if let Err(e) = users.ensure_user(&username) {
+            log::error!("cannot add proxy user {}: {}", username, e.msg);
+            return None;
+        }
+        match Self::check_enabled(&users, &username) {
+            Ok(()) => Some(username),
+            Err(e) => {
+                log::info!("refusing proxy user: {}", e.msg);
+                None
+            }
+        }
+    }
+
+    /// Borrow the users database, waiting on other requests using it.
+    pub fn users(&self) -> MutexGuard<'_, SqliteAuth> {
+        lock_users(&self.users)
+    }
+}
+
+fn lock_users(users: &Mutex<SqliteAuth>) -> MutexGuard<'_, SqliteAuth> {
+    users
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+#[path = "./auth_backends_test.rs"]
+mod auth_backends_test;
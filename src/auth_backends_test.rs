@@ -0,0 +1,152 @@
+use super::*;
+use rocket::http::Header;
+
+fn htpasswd_file(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("okra_{}_{}.htpasswd", name, std::process::id()));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn checks_htpasswd_users() {
+    let path = htpasswd_file(
+        "checks_htpasswd_users",
+        &format!(
+            "# admins\nbob:{}\nalice:{{SHA}}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\n",
+            hash("secret", 4).unwrap().replace("$2b$", "$2y$")
+        ),
+    );
+    let auth_state = AuthState::new(
+        Box::new(HtpasswdBackend::new(&path)),
+        SqliteAuth::new(":memory:").unwrap(),
+    );
+    assert!(auth_state.manages_passwords().is_err());
+
+    let login = |username, password| LoginInfo { username, password };
+    assert_eq!(
+        auth_state.auth_user(&login("bob", "secret")).unwrap(),
+        "bob"
+    );
+    let users = auth_state.users();
+    assert_eq!(users.get_user_id("bob").unwrap().len(), 32);
+    // no password login against the users database for backend users
+    assert!(users.auth_user(&login("bob", "secret")).is_err());
+    drop(users);
+
+    for (username, password) in &[("bob", "guess"), ("alice", "password"), ("carol", "secret")] {
+        let e = auth_state
+            .auth_user(&login(username, password))
+            .unwrap_err();
+        assert_eq!(e.kind, AuthErrorKind::Unauthorized);
+    }
+    assert!(auth_state.users().get_user_id("carol").is_err());
+}
+
+#[test]
+fn names_clients_behind_trusted_proxies() {
+    let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+    let client: IpAddr = "192.0.2.7".parse().unwrap();
+    let auth_state = AuthState::new(
+        Box::new(SqliteBackend),
+        SqliteAuth::new(":memory:").unwrap(),
+    )
+    .with_trusted_proxies(&[proxy]);
+    let mut headers = HeaderMap::new();
+    assert_eq!(auth_state.client_ip(Some(proxy), &headers), Some(proxy));
+    headers.add(Header::new("X-Real-IP", "192.0.2.7"));
+    assert_eq!(auth_state.client_ip(Some(proxy), &headers), Some(client));
+    let other: IpAddr = "10.0.0.2".parse().unwrap();
+    assert_eq!(auth_state.client_ip(Some(other), &headers), Some(other));
+    assert_eq!(auth_state.client_ip(None, &headers), None);
+}
+
+#[test]
+fn trusts_remote_users_from_proxies() {
+    let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+    let auth_state = AuthState::new(
+        Box::new(ProxyBackend::new("X-Remote-User", &[proxy])),
+        SqliteAuth::new(":memory:").unwrap(),
+    );
+    let mut headers = HeaderMap::new();
+    assert_eq!(auth_state.remote_user(Some(proxy), &headers), None);
+    headers.add(Header::new("X-Remote-User", "bob@example.com"));
+    assert_eq!(
+        auth_state.remote_user(Some(proxy), &headers),
+        Some("bob@example.com".to_string())
+    );
+    assert!(auth_state.users().get_user_id("bob@example.com").is_ok());
+    assert_eq!(
+        auth_state.remote_user(Some("10.0.0.2".parse().unwrap()), &headers),
+        None
+    );
+    assert_eq!(auth_state.remote_user(None, &headers), None);
+
+    let login = LoginInfo {
+        username: "bob@example.com",
+        password: "secret",
+    };
+    let e = auth_state.auth_user(&login).unwrap_err();
+    assert_eq!(e.kind, AuthErrorKind::Forbidden);
+
+    auth_state
+        .users()
+        .set_disabled("bob@example.com", true)
+        .unwrap();
+    assert_eq!(auth_state.remote_user(Some(proxy), &headers), None);
+}
+
+#[test]
+fn refuses_disabled_users() {
+    let auth_state = AuthState::new(
+        Box::new(SqliteBackend),
+        SqliteAuth::new(":memory:").unwrap(),
+    );
+    let login = LoginInfo {
+        username: "bob",
+        password: "secret",
+    };
+    auth_state.users().add_user(&login).unwrap();
+    assert_eq!(auth_state.auth_user(&login).unwrap(), "bob");
+    auth_state.users().set_disabled("bob", true).unwrap();
+    let e = auth_state.auth_user(&login).unwrap_err();
+    assert_eq!(e.kind, AuthErrorKind::Forbidden);
+}
+
+#[test]
+fn builds_backends_from_config() {
+    let mut config = OkraConfig {
+        data_dir: std::env::temp_dir().to_str().unwrap().to_string(),
+        ..OkraConfig::default()
+    };
+    assert!(AuthState::from_config(&config).is_ok());
+    config.auth_backend = AuthBackendKind::Htpasswd;
+    assert!(AuthState::from_config(&config).is_err());
+    config.auth_backend = AuthBackendKind::Proxy;
+    assert!(AuthState::from_config(&config).is_err());
+    config.trusted_proxies = vec!["localhost".to_string()];
+    assert!(AuthState::from_config(&config).is_err());
+    config.trusted_proxies = vec!["127.0.0.1".to_string(), "::1".to_string()];
+    assert!(AuthState::from_config(&config).is_ok());
+}
+
+#[test]
+fn promotes_configured_admins() {
+    let dir = std::env::temp_dir().join(format!("okra_admins_{}", std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    let mut config = OkraConfig {
+        data_dir: dir.to_str().unwrap().to_string(),
+        ..OkraConfig::default()
+    };
+    AuthState::from_config(&config)
+        .unwrap()
+        .users()
+        .ensure_user("bob")
+        .unwrap();
+    config.admins = vec!["bob".to_string(), "carol".to_string()];
+    let auth_state = AuthState::from_config(&config).unwrap();
+    assert_eq!(
+        auth_state.users().get_user("bob").unwrap().role,
+        Role::Admin
+    );
+}
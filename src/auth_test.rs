@@ -1,4 +1,6 @@
 use super::*;
+use crate::passwords::HashAlgorithm;
+use std::sync::atomic::{AtomicI64, Ordering};
 
 #[test]
 fn denies_missing_user() {
@@ -30,8 +32,7 @@ fn grants_user() {
         password: "secret",
     };
     auth.add_user(&login).unwrap();
-    let cookie = auth.auth_user(&login).unwrap();
-    assert!(cookie.len() > 0);
+    assert_eq!(auth.auth_user(&login).unwrap(), "bob");
 }
 
 #[test]
@@ -42,9 +43,17 @@ fn auths_good_cookie() {
         password: "secret",
     };
     auth.add_user(&login).unwrap();
-    let cookie = auth.auth_user(&login).unwrap();
-    let new_cookie = auth.auth_cookie(&cookie).unwrap();
-    assert!(new_cookie.len() > 0);
+    let username = auth.auth_user(&login).unwrap();
+    let cookie = auth
+        .create_session(&username, &ClientInfo::default())
+        .unwrap();
+    let key = auth.auth_cookie(&cookie).unwrap();
+    assert_eq!(key.username, "bob");
+
+    // only the token's hash is stored
+    let mut stat = auth.conn.prepare("SELECT hash FROM sessions;").unwrap();
+    stat.next().unwrap();
+    assert_eq!(stat.read::<String>(0).unwrap(), hash_token(&cookie));
 }
 
 #[test]
@@ -64,6 +73,17 @@ fn denies_expired_cookie() {
     auth.add_user(&login).unwrap();
     let cookie = format!("{} {}", 0, login.username);
     assert!(auth.auth_cookie(&cookie).is_err());
+
+    let cookie = auth
+        .create_session(login.username, &ClientInfo::default())
+        .unwrap();
+    auth.conn
+        .execute("UPDATE sessions SET expires = 0;")
+        .unwrap();
+    let err = auth.auth_cookie(&cookie).unwrap_err();
+    assert_eq!(err.kind, AuthErrorKind::SessionExpired);
+    let mut sessions = vec![Session::default(); 2];
+    assert_eq!(auth.get_sessions("bob", &mut sessions).unwrap(), 0);
 }
 
 #[test]
@@ -76,3 +96,588 @@ fn rejects_adding_duplicate_user() {
     assert!(auth.add_user(&login).is_ok());
     assert!(auth.add_user(&login).is_err());
 }
+
+#[test]
+fn lists_and_revokes_sessions() {
+    let mut auth = SqliteAuth::new(":memory:").unwrap();
+    let client = ClientInfo {
+        user_agent: "curl/7.68.0".to_string(),
+        ip: "10.0.0.2".to_string(),
+    };
+    let phone = auth.create_session("bob", &client).unwrap();
+    let laptop = auth.create_session("bob", &client).unwrap();
+    let other = auth.create_session("alice", &client).unwrap();
+    assert_ne!(phone, laptop);
+
+    let mut sessions = vec![Session::default(); 3];
+    assert_eq!(auth.get_sessions("bob", &mut sessions).unwrap(), 2);
+    assert_eq!(sessions[0].user_agent, "curl/7.68.0");
+    assert_eq!(sessions[0].ip, "10.0.0.2");
+
+    let phone_id = auth.auth_cookie(&phone).unwrap().session;
+    let e = auth.revoke_session("alice", phone_id).unwrap_err();
+    assert_eq!(e.kind, AuthErrorKind::NotFound);
+    assert!(auth.auth_cookie(&phone).is_ok());
+    auth.revoke_session("bob", phone_id).unwrap();
+    assert!(auth.auth_cookie(&phone).is_err());
+    assert!(auth.auth_cookie(&laptop).is_ok());
+
+    auth.revoke_sessions("bob").unwrap();
+    assert!(auth.auth_cookie(&laptop).is_err());
+    assert!(auth.auth_cookie(&other).is_ok());
+}
+
+#[test]
+fn renews_sessions_until_absolute_expiry() {
+    let mut auth = SqliteAuth::new(":memory:")
+        .unwrap()
+        .with_timeouts(SessionTimeouts {
+            idle: Duration::from_secs(60 * 60),
+            absolute: Duration::from_secs(24 * 60 * 60),
+        });
+    let cookie = auth.create_session("bob", &ClientInfo::default()).unwrap();
+    let key = auth.auth_cookie(&cookie).unwrap();
+    assert!(!key.renew);
+
+    // Pretend the session is 50 minutes into its idle hour.
+    let now = get_time();
+    auth.conn
+        .execute(format!(
+            "UPDATE sessions SET expires = {};",
+            now + 10 * 60 * 1000
+        ))
+        .unwrap();
+    let key = auth.auth_cookie(&cookie).unwrap();
+    assert!(key.renew);
+    let key = auth.refresh_session(&cookie).unwrap();
+    assert!(key.expires >= now + 60 * 60 * 1000);
+
+    // Refreshing cannot outlive the absolute timeout.
+    auth.conn
+        .execute(format!(
+            "UPDATE sessions SET created = {};",
+            now - 24 * 60 * 60 * 1000 + 5 * 60 * 1000
+        ))
+        .unwrap();
+    let key = auth.refresh_session(&cookie).unwrap();
+    assert!(key.expires <= now + 5 * 60 * 1000);
+    assert!(!auth.auth_cookie(&cookie).unwrap().renew);
+
+    auth.conn
+        .execute(format!(
+            "UPDATE sessions SET created = {};",
+            now - 24 * 60 * 60 * 1000
+        ))
+        .unwrap();
+    let err = auth.auth_cookie(&cookie).unwrap_err();
+    assert_eq!(err.kind, AuthErrorKind::SessionExpired);
+}
+
+#[test]
+fn creates_and_revokes_scoped_tokens() {
+    let mut auth = SqliteAuth::new(":memory:").unwrap();
+    let (full_id, full) = auth
+        .create_token("bob", "laptop", TokenScope::Full)
+        .unwrap();
+    let (_, logger) = auth
+        .create_token("bob", "button box", TokenScope::LogOnly)
+        .unwrap();
+    assert_ne!(full, logger);
+    assert_eq!(
+        auth.auth_token(&full).unwrap(),
+        ("bob".to_string(), TokenScope::Full)
+    );
+    assert_eq!(
+        auth.auth_token(&logger).unwrap(),
+        ("bob".to_string(), TokenScope::LogOnly)
+    );
+    assert!(auth.auth_token("bogus").is_err());
+
+    let mut stat = auth
+        .conn
+        .prepare("SELECT COUNT(*) FROM apiTokens WHERE hash = ?")
+        .unwrap();
+    stat.bind(1, full.as_str()).unwrap();
+    stat.next().unwrap();
+    assert_eq!(stat.read::<i64>(0).unwrap(), 0);
+    drop(stat);
+
+    let mut tokens = vec![ApiToken::default(); 3];
+    assert_eq!(auth.get_tokens("bob", &mut tokens).unwrap(), 2);
+    assert_eq!(tokens[0].id, full_id);
+    assert_eq!(tokens[0].name, "laptop");
+    assert!(tokens[0].last_used.is_some());
+    assert_eq!(tokens[1].scope, TokenScope::LogOnly);
+    assert_eq!(auth.get_tokens("alice", &mut tokens).unwrap(), 0);
+
+    let e = auth.revoke_token("alice", full_id).unwrap_err();
+    assert_eq!(e.kind, AuthErrorKind::NotFound);
+    assert!(auth.auth_token(&full).is_ok());
+    auth.revoke_token("bob", full_id).unwrap();
+    assert!(auth.auth_token(&full).is_err());
+    assert!(auth.auth_token(&logger).is_ok());
+}
+
+#[test]
+fn checks_usernames() {
+    assert!(check_username("bob").is_ok());
+    assert!(check_username("Bob_2-x").is_ok());
+    assert!(check_username("").is_err());
+    assert!(check_username("-bob").is_err());
+    assert!(check_username("../bob").is_err());
+    assert!(check_username("bob/../alice").is_err());
+    assert!(check_username("bob.sqlite").is_err());
+    assert!(check_username(&"b".repeat(33)).is_err());
+}
+
+#[test]
+fn checks_password_strength() {
+    let login = |password| LoginInfo {
+        username: "bob",
+        password,
+    };
+    assert!(check_password(&login("okra-4-ever"), 10).is_ok());
+    assert!(check_password(&login("okra-4"), 10).is_err());
+    assert!(check_password(&login("okraokraokra"), 10).is_err());
+    assert!(check_password(&login("BOB-is-great"), 10).is_err());
+}
+
+#[test]
+fn redeems_invites_once() {
+    let mut auth = SqliteAuth::new(":memory:").unwrap();
+    auth.redeem_invite("okra-42", "bob").unwrap();
+    let e = auth.redeem_invite("okra-42", "alice").unwrap_err();
+    assert_eq!(e.kind, AuthErrorKind::Forbidden);
+}
+
+#[test]
+fn deletes_users() {
+    let mut auth = SqliteAuth::new(":memory:").unwrap();
+    let login = LoginInfo {
+        username: "bob",
+        password: "secret",
+    };
+    auth.add_user(&login).unwrap();
+    let e = auth.add_user(&login).unwrap_err();
+    assert_eq!(e.kind, AuthErrorKind::Conflict);
+
+    let token = auth.create_session("bob", &ClientInfo::default()).unwrap();
+    auth.delete_user("bob").unwrap();
+    assert!(auth.auth_user(&login).is_err());
+    assert!(auth.auth_cookie(&token).is_err());
+    auth.add_user(&login).unwrap();
+}
+
+#[test]
+fn sets_passwords() {
+    let mut auth = SqliteAuth::new(":memory:").unwrap();
+    let old_login = LoginInfo {
+        username: "bob",
+        password: "secret",
+    };
+    let new_login = LoginInfo {
+        username: "bob",
+        password: "new-secret-1",
+    };
+    auth.add_user(&old_login).unwrap();
+    auth.set_password("bob", new_login.password).unwrap();
+    assert!(auth.auth_user(&old_login).is_err());
+    assert_eq!(auth.auth_user(&new_login).unwrap(), "bob");
+
+    let e = auth.set_password("alice", "new-secret-1").unwrap_err();
+    assert_eq!(e.kind, AuthErrorKind::NotFound);
+}
+
+#[test]
+fn rehashes_outdated_passwords() {
+    let stored_hash = |auth: &SqliteAuth| {
+        let mut stat = auth
+            .conn
+            .prepare("SELECT secret FROM users WHERE username = 'bob';")
+            .unwrap();
+        stat.next().unwrap();
+        stat.read::<String>(0).unwrap()
+    };
+    let bcrypt = PasswordPolicy {
+        bcrypt_cost: 4,
+        ..PasswordPolicy::default()
+    };
+    let mut auth = SqliteAuth::new(":memory:")
+        .unwrap()
+        .with_password_policy(bcrypt);
+    let login = LoginInfo {
+        username: "bob",
+        password: "secret",
+    };
+    auth.add_user(&login).unwrap();
+    let original = stored_hash(&auth);
+    assert!(original.starts_with("$2b$04$"));
+    assert_eq!(auth.auth_user(&login).unwrap(), "bob");
+    assert_eq!(stored_hash(&auth), original);
+
+    let auth = auth.with_password_policy(PasswordPolicy {
+        bcrypt_cost: 5,
+        ..bcrypt
+    });
+    assert!(auth
+        .auth_user(&LoginInfo {
+            username: "bob",
+            password: "guess",
+        })
+        .is_err());
+    assert_eq!(stored_hash(&auth), original);
+    assert_eq!(auth.auth_user(&login).unwrap(), "bob");
+    assert!(stored_hash(&auth).starts_with("$2b$05$"));
+
+    let auth = auth.with_password_policy(PasswordPolicy {
+        algorithm: HashAlgorithm::Argon2id,
+        argon2_memory_kib: 64,
+        argon2_iterations: 1,
+        ..bcrypt
+    });
+    assert_eq!(auth.auth_user(&login).unwrap(), "bob");
+    assert!(stored_hash(&auth).starts_with("$argon2id$v=19$m=64,t=1,p=1$"));
+    assert_eq!(auth.auth_user(&login).unwrap(), "bob");
+}
+
+#[test]
+fn assigns_user_ids() {
+    let mut auth = SqliteAuth::new(":memory:").unwrap();
+    for username in &["bob", "alice"] {
+        auth.add_user(&LoginInfo {
+            username,
+            password: "secret",
+        })
+        .unwrap();
+    }
+    let bob = auth.get_user_id("bob").unwrap();
+    assert_eq!(bob.len(), 32);
+    assert_ne!(bob, auth.get_user_id("alice").unwrap());
+    assert_eq!(bob, auth.get_user_id("bob").unwrap());
+    let e = auth.get_user_id("carol").unwrap_err();
+    assert_eq!(e.kind, AuthErrorKind::NotFound);
+}
+
+#[test]
+fn assigns_ids_to_existing_users() {
+    let path = std::env::temp_dir().join(format!("okra_users_{}.sqlite", std::process::id()));
+    std::fs::remove_file(&path).ok();
+    let conn = sqlite::open(&path).unwrap();
+    conn.execute("CREATE TABLE users (username TEXT UNIQUE, secret TEXT); INSERT INTO users VALUES ('bob', 'x');")
+        .unwrap();
+    drop(conn);
+
+    let auth = SqliteAuth::new(path.to_str().unwrap()).unwrap();
+    assert_eq!(auth.get_user_id("bob").unwrap().len(), 32);
+    let bob = auth.get_user("bob").unwrap();
+    assert_eq!(bob.role, Role::User);
+    assert!(!bob.disabled);
+}
+
+#[test]
+fn hashes_existing_session_tokens() {
+    let path = std::env::temp_dir().join(format!("okra_sessions_{}.sqlite", std::process::id()));
+    std::fs::remove_file(&path).ok();
+    let conn = sqlite::open(&path).unwrap();
+    conn.execute(
+        "CREATE TABLE sessions (
+            token TEXT UNIQUE, username TEXT, created INTEGER, lastSeen INTEGER,
+            expires INTEGER, userAgent TEXT, ip TEXT);",
+    )
+    .unwrap();
+    let now = get_time();
+    conn.execute(format!(
+        "INSERT INTO sessions VALUES ('abc', 'bob', {}, {}, {}, '', '');",
+        now,
+        now,
+        now + 60 * 60 * 1000
+    ))
+    .unwrap();
+    drop(conn);
+
+    let auth = SqliteAuth::new(path.to_str().unwrap()).unwrap();
+    assert_eq!(auth.auth_cookie("abc").unwrap().username, "bob");
+    drop(auth);
+    // and only once
+    let auth = SqliteAuth::new(path.to_str().unwrap()).unwrap();
+    assert_eq!(auth.auth_cookie("abc").unwrap().username, "bob");
+    std::fs::remove_file(&path).ok();
+}
+
+#[test]
+fn sets_roles_and_disables_users() {
+    let mut auth = SqliteAuth::new(":memory:").unwrap();
+    for username in &["bob", "alice"] {
+        auth.add_user(&LoginInfo {
+            username,
+            password: "secret",
+        })
+        .unwrap();
+    }
+    let mut users = vec![UserInfo::default(); 1];
+    assert_eq!(auth.get_users("", &mut users).unwrap(), 1);
+    assert_eq!(users[0].username, "alice");
+    assert_eq!(users[0].user_id, auth.get_user_id("alice").unwrap());
+    assert_eq!(users[0].role, Role::User);
+    assert_eq!(auth.get_users("alice", &mut users).unwrap(), 1);
+    assert_eq!(users[0].username, "bob");
+    assert_eq!(auth.get_users("bob", &mut users).unwrap(), 0);
+
+    auth.set_role("bob", Role::Admin).unwrap();
+    assert_eq!(auth.get_user("bob").unwrap().role, Role::Admin);
+    let e = auth.set_role("carol", Role::Admin).unwrap_err();
+    assert_eq!(e.kind, AuthErrorKind::NotFound);
+    auth.add_user_with_role(
+        &LoginInfo {
+            username: "dave",
+            password: "secret",
+        },
+        Role::Admin,
+    )
+    .unwrap();
+    assert_eq!(auth.get_user("dave").unwrap().role, Role::Admin);
+
+    let cookie = auth.create_session("bob", &ClientInfo::default()).unwrap();
+    let (_, token) = auth
+        .create_token("bob", "laptop", TokenScope::Full)
+        .unwrap();
+    auth.set_disabled("bob", true).unwrap();
+    assert!(auth.get_user("bob").unwrap().disabled);
+    assert!(auth.auth_cookie(&cookie).is_err());
+    assert!(auth.auth_token(&token).is_err());
+    auth.set_disabled("bob", false).unwrap();
+    assert!(!auth.get_user("bob").unwrap().disabled);
+    let e = auth.set_disabled("carol", true).unwrap_err();
+    assert_eq!(e.kind, AuthErrorKind::NotFound);
+}
+
+#[test]
+fn schedules_and_cancels_deletions() {
+    let mut auth = SqliteAuth::new(":memory:").unwrap();
+    for username in &["bob", "alice"] {
+        auth.add_user(&LoginInfo {
+            username,
+            password: "secret",
+        })
+        .unwrap();
+    }
+    let cookie = auth.create_session("bob", &ClientInfo::default()).unwrap();
+    let (_, token) = auth
+        .create_token("bob", "laptop", TokenScope::Full)
+        .unwrap();
+    auth.schedule_deletion("bob", 1000).unwrap();
+    auth.schedule_deletion("alice", 5000).unwrap();
+    assert!(auth.auth_cookie(&cookie).is_err());
+    assert!(auth.auth_token(&token).is_err());
+    let e = auth.schedule_deletion("carol", 1000).unwrap_err();
+    assert_eq!(e.kind, AuthErrorKind::NotFound);
+
+    let mut due = vec!["".to_string(); 3];
+    assert_eq!(auth.get_due_deletions(999, &mut due).unwrap(), 0);
+    assert_eq!(auth.get_due_deletions(5000, &mut due).unwrap(), 2);
+    assert_eq!(due[..2], ["bob".to_string(), "alice".to_string()]);
+
+    auth.cancel_deletion("bob").unwrap();
+    let e = auth.cancel_deletion("bob").unwrap_err();
+    assert_eq!(e.kind, AuthErrorKind::NotFound);
+    auth.delete_user("alice").unwrap();
+    assert_eq!(auth.get_due_deletions(5000, &mut due).unwrap(), 0);
+}
+
+#[test]
+fn backs_off_and_locks_out_failed_logins() {
+    let mut auth = SqliteAuth::new(":memory:")
+        .unwrap()
+        .with_login_limits(LoginLimits {
+            backoff: Duration::from_secs(0),
+            max_failures: 3,
+            lockout: Duration::from_secs(60),
+        });
+    auth.record_login("bob", "10.0.0.2", false).unwrap();
+    auth.record_login("bob", "10.0.0.2", false).unwrap();
+    assert!(auth.check_login("bob", "10.0.0.3").is_ok());
+    auth.record_login("bob", "10.0.0.2", true).unwrap();
+    auth.record_login("bob", "10.0.0.3", false).unwrap();
+    assert!(auth.check_login("bob", "10.0.0.3").is_ok());
+
+    auth.record_login("alice", "10.0.0.2", false).unwrap();
+    let e = auth.check_login("carol", "10.0.0.2").unwrap_err();
+    assert_eq!(e.kind, AuthErrorKind::TooManyAttempts);
+    assert!(auth.check_login("carol", "10.0.0.4").is_ok());
+
+    let mut failures = vec![LoginFailures::default(); 5];
+    assert_eq!(auth.get_login_failures(&mut failures).unwrap(), 4);
+    let ip = failures
+        .iter()
+        .find(|f| f.kind == "ip" && f.key == "10.0.0.2")
+        .unwrap();
+    assert_eq!(ip.failures, 3);
+    assert!(ip.locked_until >= ip.last_failure + 60000);
+
+    auth.conn
+        .execute("UPDATE loginFailures SET lockedUntil = 0")
+        .unwrap();
+    assert!(auth.check_login("carol", "10.0.0.2").is_ok());
+}
+
+static LOCKOUT_NOW: AtomicI64 = AtomicI64::new(1000);
+
+fn lockout_now() -> i64 {
+    LOCKOUT_NOW.load(Ordering::SeqCst)
+}
+
+#[test]
+fn locks_out_by_the_clock() {
+    let mut auth = SqliteAuth::new(":memory:")
+        .unwrap()
+        .with_clock(lockout_now)
+        .with_login_limits(LoginLimits {
+            backoff: Duration::from_secs(10),
+            max_failures: 5,
+            lockout: Duration::from_secs(60),
+        });
+    auth.record_login("bob", "10.0.0.2", false).unwrap();
+    let mut failures = vec![LoginFailures::default(); 5];
+    assert_eq!(auth.get_login_failures(&mut failures).unwrap(), 2);
+    assert_eq!(failures[0].last_failure, 1000);
+    assert_eq!(failures[0].locked_until, 11000);
+    let e = auth.check_login("bob", "10.0.0.3").unwrap_err();
+    assert_eq!(e.kind, AuthErrorKind::TooManyAttempts);
+
+    LOCKOUT_NOW.store(11000, Ordering::SeqCst);
+    assert!(auth.check_login("bob", "10.0.0.3").is_ok());
+    LOCKOUT_NOW.store(61001, Ordering::SeqCst);
+    assert_eq!(auth.get_login_failures(&mut failures).unwrap(), 0);
+}
+
+#[test]
+fn doubles_login_backoff() {
+    let mut auth = SqliteAuth::new(":memory:")
+        .unwrap()
+        .with_login_limits(LoginLimits {
+            backoff: Duration::from_secs(10),
+            max_failures: 5,
+            lockout: Duration::from_secs(25),
+        });
+    let locked_for = |auth: &SqliteAuth| {
+        let mut stat = auth
+            .conn
+            .prepare("SELECT lockedUntil - lastFailure FROM loginFailures WHERE kind = 'username'")
+            .unwrap();
+        stat.next().unwrap();
+        stat.read::<i64>(0).unwrap()
+    };
+    auth.record_login("bob", "10.0.0.2", false).unwrap();
+    assert_eq!(locked_for(&auth), 10000);
+    auth.record_login("bob", "10.0.0.2", false).unwrap();
+    assert_eq!(locked_for(&auth), 20000);
+    auth.record_login("bob", "10.0.0.2", false).unwrap();
+    assert_eq!(locked_for(&auth), 25000);
+    assert!(auth.check_login("bob", "10.0.0.9").is_err());
+}
+
+#[test]
+fn denies_unknown_users_like_bad_passwords() {
+    let mut auth = SqliteAuth::new(":memory:").unwrap();
+    auth.add_user(&LoginInfo {
+        username: "bob",
+        password: "secret",
+    })
+    .unwrap();
+    let bad_password = auth
+        .auth_user(&LoginInfo {
+            username: "bob",
+            password: "guess",
+        })
+        .unwrap_err();
+    let unknown_user = auth
+        .auth_user(&LoginInfo {
+            username: "alice",
+            password: "guess",
+        })
+        .unwrap_err();
+    assert_eq!(bad_password.kind, AuthErrorKind::Unauthorized);
+    assert_eq!(unknown_user.kind, bad_password.kind);
+    assert_eq!(unknown_user.msg, bad_password.msg);
+}
+
+static TOTP_NOW: AtomicI64 = AtomicI64::new(1111111111000);
+
+fn totp_now() -> i64 {
+    TOTP_NOW.load(Ordering::SeqCst)
+}
+
+fn totp_code(secret: &str) -> String {
+    let secret = totp::decode_secret(secret).unwrap();
+    format!(
+        "{:06}",
+        totp::code_at_step(&secret, totp::step(totp_now()), 6)
+    )
+}
+
+#[test]
+fn enrolls_and_verifies_totp() {
+    let mut auth = SqliteAuth::new(":memory:").unwrap().with_clock(totp_now);
+    let enrollment = auth.enroll_totp("bob", "Okra").unwrap();
+    assert!(enrollment
+        .uri
+        .expose()
+        .starts_with("otpauth://totp/Okra:bob?secret="));
+    assert!(!auth.has_totp("bob").unwrap());
+    let wrong = if totp_code(enrollment.secret.expose()) == "000000" {
+        "111111"
+    } else {
+        "000000"
+    };
+    assert!(auth.confirm_totp("bob", wrong).is_err());
+
+    let code = totp_code(enrollment.secret.expose());
+    let recovery_codes = auth.confirm_totp("bob", &code).unwrap();
+    assert_eq!(recovery_codes.len(), 10);
+    assert!(auth.has_totp("bob").unwrap());
+    assert!(!auth.has_totp("alice").unwrap());
+    let e = auth.enroll_totp("bob", "Okra").unwrap_err();
+    assert_eq!(e.kind, AuthErrorKind::Conflict);
+
+    // codes are good once
+    assert!(auth.verify_totp("bob", &code).is_err());
+    TOTP_NOW.fetch_add(30000, Ordering::SeqCst);
+    let code = totp_code(enrollment.secret.expose());
+    auth.verify_totp("bob", &code).unwrap();
+    assert!(auth.verify_totp("bob", &code).is_err());
+
+    auth.verify_totp("bob", &recovery_codes[3].to_uppercase())
+        .unwrap();
+    assert!(auth.verify_totp("bob", &recovery_codes[3]).is_err());
+    assert!(auth.verify_totp("alice", &recovery_codes[4]).is_err());
+
+    auth.disable_totp("bob").unwrap();
+    assert!(!auth.has_totp("bob").unwrap());
+    assert!(auth.verify_totp("bob", &recovery_codes[4]).is_err());
+}
+
+#[test]
+fn expires_login_challenges() {
+    fn before() -> i64 {
+        1000
+    }
+    fn after() -> i64 {
+        1000 + 5 * 60 * 1000
+    }
+    let mut auth = SqliteAuth::new(":memory:").unwrap().with_clock(before);
+    let (challenge, expires) = auth.create_login_challenge("bob").unwrap();
+    assert_eq!(expires, after());
+    assert_eq!(auth.take_login_challenge(&challenge).unwrap(), "bob");
+    assert!(auth.take_login_challenge(&challenge).is_err());
+
+    let (challenge, _) = auth.create_login_challenge("bob").unwrap();
+    let mut auth = auth.with_clock(after);
+    let e = auth.take_login_challenge(&challenge).unwrap_err();
+    assert_eq!(e.kind, AuthErrorKind::SessionExpired);
+}
+
+#[test]
+fn caps_result_buffers() {
+    assert_eq!(result_buffer::<i64>(3), vec![0; 3]);
+    assert_eq!(result_buffer::<i64>(usize::MAX).len(), MAX_RESULTS);
+}
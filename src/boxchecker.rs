@@ -1,57 +1,433 @@
+use crate::calendar::{parse_local_time, parse_tz};
+use chrono_tz::Tz;
+use rocket::serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
 // Break up into
 // - activity creation
 // - activity log
 // - activity search
+// - goal tracking
 
 pub type ActionId = i64;
 pub type ActivityId = i64;
 pub type AnnotationId = i64;
 
-pub struct BoxCheckerError {
-    pub msg: String,
+/// How a daily goal is measured: total minutes of timed activity, or the
+/// number of activities logged.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GoalUnit {
+    #[default]
+    Count,
+    Minutes,
+}
+
+impl FromStr for GoalUnit {
+    type Err = BoxCheckerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "count" => Ok(GoalUnit::Count),
+            "minutes" => Ok(GoalUnit::Minutes),
+            _ => Err(BoxCheckerError::InvalidInput(format!(
+                "unknown goal unit: '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Goal {
+    pub action: ActionId,
+    pub target: i64,
+    pub unit: GoalUnit,
+}
+
+/// A daily goal to set for an action, e.g. {"target": 30, "unit": "minutes"}.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct GoalSetting {
+    pub target: i64,
+    pub unit: GoalUnit,
+}
+
+/// Progress towards a daily goal over some time range.
+/// Completed is measured in the goal's unit.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct GoalProgress {
+    pub action: ActionId,
+    pub target: i64,
+    pub unit: GoalUnit,
+    pub completed: f64,
+    pub percent: f64,
+}
+
+/// Milliseconds spent on an action, on its own and including time spent on
+/// all of its descendants.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Rollup {
+    pub action: ActionId,
+    pub own_millis: i64,
+    pub total_millis: i64,
+}
+
+/// Consecutive days on which an action was completed, i.e. its goal was met,
+/// or, without a goal, at least one activity was logged.
+/// The current streak is still alive if the action was completed yesterday,
+/// but not yet today.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Streak {
+    pub action: ActionId,
+    pub current: usize,
+    pub longest: usize,
+    pub last_completed: Option<String>,
+}
+
+/// An activity to log, possibly after the fact.
+/// The start of the activity is given either as epoch milliseconds or as a
+/// local date and time in an IANA timezone, defaulting to UTC, or else is
+/// taken to be now.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct LogEntry {
+    pub action: ActionId,
+    pub time: Option<i64>,
+    pub local_time: Option<String>,
+    pub tz: Option<String>,
+    pub duration: Option<i64>,
+    pub note: Option<String>,
+}
+
+impl LogEntry {
+    /// Resolve the start of the activity in epoch milliseconds.
+    pub fn epoch_millis(&self, now_millis: i64) -> Result<i64, BoxCheckerError> {
+        match (self.time, &self.local_time) {
+            (Some(_), Some(_)) => Err(BoxCheckerError::InvalidInput(
+                "both time and local_time given".to_string(),
+            )),
+            (Some(time), None) => Ok(time),
+            (None, Some(local_time)) => {
+                let tz = parse_tz(self.tz.as_deref()).ok_or_else(|| {
+                    BoxCheckerError::InvalidInput(format!(
+                        "unknown timezone: '{}'",
+                        self.tz.as_deref().unwrap_or_default()
+                    ))
+                })?;
+                parse_local_time(&tz, local_time).ok_or_else(|| {
+                    BoxCheckerError::InvalidInput(format!("invalid local time: '{}'", local_time))
+                })
+            }
+            (None, None) => Ok(now_millis),
+        }
+    }
+}
+
+/// A note to attach to an activity, with optional named fields, e.g.
+/// {"tempo": "80"}.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct NoteEntry {
+    pub activity: ActivityId,
+    pub text: String,
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
+}
+
+/// A new name for an action, e.g. {"name": "scales"}.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct ActionRename {
+    pub name: String,
+}
+
+/// Replacement text for a note, e.g. {"text": "rushed"}.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct NoteEdit {
+    pub text: String,
+}
+
+/// A note attached to the activity logged at the given time.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Note {
+    pub id: AnnotationId,
+    pub activity: ActivityId,
+    pub text: String,
+    pub fields: BTreeMap<String, String>,
+}
+
+/// An activity with its duration, if timed and stopped, and its notes.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct JournalEntry {
+    pub activity: ActivityId,
+    pub action: ActionId,
+    pub duration: Option<i64>,
+    pub notes: Vec<Note>,
+}
+
+/// An action as exported, including archived ones.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ExportedAction {
+    pub id: ActionId,
+    pub name: String,
+    pub archived: bool,
+}
+
+/// A link from a parent action to one of its children.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ActionLink {
+    pub parent: ActionId,
+    pub child: ActionId,
 }
 
+/// Everything kept in a user's boxes, e.g. for them to take elsewhere.
+/// Activities are in time order, with their durations and notes.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct BoxExport {
+    pub actions: Vec<ExportedAction>,
+    pub hierarchy: Vec<ActionLink>,
+    pub activities: Vec<JournalEntry>,
+    pub goals: Vec<Goal>,
+}
+
+/// Ids recorded for a log entry.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct LoggedActivity {
+    pub activity: ActivityId,
+    pub note: Option<AnnotationId>,
+}
+
+/// Failures from the box traits, serialized for clients as
+/// {"error": "not_found", "msg": "..."}.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "error", content = "msg", rename_all = "snake_case")]
+pub enum BoxCheckerError {
+    /// The referenced action, activity, note or goal does not exist.
+    NotFound(String),
+    /// The change contradicts what is already stored, e.g. a hierarchy cycle.
+    Conflict(String),
+    /// The underlying store failed, e.g. the database is locked.
+    Storage(String),
+    /// The request itself is malformed, e.g. a negative duration.
+    InvalidInput(String),
+}
+
+impl fmt::Display for BoxCheckerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BoxCheckerError::NotFound(msg) => write!(f, "not found: {}", msg),
+            BoxCheckerError::Conflict(msg) => write!(f, "conflict: {}", msg),
+            BoxCheckerError::Storage(msg) => write!(f, "storage: {}", msg),
+            BoxCheckerError::InvalidInput(msg) => write!(f, "invalid input: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for BoxCheckerError {}
+
 pub trait BoxMaker {
-    fn create_action(&mut self, action_name: &str) -> ActionId;
-    fn make_action_parent_of(&mut self, parent: ActionId, child: ActionId);
+    /// Hide the action from searches and refuse new activities for it, or
+    /// restore it.
+    fn archive_action(&mut self, action: ActionId, archived: bool) -> Result<(), BoxCheckerError>;
+    fn create_action(&mut self, action_name: &str) -> Result<ActionId, BoxCheckerError>;
+    fn make_action_parent_of(
+        &mut self,
+        parent: ActionId,
+        child: ActionId,
+    ) -> Result<(), BoxCheckerError>;
+    fn rename_action(&mut self, action: ActionId, action_name: &str)
+        -> Result<(), BoxCheckerError>;
 }
 
 pub trait BoxChecker {
-    fn annotate_activity(&mut self, activity: ActivityId, text: &str) -> AnnotationId;
-    fn log_activities(&mut self, actions: &Vec<ActionId>) -> ActivityId;
-    fn log_activity(&mut self, action: ActionId) -> ActivityId;
-    fn log_activity_at_time(&mut self, action: ActionId, epoch_millis: i64) -> ActivityId;
+    fn annotate_activity(
+        &mut self,
+        activity: ActivityId,
+        text: &str,
+    ) -> Result<AnnotationId, BoxCheckerError>;
+    fn annotate_activity_with_fields(
+        &mut self,
+        activity: ActivityId,
+        text: &str,
+        fields: &BTreeMap<String, String>,
+    ) -> Result<AnnotationId, BoxCheckerError>;
+    fn delete_annotation(
+        &mut self,
+        activity: ActivityId,
+        annotation: AnnotationId,
+    ) -> Result<(), BoxCheckerError>;
+    /// Replace the text of a note, returning the id of the replacement note.
+    fn edit_annotation(
+        &mut self,
+        activity: ActivityId,
+        annotation: AnnotationId,
+        text: &str,
+    ) -> Result<AnnotationId, BoxCheckerError>;
+
+    fn delete_activity(
+        &mut self,
+        activity: ActivityId,
+        action: ActionId,
+    ) -> Result<(), BoxCheckerError>;
+    fn log_activities(&mut self, actions: &Vec<ActionId>) -> Result<ActivityId, BoxCheckerError>;
+    fn log_activity(&mut self, action: ActionId) -> Result<ActivityId, BoxCheckerError>;
+    /// Log the activity, with its duration and note if given, validating the
+    /// entry before recording anything.
+    fn log_entry(&mut self, entry: &LogEntry) -> Result<LoggedActivity, BoxCheckerError>;
+    /// Log all of the entries, or none of them on failure.
+    fn log_entries(&mut self, entries: &[LogEntry])
+        -> Result<Vec<LoggedActivity>, BoxCheckerError>;
+    fn log_activity_at_time(
+        &mut self,
+        action: ActionId,
+        epoch_millis: i64,
+    ) -> Result<ActivityId, BoxCheckerError>;
+    fn log_activity_with_duration(
+        &mut self,
+        action: ActionId,
+        epoch_millis: i64,
+        duration_millis: i64,
+    ) -> Result<ActivityId, BoxCheckerError>;
+
+    fn start_activity(&mut self, action: ActionId) -> Result<ActivityId, BoxCheckerError>;
+    fn start_activity_at_time(
+        &mut self,
+        action: ActionId,
+        epoch_millis: i64,
+    ) -> Result<ActivityId, BoxCheckerError>;
+    /// Stop the action's open activity, as several actions may be started
+    /// at the same time.
+    fn stop_activity(
+        &mut self,
+        activity: ActivityId,
+        action: ActionId,
+    ) -> Result<i64, BoxCheckerError>;
+    fn stop_activity_at_time(
+        &mut self,
+        activity: ActivityId,
+        action: ActionId,
+        epoch_millis: i64,
+    ) -> Result<i64, BoxCheckerError>;
+    /// Move an activity of the action to another time and/or action,
+    /// returning its new id.
+    fn update_activity(
+        &mut self,
+        activity: ActivityId,
+        action: ActionId,
+        epoch_millis: i64,
+        new_action: ActionId,
+    ) -> Result<ActivityId, BoxCheckerError>;
+}
+
+pub trait GoalKeeper {
+    fn clear_goal(&mut self, action: ActionId) -> Result<(), BoxCheckerError>;
+    fn set_goal(
+        &mut self,
+        action: ActionId,
+        target: i64,
+        unit: GoalUnit,
+    ) -> Result<(), BoxCheckerError>;
+
+    fn get_goals(&self, dest: &mut Vec<Goal>) -> Result<usize, BoxCheckerError>;
+    fn get_goal_progress(
+        &self,
+        from: usize,
+        to: usize,
+        dest: &mut Vec<GoalProgress>,
+    ) -> Result<usize, BoxCheckerError>;
 }
 
 pub trait BoxSearcher<'a> {
-    fn get_action_name(&self, action: ActionId) -> String;
+    fn get_action_ancestors(
+        &self,
+        action: ActionId,
+        dest: &mut Vec<ActionId>,
+    ) -> Result<usize, BoxCheckerError>;
+    fn get_action_children(
+        &self,
+        action: ActionId,
+        last_idx: ActionId,
+        dest: &mut Vec<ActionId>,
+    ) -> Result<usize, BoxCheckerError>;
+    fn get_action_name(&self, action: ActionId) -> Result<String, BoxCheckerError>;
+    fn get_action_subtree(
+        &self,
+        action: ActionId,
+        dest: &mut Vec<ActionId>,
+    ) -> Result<usize, BoxCheckerError>;
+    fn get_archived_actions(&self, dest: &mut Vec<ActionId>) -> Result<usize, BoxCheckerError>;
+
+    fn get_open_activities(
+        &self,
+        dest: &mut Vec<(ActivityId, ActionId)>,
+    ) -> Result<usize, BoxCheckerError>;
 
     fn get_notations(
         &self,
         activity: ActivityId,
         last_idx: AnnotationId,
         dest: &mut Vec<AnnotationId>,
-    ) -> usize;
-    fn get_note(&self, annotation: AnnotationId) -> String;
+    ) -> Result<usize, BoxCheckerError>;
+    fn get_note(&self, annotation: AnnotationId) -> Result<String, BoxCheckerError>;
+    fn get_notes(
+        &self,
+        activity: ActivityId,
+        last_idx: AnnotationId,
+        dest: &mut Vec<Note>,
+    ) -> Result<usize, BoxCheckerError>;
+    fn get_note_fields(
+        &self,
+        activity: ActivityId,
+        annotation: AnnotationId,
+        dest: &mut Vec<(String, String)>,
+    ) -> Result<usize, BoxCheckerError>;
     fn get_note_bulk(
         &self,
         ids: &Vec<AnnotationId>,
         dest: &mut Vec<(AnnotationId, String)>,
-    ) -> usize;
+    ) -> Result<usize, BoxCheckerError>;
 
     fn search_action_names(
         &self,
         substring: &str,
         last_idx: ActionId,
         dest: &mut Vec<(ActionId, String)>,
-    ) -> usize;
+    ) -> Result<usize, BoxCheckerError>;
+
+    fn get_rollup_by_time(
+        &self,
+        from: usize,
+        to: usize,
+        dest: &mut Vec<Rollup>,
+    ) -> Result<usize, BoxCheckerError>;
+    fn get_streaks(
+        &self,
+        tz: &Tz,
+        now_millis: i64,
+        dest: &mut Vec<Streak>,
+    ) -> Result<usize, BoxCheckerError>;
+
+    fn get_journal(
+        &self,
+        from: usize,
+        to: usize,
+        dest: &mut Vec<JournalEntry>,
+    ) -> Result<usize, BoxCheckerError>;
 
     fn search_activity_by_time(
         &self,
         from: usize,
         to: usize,
         dest: &mut Vec<(ActivityId, ActionId)>,
-    ) -> usize;
+    ) -> Result<usize, BoxCheckerError>;
+
+    fn search_durations_by_time(
+        &self,
+        from: usize,
+        to: usize,
+        dest: &mut Vec<(ActivityId, ActionId, i64)>,
+    ) -> Result<usize, BoxCheckerError>;
+
+    fn export(&self) -> Result<BoxExport, BoxCheckerError>;
 
     // activity search criteria
     // - min/max time
@@ -0,0 +1,77 @@
+use chrono::{Duration, NaiveDate, NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+
+/// Parse an IANA timezone name, e.g. "America/Chicago", defaulting to UTC
+/// when no name is given.
+pub fn parse_tz(name: Option<&str>) -> Option<Tz> {
+    match name {
+        Some(name) => name.parse::<Tz>().ok(),
+        None => Some(Tz::UTC),
+    }
+}
+
+/// The first and last epoch milliseconds of the years 1 through 9999, the
+/// times okra accepts, well within what chrono can represent in any
+/// timezone.
+pub const EARLIEST_MILLIS: i64 = -62_135_596_800_000;
+pub const LATEST_MILLIS: i64 = 253_402_300_799_999;
+
+/// Whether the epoch milliseconds fall within the years okra accepts.
+pub fn in_range(epoch_millis: i64) -> bool {
+    (EARLIEST_MILLIS..=LATEST_MILLIS).contains(&epoch_millis)
+}
+
+/// Find the calendar date in the timezone at the given epoch milliseconds,
+/// or None for times out of range.
+pub fn local_date(tz: &Tz, epoch_millis: i64) -> Option<NaiveDate> {
+    if !in_range(epoch_millis) {
+        return None;
+    }
+    tz.timestamp_millis_opt(epoch_millis)
+        .single()
+        .map(|t| t.naive_local().date())
+}
+
+/// Find the epoch milliseconds of a local date and time in the timezone,
+/// e.g. "2021-10-01T07:30" or "2021-10-01 07:30:15".
+/// Ambiguous times, repeated when clocks fall back, resolve to the earlier
+/// instant; times skipped when clocks spring forward are rejected.
+pub fn parse_local_time(tz: &Tz, local_time: &str) -> Option<i64> {
+    let local = LOCAL_TIME_FORMATS
+        .iter()
+        .find_map(|fmt| NaiveDateTime::parse_from_str(local_time, fmt).ok())?;
+    tz.from_local_datetime(&local)
+        .earliest()
+        .map(|t| t.timestamp_millis())
+}
+
+const LOCAL_TIME_FORMATS: [&str; 4] = [
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%d %H:%M",
+];
+
+/// Find the epoch milliseconds starting the date in the timezone.
+/// Where midnight is skipped by a daylight-saving transition, the day starts
+/// at the first valid local time afterwards.
+fn day_start(tz: &Tz, date: NaiveDate) -> i64 {
+    let mut local = date.and_hms_opt(0, 0, 0).unwrap();
+    loop {
+        if let Some(start) = tz.from_local_datetime(&local).earliest() {
+            return start.timestamp_millis();
+        }
+        local += Duration::minutes(30);
+    }
+}
+
+/// Find the first and last epoch milliseconds, inclusive, of the date in the
+/// timezone.
+pub fn day_bounds(tz: &Tz, date: NaiveDate) -> (i64, i64) {
+    let next = date.succ_opt().unwrap();
+    (day_start(tz, date), day_start(tz, next) - 1)
+}
+
+#[cfg(test)]
+#[path = "./calendar_test.rs"]
+mod calendar_test;
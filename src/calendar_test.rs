@@ -0,0 +1,81 @@
+use super::*;
+
+#[test]
+fn defaults_to_utc() {
+    assert_eq!(parse_tz(None), Some(Tz::UTC));
+    assert_eq!(
+        parse_tz(Some("America/Chicago")),
+        Some(Tz::America__Chicago)
+    );
+    assert_eq!(parse_tz(Some("Mars/Olympus_Mons")), None);
+}
+
+#[test]
+fn finds_local_date() {
+    // 2021-10-01T03:00:00Z is still September 30 in Chicago.
+    let millis = 1633057200000;
+    assert_eq!(
+        local_date(&Tz::UTC, millis),
+        NaiveDate::from_ymd_opt(2021, 10, 1)
+    );
+    assert_eq!(
+        local_date(&Tz::America__Chicago, millis),
+        NaiveDate::from_ymd_opt(2021, 9, 30)
+    );
+}
+
+#[test]
+fn rejects_times_out_of_range() {
+    assert_eq!(
+        local_date(&Tz::Pacific__Kiritimati, LATEST_MILLIS),
+        NaiveDate::from_ymd_opt(10000, 1, 1)
+    );
+    assert_eq!(
+        local_date(&Tz::UTC, EARLIEST_MILLIS),
+        NaiveDate::from_ymd_opt(1, 1, 1)
+    );
+    assert_eq!(local_date(&Tz::UTC, LATEST_MILLIS + 1), None);
+    assert_eq!(local_date(&Tz::UTC, EARLIEST_MILLIS - 1), None);
+    assert_eq!(local_date(&Tz::UTC, i64::MAX), None);
+    assert!(!in_range(i64::MIN));
+}
+
+#[test]
+fn bounds_daylight_saving_days() {
+    let date = NaiveDate::from_ymd_opt(2021, 3, 14).unwrap();
+    let (start, end) = day_bounds(&Tz::America__Chicago, date);
+    assert_eq!(end - start + 1, 23 * 60 * 60 * 1000);
+    assert_eq!(local_date(&Tz::America__Chicago, start), Some(date));
+    assert_eq!(local_date(&Tz::America__Chicago, end), Some(date));
+}
+
+#[test]
+fn bounds_days_skipping_midnight() {
+    // Sao Paulo skipped from midnight to 1am on 2018-11-04.
+    let date = NaiveDate::from_ymd_opt(2018, 11, 4).unwrap();
+    let (start, _) = day_bounds(&Tz::America__Sao_Paulo, date);
+    assert_eq!(local_date(&Tz::America__Sao_Paulo, start), Some(date));
+    assert_eq!(
+        local_date(&Tz::America__Sao_Paulo, start - 1),
+        date.pred_opt()
+    );
+}
+
+#[test]
+fn parses_local_times() {
+    // 07:30 in Chicago is 12:30 UTC during daylight saving time.
+    assert_eq!(
+        parse_local_time(&Tz::America__Chicago, "2021-10-01T07:30"),
+        Some(1633091400000)
+    );
+    assert_eq!(
+        parse_local_time(&Tz::America__Chicago, "2021-10-01 07:30:00"),
+        Some(1633091400000)
+    );
+    assert_eq!(parse_local_time(&Tz::UTC, "yesterday"), None);
+    // 02:30 did not happen in Chicago on 2021-03-14.
+    assert_eq!(
+        parse_local_time(&Tz::America__Chicago, "2021-03-14T02:30"),
+        None
+    );
+}
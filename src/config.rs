@@ -0,0 +1,259 @@
+use crate::auth::{LoginLimits, SessionTimeouts};
+use crate::passwords::{HashAlgorithm, PasswordPolicy};
+use rocket::figment::Figment;
+use rocket::serde::Deserialize;
+use std::time::Duration;
+
+/// Who may sign up through POST /users/register.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RegistrationPolicy {
+    Open,
+    Invite,
+    Disabled,
+}
+
+/// Where users and passwords come from: the SQLite users database, an
+/// htpasswd file, a header set by an authenticating reverse proxy, or an
+/// OpenID Connect identity provider.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthBackendKind {
+    Sqlite,
+    Htpasswd,
+    Proxy,
+    Oidc,
+}
+
+/// Okra settings read alongside Rocket's own, e.g. from Rocket.toml or
+/// ROCKET_-prefixed environment variables.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct OkraConfig {
+    /// Keep serving GET /activity/notate/<activity_id>/<notes> for older
+    /// clients, despite note text leaking into URLs.
+    #[serde(default)]
+    pub legacy_notate_route: bool,
+    /// Seconds a session survives without being refreshed.
+    #[serde(default = "default_session_idle_secs")]
+    pub session_idle_secs: u64,
+    /// Seconds a session survives after login, however often it is refreshed.
+    #[serde(default = "default_session_absolute_secs")]
+    pub session_absolute_secs: u64,
+    /// Seconds to delay logins after the first failure, doubling with each
+    /// further failure.
+    #[serde(default = "default_login_backoff_secs")]
+    pub login_backoff_secs: u64,
+    /// Failed logins for a username or address before locking it out.
+    #[serde(default = "default_login_max_failures")]
+    pub login_max_failures: u32,
+    /// Seconds a lockout lasts, also how long failures are remembered.
+    #[serde(default = "default_login_lockout_secs")]
+    pub login_lockout_secs: u64,
+    /// Whether anyone, only holders of an invite code, or no one may sign up.
+    #[serde(default = "default_registration")]
+    pub registration: RegistrationPolicy,
+    /// Single-use codes accepted under the invite registration policy.
+    #[serde(default)]
+    pub invite_codes: Vec<String>,
+    /// Shortest password accepted when registering.
+    #[serde(default = "default_min_password_length")]
+    pub min_password_length: usize,
+    /// How new passwords are hashed. Passwords hashed otherwise are
+    /// re-hashed at their users' next login.
+    #[serde(default = "default_password_hash")]
+    pub password_hash: HashAlgorithm,
+    #[serde(default = "default_bcrypt_cost")]
+    pub bcrypt_cost: u32,
+    #[serde(default = "default_argon2_memory_kib")]
+    pub argon2_memory_kib: u32,
+    #[serde(default = "default_argon2_iterations")]
+    pub argon2_iterations: u32,
+    #[serde(default = "default_argon2_parallelism")]
+    pub argon2_parallelism: u32,
+    /// Issuer shown alongside the username in authenticator apps.
+    #[serde(default = "default_totp_issuer")]
+    pub totp_issuer: String,
+    /// Where users and passwords come from.
+    #[serde(default = "default_auth_backend")]
+    pub auth_backend: AuthBackendKind,
+    /// The file of users and bcrypt hashes for the htpasswd backend.
+    #[serde(default)]
+    pub htpasswd_file: Option<String>,
+    /// The header naming the user for the proxy backend.
+    #[serde(default = "default_proxy_user_header")]
+    pub proxy_user_header: String,
+    /// Addresses of proxies allowed to name users for the proxy backend,
+    /// and to pass on client addresses in X-Real-IP.
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+    /// The OpenID Connect provider's issuer URL for the oidc backend.
+    #[serde(default)]
+    pub oidc_issuer: Option<String>,
+    /// Okra's client id registered with the OpenID Connect provider.
+    #[serde(default)]
+    pub oidc_client_id: Option<String>,
+    /// Okra's client secret, if the provider issued one.
+    #[serde(default)]
+    pub oidc_client_secret: Option<String>,
+    /// Where the provider sends users back to, i.e. okra's
+    /// /users/oidc/callback route.
+    #[serde(default)]
+    pub oidc_redirect_uri: Option<String>,
+    /// The ID token claim naming new users.
+    #[serde(default = "default_oidc_username_claim")]
+    pub oidc_username_claim: String,
+    /// Link provider users to existing okra users with the same name,
+    /// rather than refusing them.
+    #[serde(default)]
+    pub oidc_link_existing_users: bool,
+    /// Directory holding the users database and each user's boxes.
+    #[serde(default = "default_data_dir")]
+    pub data_dir: String,
+    /// Seconds between a user asking to delete their account and its purge,
+    /// during which they may log in and cancel. 0 deletes straight away.
+    /// Due accounts are purged at startup or by the purge-accounts binary.
+    #[serde(default)]
+    pub account_deletion_grace_secs: u64,
+    /// Users given the admin role at startup, e.g. to bootstrap the first
+    /// admin.
+    #[serde(default)]
+    pub admins: Vec<String>,
+}
+
+fn default_login_backoff_secs() -> u64 {
+    LoginLimits::default().backoff.as_secs()
+}
+
+fn default_login_max_failures() -> u32 {
+    LoginLimits::default().max_failures
+}
+
+fn default_login_lockout_secs() -> u64 {
+    LoginLimits::default().lockout.as_secs()
+}
+
+fn default_auth_backend() -> AuthBackendKind {
+    AuthBackendKind::Sqlite
+}
+
+fn default_proxy_user_header() -> String {
+    "X-Remote-User".to_string()
+}
+
+fn default_oidc_username_claim() -> String {
+    "preferred_username".to_string()
+}
+
+fn default_totp_issuer() -> String {
+    "Okra".to_string()
+}
+
+fn default_data_dir() -> String {
+    "data".to_string()
+}
+
+fn default_registration() -> RegistrationPolicy {
+    RegistrationPolicy::Disabled
+}
+
+fn default_min_password_length() -> usize {
+    10
+}
+
+fn default_password_hash() -> HashAlgorithm {
+    PasswordPolicy::default().algorithm
+}
+
+fn default_bcrypt_cost() -> u32 {
+    PasswordPolicy::default().bcrypt_cost
+}
+
+fn default_argon2_memory_kib() -> u32 {
+    PasswordPolicy::default().argon2_memory_kib
+}
+
+fn default_argon2_iterations() -> u32 {
+    PasswordPolicy::default().argon2_iterations
+}
+
+fn default_argon2_parallelism() -> u32 {
+    PasswordPolicy::default().argon2_parallelism
+}
+
+fn default_session_idle_secs() -> u64 {
+    SessionTimeouts::default().idle.as_secs()
+}
+
+fn default_session_absolute_secs() -> u64 {
+    SessionTimeouts::default().absolute.as_secs()
+}
+
+impl Default for OkraConfig {
+    fn default() -> Self {
+        OkraConfig {
+            legacy_notate_route: false,
+            session_idle_secs: default_session_idle_secs(),
+            session_absolute_secs: default_session_absolute_secs(),
+            login_backoff_secs: default_login_backoff_secs(),
+            login_max_failures: default_login_max_failures(),
+            login_lockout_secs: default_login_lockout_secs(),
+            registration: default_registration(),
+            invite_codes: Vec::new(),
+            min_password_length: default_min_password_length(),
+            password_hash: default_password_hash(),
+            bcrypt_cost: default_bcrypt_cost(),
+            argon2_memory_kib: default_argon2_memory_kib(),
+            argon2_iterations: default_argon2_iterations(),
+            argon2_parallelism: default_argon2_parallelism(),
+            totp_issuer: default_totp_issuer(),
+            auth_backend: default_auth_backend(),
+            htpasswd_file: None,
+            proxy_user_header: default_proxy_user_header(),
+            trusted_proxies: Vec::new(),
+            oidc_issuer: None,
+            oidc_client_id: None,
+            oidc_client_secret: None,
+            oidc_redirect_uri: None,
+            oidc_username_claim: default_oidc_username_claim(),
+            oidc_link_existing_users: false,
+            data_dir: default_data_dir(),
+            account_deletion_grace_secs: 0,
+            admins: Vec::new(),
+        }
+    }
+}
+
+impl OkraConfig {
+    pub fn session_timeouts(&self) -> SessionTimeouts {
+        SessionTimeouts {
+            idle: Duration::from_secs(self.session_idle_secs),
+            absolute: Duration::from_secs(self.session_absolute_secs),
+        }
+    }
+
+    pub fn login_limits(&self) -> LoginLimits {
+        LoginLimits {
+            backoff: Duration::from_secs(self.login_backoff_secs),
+            max_failures: self.login_max_failures,
+            lockout: Duration::from_secs(self.login_lockout_secs),
+        }
+    }
+
+    pub fn password_policy(&self) -> PasswordPolicy {
+        PasswordPolicy {
+            algorithm: self.password_hash,
+            bcrypt_cost: self.bcrypt_cost,
+            argon2_memory_kib: self.argon2_memory_kib,
+            argon2_iterations: self.argon2_iterations,
+            argon2_parallelism: self.argon2_parallelism,
+        }
+    }
+
+    pub fn from_figment(figment: &Figment) -> Result<Self, String> {
+        figment.extract::<OkraConfig>().map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+#[path = "./config_test.rs"]
+mod config_test;
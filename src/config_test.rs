@@ -0,0 +1,46 @@
+use super::*;
+
+#[test]
+fn defaults_settings() {
+    let config = OkraConfig::from_figment(&rocket::Config::figment()).unwrap();
+    assert_eq!(config, OkraConfig::default());
+}
+
+#[test]
+fn reads_settings() {
+    let figment = rocket::Config::figment().merge(("legacy_notate_route", true));
+    let config = OkraConfig::from_figment(&figment).unwrap();
+    assert!(config.legacy_notate_route);
+}
+
+#[test]
+fn reads_registration_policy() {
+    let figment = rocket::Config::figment()
+        .merge(("registration", "invite"))
+        .merge(("invite_codes", vec!["okra-42"]));
+    let config = OkraConfig::from_figment(&figment).unwrap();
+    assert_eq!(config.registration, RegistrationPolicy::Invite);
+    assert_eq!(config.invite_codes, vec!["okra-42".to_string()]);
+    assert_eq!(config.min_password_length, 10);
+    assert_eq!(
+        OkraConfig::default().registration,
+        RegistrationPolicy::Disabled
+    );
+}
+
+#[test]
+fn reads_password_policy() {
+    assert_eq!(
+        OkraConfig::default().password_policy(),
+        PasswordPolicy::default()
+    );
+    let figment = rocket::Config::figment()
+        .merge(("password_hash", "argon2id"))
+        .merge(("argon2_memory_kib", 65536));
+    let policy = OkraConfig::from_figment(&figment)
+        .unwrap()
+        .password_policy();
+    assert_eq!(policy.algorithm, HashAlgorithm::Argon2id);
+    assert_eq!(policy.argon2_memory_kib, 65536);
+    assert_eq!(policy.bcrypt_cost, 12);
+}
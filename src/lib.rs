@@ -1,4 +1,13 @@
 #![feature(duration_consts_2)]
+pub mod admin;
+pub mod audit;
 pub mod auth;
+pub mod auth_backends;
 pub mod boxchecker;
+pub mod calendar;
+pub mod config;
+pub mod oidc;
+pub mod passwords;
 pub mod sqlite_boxchecker;
+pub mod storage;
+pub mod totp;
@@ -4,43 +4,222 @@
 extern crate rocket;
 extern crate rocket_contrib;
 
-use okra::auth::{login, logout, AuthKey};
-use okra::boxchecker::{ActionId, ActivityId, BoxChecker, BoxSearcher};
+use chrono_tz::Tz;
+use okra::admin::{
+    create_user, end_user_sessions, get_storage_usage, list_users, remove_user, update_user,
+};
+use okra::auth::{
+    cancel_account_deletion, change_password, confirm_totp, create_token, delete_account,
+    disable_totp, enroll_totp, forbidden, get_login_failures, get_sessions, get_tokens, login,
+    login_totp, logout, refresh_session, register, reset_password, result_buffer, revoke_session,
+    revoke_sessions, revoke_token, unauthorized, Auth, AuthErrorKind, AuthKey, LogKey, ReadKey,
+};
+use okra::auth_backends::AuthState;
+use okra::boxchecker::{
+    ActionId, ActionRename, ActivityId, AnnotationId, BoxChecker, BoxCheckerError, BoxExport,
+    BoxMaker, BoxSearcher, Goal, GoalKeeper, GoalProgress, GoalSetting, JournalEntry, LogEntry,
+    LoggedActivity, Note, NoteEdit, NoteEntry, Rollup, Streak,
+};
+use okra::calendar::{day_bounds, local_date, parse_tz};
+use okra::config::{AuthBackendKind, OkraConfig};
+use okra::oidc::{oidc_callback, oidc_login, OidcProvider};
 use okra::sqlite_boxchecker::SqliteBoxes;
-use rocket::http::Method;
+use okra::storage::BoxPaths;
+use rocket::http::{Method, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder};
 use rocket::serde::json::Json;
+use rocket::serde::Serialize;
+use rocket::State;
 use rocket_cors::{AllowedHeaders, AllowedOrigins, CorsOptions};
 use std::convert::TryInto;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-static USER_BOX_PREFIX: &str = "data/user_";
+/// Respond to box failures with a JSON body and a status reflecting the
+/// kind of failure.
+#[derive(Debug)]
+struct BoxError(BoxCheckerError);
 
-fn get_boxer<'a>(auth: &'a AuthKey) -> SqliteBoxes<'a> {
-    SqliteBoxes::new(format!("{}{}.sqlite", USER_BOX_PREFIX, auth.0).as_str())
+impl From<BoxCheckerError> for BoxError {
+    fn from(e: BoxCheckerError) -> Self {
+        BoxError(e)
+    }
+}
+
+impl<'r> Responder<'r, 'static> for BoxError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let status = match &self.0 {
+            BoxCheckerError::NotFound(_) => Status::NotFound,
+            BoxCheckerError::Conflict(_) => Status::Conflict,
+            BoxCheckerError::Storage(_) => Status::InternalServerError,
+            BoxCheckerError::InvalidInput(_) => Status::BadRequest,
+        };
+        if status == Status::InternalServerError {
+            log::error!("{} {}: {}", request.method(), request.uri(), self.0);
+        }
+        (status, Json(self.0)).respond_to(request)
+    }
+}
+
+type BoxResult<T> = Result<T, BoxError>;
+
+/// Open the user's boxes, which are named by user id rather than username.
+fn get_boxer(
+    config: &OkraConfig,
+    auth_state: &AuthState,
+    username: &str,
+) -> BoxResult<SqliteBoxes<'static>> {
+    let user_id = auth_state
+        .users()
+        .get_user_id(username)
+        .map_err(|e| match e.kind {
+            AuthErrorKind::NotFound => BoxCheckerError::NotFound(e.msg),
+            _ => BoxCheckerError::Storage(e.msg),
+        })?;
+    let path = BoxPaths::new(&config.data_dir).resolve(&user_id)?;
+    Ok(SqliteBoxes::new(&path.to_string_lossy()))
+}
+
+fn get_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+fn get_tz(tz: Option<&str>) -> BoxResult<Tz> {
+    parse_tz(tz).ok_or_else(|| {
+        BoxError(BoxCheckerError::InvalidInput(format!(
+            "unknown timezone: '{}'",
+            tz.unwrap_or_default()
+        )))
+    })
+}
+
+#[get("/action/ancestors/<action_id>/<max_results>")]
+fn get_action_ancestors(
+    action_id: ActionId,
+    max_results: usize,
+    auth: ReadKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<Json<Vec<ActionId>>> {
+    let boxer = get_boxer(config, auth_state, &auth.0)?;
+    let mut dest = result_buffer(max_results);
+    let num_results = boxer.get_action_ancestors(action_id, &mut dest)?;
+    dest.truncate(num_results);
+    Ok(Json(dest))
+}
+
+/// Archive the action, hiding it from action searches.
+#[put("/action/archive/<action_id>")]
+fn archive_action(
+    action_id: ActionId,
+    auth: AuthKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<String> {
+    let mut boxer = get_boxer(config, auth_state, &auth.0)?;
+    boxer.archive_action(action_id, true)?;
+    Ok(action_id.to_string())
+}
+
+/// Restore an archived action.
+#[delete("/action/archive/<action_id>")]
+fn restore_action(
+    action_id: ActionId,
+    auth: AuthKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<String> {
+    let mut boxer = get_boxer(config, auth_state, &auth.0)?;
+    boxer.archive_action(action_id, false)?;
+    Ok(action_id.to_string())
+}
+
+#[get("/action/archived/<max_results>")]
+fn get_archived_actions(
+    max_results: usize,
+    auth: ReadKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<Json<Vec<ActionId>>> {
+    let boxer = get_boxer(config, auth_state, &auth.0)?;
+    let mut dest = result_buffer(max_results);
+    let num_results = boxer.get_archived_actions(&mut dest)?;
+    dest.truncate(num_results);
+    Ok(Json(dest))
+}
+
+/// Rename an action from a JSON body, e.g. {"name": "scales"}.
+#[put("/action/name/<action_id>", data = "<rename>")]
+fn rename_action(
+    action_id: ActionId,
+    rename: Json<ActionRename>,
+    auth: AuthKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<String> {
+    let mut boxer = get_boxer(config, auth_state, &auth.0)?;
+    boxer.rename_action(action_id, &rename.name)?;
+    Ok(action_id.to_string())
+}
+
+#[get("/action/children/<action_id>/<max_results>/<last_id>")]
+fn get_action_children(
+    action_id: ActionId,
+    max_results: usize,
+    last_id: ActionId,
+    auth: ReadKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<Json<Vec<ActionId>>> {
+    let boxer = get_boxer(config, auth_state, &auth.0)?;
+    let mut dest = result_buffer(max_results);
+    let num_results = boxer.get_action_children(action_id, last_id, &mut dest)?;
+    dest.truncate(num_results);
+    Ok(Json(dest))
+}
+
+#[get("/action/subtree/<action_id>/<max_results>")]
+fn get_action_subtree(
+    action_id: ActionId,
+    max_results: usize,
+    auth: ReadKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<Json<Vec<ActionId>>> {
+    let boxer = get_boxer(config, auth_state, &auth.0)?;
+    let mut dest = result_buffer(max_results);
+    let num_results = boxer.get_action_subtree(action_id, &mut dest)?;
+    dest.truncate(num_results);
+    Ok(Json(dest))
 }
 
 #[get("/action/get/<max_results>/<last_id>")]
 fn get_actions(
     max_results: usize,
     last_id: usize,
-    auth: AuthKey,
-) -> Option<Json<Vec<(ActionId, String)>>> {
-    // XXX limit or ossify/remove max_results
-    let boxer = get_boxer(&auth);
-    let mut dest = vec![(0, "".to_string()); max_results];
-    let num_results = boxer.search_action_names("%", last_id.try_into().unwrap(), &mut dest);
+    auth: ReadKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<Json<Vec<(ActionId, String)>>> {
+    let boxer = get_boxer(config, auth_state, &auth.0)?;
+    let mut dest = result_buffer(max_results);
+    let num_results = boxer.search_action_names("%", last_id.try_into().unwrap(), &mut dest)?;
     dest.truncate(num_results);
-    Some(Json(dest))
+    Ok(Json(dest))
 }
 
 #[get("/action/get_name/<action_id>")]
-fn get_action_name(action_id: ActionId, auth: AuthKey) -> Option<String> {
-    let boxer = get_boxer(&auth);
-    let name = boxer.get_action_name(action_id);
-    if name == "" {
-        None
-    } else {
-        Some(name)
-    }
+fn get_action_name(
+    action_id: ActionId,
+    auth: ReadKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<String> {
+    let boxer = get_boxer(config, auth_state, &auth.0)?;
+    Ok(boxer.get_action_name(action_id)?)
 }
 
 #[get("/activity/get/<start>/<end>/<max_results>")]
@@ -48,35 +227,405 @@ fn get_activities(
     start: usize,
     end: usize,
     max_results: usize,
+    auth: ReadKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<Json<Vec<(ActivityId, ActionId)>>> {
+    let boxer = get_boxer(config, auth_state, &auth.0)?;
+    let mut dest = result_buffer(max_results);
+    let num_results = boxer.search_activity_by_time(start, end, &mut dest)?;
+    dest.truncate(num_results);
+    Ok(Json(dest))
+}
+
+#[get("/activity/durations/<start>/<end>/<max_results>")]
+fn get_activity_durations(
+    start: usize,
+    end: usize,
+    max_results: usize,
+    auth: ReadKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<Json<Vec<(ActivityId, ActionId, i64)>>> {
+    let boxer = get_boxer(config, auth_state, &auth.0)?;
+    let mut dest = result_buffer(max_results);
+    let num_results = boxer.search_durations_by_time(start, end, &mut dest)?;
+    dest.truncate(num_results);
+    Ok(Json(dest))
+}
+
+#[get("/activity/open/<max_results>")]
+fn get_open_activities(
+    max_results: usize,
+    auth: ReadKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<Json<Vec<(ActivityId, ActionId)>>> {
+    let boxer = get_boxer(config, auth_state, &auth.0)?;
+    let mut dest = result_buffer(max_results);
+    let num_results = boxer.get_open_activities(&mut dest)?;
+    dest.truncate(num_results);
+    Ok(Json(dest))
+}
+
+#[delete("/goal/<action_id>")]
+fn clear_goal(
+    action_id: ActionId,
     auth: AuthKey,
-) -> Option<Json<Vec<(ActivityId, ActionId)>>> {
-    let boxer = get_boxer(&auth);
-    let mut dest = vec![(0, 0); max_results];
-    let num_results = boxer.search_activity_by_time(start, end, &mut dest);
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<String> {
+    let mut boxer = get_boxer(config, auth_state, &auth.0)?;
+    boxer.clear_goal(action_id)?;
+    Ok("OK".to_string())
+}
+
+#[get("/goal/get/<max_results>")]
+fn get_goals(
+    max_results: usize,
+    auth: ReadKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<Json<Vec<Goal>>> {
+    let boxer = get_boxer(config, auth_state, &auth.0)?;
+    let mut dest = result_buffer(max_results);
+    let num_results = boxer.get_goals(&mut dest)?;
     dest.truncate(num_results);
-    Some(Json(dest))
+    Ok(Json(dest))
+}
+
+#[get("/goal/progress/<start>/<end>/<max_results>")]
+fn get_goal_progress(
+    start: usize,
+    end: usize,
+    max_results: usize,
+    auth: ReadKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<Json<Vec<GoalProgress>>> {
+    let boxer = get_boxer(config, auth_state, &auth.0)?;
+    let mut dest = result_buffer(max_results);
+    let num_results = boxer.get_goal_progress(start, end, &mut dest)?;
+    dest.truncate(num_results);
+    Ok(Json(dest))
+}
+
+/// Report goal progress since midnight in the IANA timezone, defaulting to
+/// UTC.
+#[get("/goal/progress/today/<max_results>?<tz>")]
+fn get_goal_progress_today(
+    max_results: usize,
+    tz: Option<&str>,
+    auth: ReadKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<Json<Vec<GoalProgress>>> {
+    let tz = get_tz(tz)?;
+    let today = local_date(&tz, get_time()).ok_or_else(|| {
+        BoxError(BoxCheckerError::Storage(
+            "the clock is out of range".to_string(),
+        ))
+    })?;
+    let (start, end) = day_bounds(&tz, today);
+    get_goal_progress(
+        start as usize,
+        end as usize,
+        max_results,
+        auth,
+        config,
+        auth_state,
+    )
+}
+
+/// Set the action's daily goal from a JSON body, e.g.
+/// {"target": 30, "unit": "minutes"}.
+#[put("/goal/<action_id>", data = "<goal>")]
+fn set_goal(
+    action_id: ActionId,
+    goal: Json<GoalSetting>,
+    auth: AuthKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<String> {
+    let mut boxer = get_boxer(config, auth_state, &auth.0)?;
+    boxer.set_goal(action_id, goal.target, goal.unit)?;
+    Ok(action_id.to_string())
+}
+
+/// Report time spent on each action, including time spent on its
+/// descendants in the action hierarchy.
+#[get("/report/rollup/<start>/<end>/<max_results>")]
+fn get_rollup(
+    start: usize,
+    end: usize,
+    max_results: usize,
+    auth: ReadKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<Json<Vec<Rollup>>> {
+    let boxer = get_boxer(config, auth_state, &auth.0)?;
+    let mut dest = result_buffer(max_results);
+    let num_results = boxer.get_rollup_by_time(start, end, &mut dest)?;
+    dest.truncate(num_results);
+    Ok(Json(dest))
+}
+
+/// Report streaks with days ending at midnight in the IANA timezone,
+/// defaulting to UTC.
+#[get("/streak/get/<max_results>?<tz>")]
+fn get_streaks(
+    max_results: usize,
+    tz: Option<&str>,
+    auth: ReadKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<Json<Vec<Streak>>> {
+    let tz = get_tz(tz)?;
+    let boxer = get_boxer(config, auth_state, &auth.0)?;
+    let mut dest = result_buffer(max_results);
+    let num_results = boxer.get_streaks(&tz, get_time(), &mut dest)?;
+    dest.truncate(num_results);
+    Ok(Json(dest))
 }
 
 #[get("/activity/log/<action_id>")]
-fn log_activity(action_id: ActionId, auth: AuthKey) -> Option<String> {
-    let mut boxer = get_boxer(&auth);
-    let id = boxer.log_activity(action_id);
-    if id != 0 {
-        Some(id.to_string()) // Responder<i64> not implemented
-    } else {
-        None
-    }
+fn log_activity(
+    action_id: ActionId,
+    auth: LogKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<String> {
+    let mut boxer = get_boxer(config, auth_state, &auth.0)?;
+    let id = boxer.log_activity(action_id)?;
+    Ok(id.to_string()) // Responder<i64> not implemented
+}
+
+/// Log an activity described by a JSON body, e.g.
+/// {"action": 3, "local_time": "2021-10-01T07:30", "tz": "America/Chicago",
+/// "duration": 1800000, "note": "metronome at 80"}.
+#[post("/activity/log", data = "<entry>")]
+fn log_entry(
+    entry: Json<LogEntry>,
+    auth: LogKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<Json<LoggedActivity>> {
+    let mut boxer = get_boxer(config, auth_state, &auth.0)?;
+    Ok(Json(boxer.log_entry(&entry)?))
+}
+
+/// Log a JSON array of entries, as for log_entry, all or nothing.
+#[post("/activity/log/batch", data = "<entries>")]
+fn log_entries(
+    entries: Json<Vec<LogEntry>>,
+    auth: LogKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<Json<Vec<LoggedActivity>>> {
+    let mut boxer = get_boxer(config, auth_state, &auth.0)?;
+    Ok(Json(boxer.log_entries(&entries)?))
+}
+
+/// Log an activity that ended just now, having lasted the given number of
+/// milliseconds.
+#[get("/activity/log/<action_id>/<duration_millis>")]
+fn log_activity_with_duration(
+    action_id: ActionId,
+    duration_millis: i64,
+    auth: LogKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<String> {
+    let mut boxer = get_boxer(config, auth_state, &auth.0)?;
+    let start = get_time().checked_sub(duration_millis).ok_or_else(|| {
+        BoxError(BoxCheckerError::InvalidInput(format!(
+            "duration {} out of range",
+            duration_millis
+        )))
+    })?;
+    let id = boxer.log_activity_with_duration(action_id, start, duration_millis)?;
+    Ok(id.to_string())
+}
+
+#[post("/activity/start/<action_id>")]
+fn start_activity(
+    action_id: ActionId,
+    auth: LogKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<String> {
+    let mut boxer = get_boxer(config, auth_state, &auth.0)?;
+    let id = boxer.start_activity(action_id)?;
+    Ok(id.to_string())
+}
+
+/// Stop an open activity of the action, returning its duration in
+/// milliseconds.
+#[post("/activity/stop/<activity_id>/<action_id>")]
+fn stop_activity(
+    activity_id: ActivityId,
+    action_id: ActionId,
+    auth: LogKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<String> {
+    let mut boxer = get_boxer(config, auth_state, &auth.0)?;
+    let duration = boxer.stop_activity(activity_id, action_id)?;
+    Ok(duration.to_string())
+}
+
+/// Move an activity of the action to another time and/or action, returning
+/// its new id.
+#[put("/activity/<activity_id>/<action_id>?<time>&<new_action>")]
+fn update_activity(
+    activity_id: ActivityId,
+    action_id: ActionId,
+    time: Option<i64>,
+    new_action: Option<ActionId>,
+    auth: AuthKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<String> {
+    let mut boxer = get_boxer(config, auth_state, &auth.0)?;
+    let id = boxer.update_activity(
+        activity_id,
+        action_id,
+        time.unwrap_or(activity_id),
+        new_action.unwrap_or(action_id),
+    )?;
+    Ok(id.to_string())
+}
+
+#[delete("/activity/<activity_id>/<action_id>")]
+fn delete_activity(
+    activity_id: ActivityId,
+    action_id: ActionId,
+    auth: AuthKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<String> {
+    let mut boxer = get_boxer(config, auth_state, &auth.0)?;
+    boxer.delete_activity(activity_id, action_id)?;
+    Ok("OK".to_string())
+}
+
+/// Replace the text of a note, returning the id of the replacement note.
+/// The JSON body holds the new text, e.g. {"text": "rushed"}.
+#[put("/activity/notate/<activity_id>/<note_id>", data = "<edit>")]
+fn edit_annotation(
+    activity_id: ActivityId,
+    note_id: AnnotationId,
+    edit: Json<NoteEdit>,
+    auth: AuthKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<String> {
+    let mut boxer = get_boxer(config, auth_state, &auth.0)?;
+    let id = boxer.edit_annotation(activity_id, note_id, &edit.text)?;
+    Ok(id.to_string())
+}
+
+#[delete("/activity/notate/<activity_id>/<note_id>")]
+fn delete_annotation(
+    activity_id: ActivityId,
+    note_id: AnnotationId,
+    auth: AuthKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<String> {
+    let mut boxer = get_boxer(config, auth_state, &auth.0)?;
+    boxer.delete_annotation(activity_id, note_id)?;
+    Ok("OK".to_string())
+}
+
+#[get("/activity/notes/<activity_id>/<max_results>/<last_id>")]
+fn get_notes(
+    activity_id: ActivityId,
+    max_results: usize,
+    last_id: AnnotationId,
+    auth: ReadKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<Json<Vec<Note>>> {
+    let boxer = get_boxer(config, auth_state, &auth.0)?;
+    let mut dest = result_buffer(max_results);
+    let num_results = boxer.get_notes(activity_id, last_id, &mut dest)?;
+    dest.truncate(num_results);
+    Ok(Json(dest))
 }
 
+/// Report activities within the time range with their durations and notes.
+#[get("/journal/<start>/<end>/<max_results>")]
+fn get_journal(
+    start: usize,
+    end: usize,
+    max_results: usize,
+    auth: ReadKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<Json<Vec<JournalEntry>>> {
+    let boxer = get_boxer(config, auth_state, &auth.0)?;
+    let mut dest = result_buffer(max_results);
+    let num_results = boxer.get_journal(start, end, &mut dest)?;
+    dest.truncate(num_results);
+    Ok(Json(dest))
+}
+
+/// Everything okra keeps for a user, e.g. for them to take elsewhere.
+#[derive(Debug, Serialize)]
+struct UserExport {
+    username: String,
+    user_id: String,
+    exported: i64,
+    boxes: BoxExport,
+}
+
+/// Export the user's actions, hierarchy, activities, notes and goals.
+#[get("/users/export")]
+fn export_data(
+    auth: AuthKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<Json<UserExport>> {
+    let boxer = get_boxer(config, auth_state, &auth.0)?;
+    let user_id = auth_state
+        .users()
+        .get_user_id(&auth.0)
+        .map_err(|e| BoxCheckerError::Storage(e.msg))?;
+    Ok(Json(UserExport {
+        username: auth.0,
+        user_id,
+        exported: get_time(),
+        boxes: boxer.export()?,
+    }))
+}
+
+/// Attach a note described by a JSON body, e.g.
+/// {"activity": 1633091400000, "text": "rushed", "fields": {"tempo": "80"}}.
+#[post("/activity/notate", data = "<entry>")]
+fn post_note(
+    entry: Json<NoteEntry>,
+    auth: LogKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<Json<AnnotationId>> {
+    let mut boxer = get_boxer(config, auth_state, &auth.0)?;
+    let id = boxer.annotate_activity_with_fields(entry.activity, &entry.text, &entry.fields)?;
+    Ok(Json(id))
+}
+
+/// Deprecated in favor of post_note, only mounted with legacy_notate_route.
 #[get("/activity/notate/<activity_id>/<notes>")]
-fn notate_activity(activity_id: ActivityId, notes: &str, auth: AuthKey) -> Option<String> {
-    let mut boxer = get_boxer(&auth);
-    let id = boxer.annotate_activity(activity_id, notes);
-    if id != 0 {
-        Some(id.to_string()) // Responder<i64> not implemented
-    } else {
-        None
-    }
+fn notate_activity(
+    activity_id: ActivityId,
+    notes: &str,
+    auth: LogKey,
+    config: &State<OkraConfig>,
+    auth_state: &State<AuthState>,
+) -> BoxResult<String> {
+    let mut boxer = get_boxer(config, auth_state, &auth.0)?;
+    let id = boxer.annotate_activity(activity_id, notes)?;
+    Ok(id.to_string()) // Responder<i64> not implemented
 }
 
 #[launch]
@@ -87,7 +636,7 @@ fn rocket() -> _ {
     let allowed_origins = AllowedOrigins::all();
     let cors = CorsOptions {
         allowed_origins,
-        allowed_methods: vec![Method::Get, Method::Post]
+        allowed_methods: vec![Method::Get, Method::Post, Method::Put, Method::Delete]
             .into_iter()
             .map(From::from)
             .collect(),
@@ -98,13 +647,95 @@ fn rocket() -> _ {
     .to_cors()
     .unwrap();
 
-    rocket::build()
+    let rocket = rocket::build();
+    let config = OkraConfig::from_figment(rocket.figment()).unwrap();
+    let legacy_notate_route = config.legacy_notate_route;
+    let auth_state = AuthState::from_config(&config).unwrap();
+    let oidc_provider = match config.auth_backend {
+        AuthBackendKind::Oidc => Some(OidcProvider::from_config(&config).unwrap()),
+        _ => None,
+    };
+    let box_paths = BoxPaths::new(&config.data_dir);
+    box_paths.migrate(&*auth_state.users()).unwrap();
+    box_paths
+        .purge(&mut *auth_state.users(), get_time())
+        .unwrap();
+
+    let rocket = rocket
         .attach(cors)
+        .manage(auth_state)
+        .manage(config)
+        .register("/", catchers![forbidden, unauthorized])
+        .mount("/", routes![archive_action])
+        .mount("/", routes![cancel_account_deletion])
+        .mount("/", routes![change_password])
+        .mount("/", routes![clear_goal])
+        .mount("/", routes![confirm_totp])
+        .mount("/", routes![create_token])
+        .mount("/", routes![create_user])
+        .mount("/", routes![delete_account])
+        .mount("/", routes![delete_activity])
+        .mount("/", routes![delete_annotation])
+        .mount("/", routes![disable_totp])
+        .mount("/", routes![edit_annotation])
+        .mount("/", routes![end_user_sessions])
+        .mount("/", routes![enroll_totp])
+        .mount("/", routes![export_data])
+        .mount("/", routes![get_action_ancestors])
+        .mount("/", routes![get_action_children])
         .mount("/", routes![get_action_name])
+        .mount("/", routes![get_action_subtree])
         .mount("/", routes![get_actions])
         .mount("/", routes![get_activities])
+        .mount("/", routes![get_activity_durations])
+        .mount("/", routes![get_archived_actions])
+        .mount("/", routes![get_goal_progress])
+        .mount("/", routes![get_goal_progress_today])
+        .mount("/", routes![get_goals])
+        .mount("/", routes![get_journal])
+        .mount("/", routes![get_login_failures])
+        .mount("/", routes![get_notes])
+        .mount("/", routes![get_open_activities])
+        .mount("/", routes![get_rollup])
+        .mount("/", routes![get_sessions])
+        .mount("/", routes![get_storage_usage])
+        .mount("/", routes![get_streaks])
+        .mount("/", routes![get_tokens])
+        .mount("/", routes![list_users])
         .mount("/", routes![log_activity])
+        .mount("/", routes![log_activity_with_duration])
+        .mount("/", routes![log_entries])
+        .mount("/", routes![log_entry])
         .mount("/", routes![login])
+        .mount("/", routes![login_totp])
         .mount("/", routes![logout])
-        .mount("/", routes![notate_activity])
+        .mount("/", routes![post_note])
+        .mount("/", routes![refresh_session])
+        .mount("/", routes![register])
+        .mount("/", routes![remove_user])
+        .mount("/", routes![rename_action])
+        .mount("/", routes![reset_password])
+        .mount("/", routes![restore_action])
+        .mount("/", routes![revoke_session])
+        .mount("/", routes![revoke_sessions])
+        .mount("/", routes![revoke_token])
+        .mount("/", routes![set_goal])
+        .mount("/", routes![start_activity])
+        .mount("/", routes![stop_activity])
+        .mount("/", routes![update_activity])
+        .mount("/", routes![update_user]);
+
+    let rocket = if legacy_notate_route {
+        rocket.mount("/", routes![notate_activity])
+    } else {
+        rocket
+    };
+
+    match oidc_provider {
+        Some(provider) => rocket
+            .manage(provider)
+            .mount("/", routes![oidc_callback])
+            .mount("/", routes![oidc_login]),
+        None => rocket,
+    }
 }
@@ -0,0 +1,442 @@
+//! Logins through an OpenID Connect identity provider, using the
+//! authorization code flow with PKCE.
+//! Provider users get a local user, linked by issuer and subject, on their
+//! first login, and the same sessions as password logins.
+use crate::audit::{audit, AuditAction};
+use crate::auth::{
+    check_username, random_hex, session_cookie, Auth, AuthError, AuthErrorKind, ClientInfo,
+    LoginStep, SqliteAuth, OIDC_LOGIN_MILLIS,
+};
+use crate::auth_backends::AuthState;
+use crate::config::OkraConfig;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use rocket::http::{Cookie, CookieJar, SameSite};
+use rocket::response::Redirect;
+use rocket::serde::json::Json;
+use rocket::serde::Deserialize;
+use rocket::{get, State};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// The private cookie tying a login's state to the browser that started it.
+static STATE_COOKIE: &str = "oidc_state";
+static DISCOVERY_PATH: &str = "/.well-known/openid-configuration";
+static SCOPES: &str = "openid profile email";
+/// Random bytes in PKCE verifiers, hex encoded to 64 characters.
+static VERIFIER_BYTES: usize = 32;
+static STATE_BYTES: usize = 16;
+
+/// The provider endpoints okra uses, from its discovery document.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Discovery {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Jwk {
+    #[serde(default)]
+    kid: Option<String>,
+    kty: String,
+    #[serde(default)]
+    n: Option<String>,
+    #[serde(default)]
+    e: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// The claims of a verified ID token.
+pub type IdClaims = HashMap<String, Value>;
+
+/// Okra's registration with an OpenID Connect provider.
+pub struct OidcProvider {
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_uri: String,
+    username_claim: String,
+    link_existing_users: bool,
+    http: reqwest::Client,
+}
+
+fn provider_error(e: reqwest::Error) -> AuthError {
+    AuthError::new(
+        AuthErrorKind::Storage,
+        &format!("identity provider failed: {}", e),
+    )
+}
+
+fn invalid_token(e: jsonwebtoken::errors::Error) -> AuthError {
+    AuthError::new(
+        AuthErrorKind::Unauthorized,
+        &format!("invalid ID token: {}", e),
+    )
+}
+
+/// The S256 PKCE challenge sent in place of the verifier.
+pub fn code_challenge(verifier: &str) -> String {
+    base64::encode_config(Sha256::digest(verifier.as_bytes()), base64::URL_SAFE_NO_PAD)
+}
+
+fn string_claim<'a>(claims: &'a IdClaims, name: &str) -> Result<&'a str, AuthError> {
+    claims.get(name).and_then(Value::as_str).ok_or_else(|| {
+        AuthError::new(
+            AuthErrorKind::Unauthorized,
+            &format!("the ID token has no {} claim", name),
+        )
+    })
+}
+
+impl OidcProvider {
+    pub fn new(issuer: &str, client_id: &str, redirect_uri: &str) -> Self {
+        OidcProvider {
+            issuer: issuer.trim_end_matches('/').to_string(),
+            client_id: client_id.to_string(),
+            client_secret: None,
+            redirect_uri: redirect_uri.to_string(),
+            username_claim: "preferred_username".to_string(),
+            link_existing_users: false,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    pub fn with_client_secret(mut self, secret: &str) -> Self {
+        self.client_secret = Some(secret.to_string());
+        self
+    }
+
+    pub fn with_username_claim(mut self, claim: &str) -> Self {
+        self.username_claim = claim.to_string();
+        self
+    }
+
+    pub fn with_link_existing_users(mut self, link: bool) -> Self {
+        self.link_existing_users = link;
+        self
+    }
+
+    pub fn from_config(config: &OkraConfig) -> Result<Self, String> {
+        let setting = |value: &Option<String>, name: &str| {
+            value
+                .clone()
+                .ok_or_else(|| format!("the oidc backend needs {}", name))
+        };
+        let provider = OidcProvider::new(
+            &setting(&config.oidc_issuer, "oidc_issuer")?,
+            &setting(&config.oidc_client_id, "oidc_client_id")?,
+            &setting(&config.oidc_redirect_uri, "oidc_redirect_uri")?,
+        )
+        .with_username_claim(&config.oidc_username_claim)
+        .with_link_existing_users(config.oidc_link_existing_users);
+        Ok(match &config.oidc_client_secret {
+            Some(secret) => provider.with_client_secret(secret),
+            None => provider,
+        })
+    }
+
+    /// Fetch the provider's discovery document, checking it is for the
+    /// configured issuer.
+    pub async fn discover(&self) -> Result<Discovery, AuthError> {
+        let discovery: Discovery = self
+            .http
+            .get(format!("{}{}", self.issuer, DISCOVERY_PATH))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(provider_error)?
+            .json()
+            .await
+            .map_err(provider_error)?;
+        if discovery.issuer.trim_end_matches('/') != self.issuer {
+            return Err(AuthError::new(
+                AuthErrorKind::Storage,
+                &format!("discovery document is for issuer {}", discovery.issuer),
+            ));
+        }
+        Ok(discovery)
+    }
+
+    /// Where to send the user to log in with the provider.
+    pub fn authorization_url(
+        &self,
+        discovery: &Discovery,
+        state: &str,
+        verifier: &str,
+        nonce: &str,
+    ) -> Result<String, AuthError> {
+        let challenge = code_challenge(verifier);
+        reqwest::Url::parse_with_params(
+            &discovery.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.client_id),
+                ("redirect_uri", &self.redirect_uri),
+                ("scope", SCOPES),
+                ("state", state),
+                ("nonce", nonce),
+                ("code_challenge", &challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map(String::from)
+        .map_err(|e| {
+            AuthError::new(
+                AuthErrorKind::Storage,
+                &format!("invalid authorization endpoint: {}", e),
+            )
+        })
+    }
+
+    /// Trade the authorization code and PKCE verifier for an ID token.
+    async fn redeem_code(
+        &self,
+        discovery: &Discovery,
+        code: &str,
+        verifier: &str,
+    ) -> Result<String, AuthError> {
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.redirect_uri),
+            ("client_id", &self.client_id),
+            ("code_verifier", verifier),
+        ];
+        if let Some(secret) = &self.client_secret {
+            form.push(("client_secret", secret));
+        }
+        let response: TokenResponse = self
+            .http
+            .post(&discovery.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(provider_error)?
+            .json()
+            .await
+            .map_err(provider_error)?;
+        Ok(response.id_token)
+    }
+
+    /// Check the ID token's signature against the provider's keys, and that
+    /// it was issued by the provider to okra for this login.
+    async fn verify_id_token(
+        &self,
+        discovery: &Discovery,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<IdClaims, AuthError> {
+        let header = decode_header(id_token).map_err(invalid_token)?;
+        let jwks: JwkSet = self
+            .http
+            .get(&discovery.jwks_uri)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(provider_error)?
+            .json()
+            .await
+            .map_err(provider_error)?;
+        let (n, e) = jwks
+            .keys
+            .iter()
+            .filter(|key| key.kty == "RSA" && (header.kid.is_none() || key.kid == header.kid))
+            .find_map(|key| Some((key.n.as_deref()?, key.e.as_deref()?)))
+            .ok_or_else(|| {
+                AuthError::new(
+                    AuthErrorKind::Unauthorized,
+                    "the ID token was signed with an unknown key",
+                )
+            })?;
+
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_audience(&[&self.client_id]);
+        validation.iss = Some(discovery.issuer.clone());
+        let claims = decode::<IdClaims>(
+            id_token,
+            &DecodingKey::from_rsa_components(n, e),
+            &validation,
+        )
+        .map_err(invalid_token)?
+        .claims;
+        if string_claim(&claims, "nonce")? != nonce {
+            return Err(AuthError::new(
+                AuthErrorKind::Unauthorized,
+                "the ID token is for another login",
+            ));
+        }
+        Ok(claims)
+    }
+
+    /// Find the local user linked to the provider user, otherwise adding
+    /// one named by the username claim.
+    /// Existing users with that name are only linked if configured to,
+    /// since the provider may let anyone pick the name.
+    pub fn local_user(
+        &self,
+        users: &mut SqliteAuth,
+        claims: &IdClaims,
+    ) -> Result<String, AuthError> {
+        let issuer = string_claim(claims, "iss")?;
+        let subject = string_claim(claims, "sub")?;
+        if let Some(username) = users.get_oidc_user(issuer, subject)? {
+            return Ok(username);
+        }
+
+        let username = string_claim(claims, &self.username_claim)?;
+        check_username(username)?;
+        match users.get_user(username) {
+            Ok(_) if !self.link_existing_users => {
+                return Err(AuthError::new(
+                    AuthErrorKind::Conflict,
+                    &format!("{} already exists", username),
+                ))
+            }
+            Err(e) if e.kind != AuthErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        users.ensure_user(username)?;
+        users.link_oidc_user(issuer, subject, username)?;
+        Ok(username.to_string())
+    }
+
+    /// Begin a login, returning its state, to keep in the browser, and the
+    /// provider URL to send the user to.
+    pub async fn start_login(&self, auth_state: &AuthState) -> Result<(String, String), AuthError> {
+        let discovery = self.discover().await?;
+        let state = random_hex(STATE_BYTES);
+        let verifier = random_hex(VERIFIER_BYTES);
+        let nonce = random_hex(STATE_BYTES);
+        auth_state
+            .users()
+            .create_oidc_login(&state, &verifier, &nonce)?;
+        let url = self.authorization_url(&discovery, &state, &verifier, &nonce)?;
+        Ok((state, url))
+    }
+
+    /// Complete the login the provider returned with the code and state,
+    /// returning the local username.
+    /// The state must match the one kept by the browser that started the
+    /// login, so a login can't be slipped into another's browser.
+    pub async fn finish_login(
+        &self,
+        auth_state: &AuthState,
+        code: &str,
+        state: &str,
+        browser_state: Option<&str>,
+    ) -> Result<String, AuthError> {
+        check_browser_state(state, browser_state)?;
+        let (verifier, nonce) = auth_state.users().take_oidc_login(state)?;
+        let discovery = self.discover().await?;
+        let id_token = self.redeem_code(&discovery, code, &verifier).await?;
+        let claims = self.verify_id_token(&discovery, &id_token, &nonce).await?;
+
+        let mut users = auth_state.users();
+        let username = self.local_user(&mut users, &claims)?;
+        AuthState::check_enabled(&users, &username)?;
+        Ok(username)
+    }
+}
+
+fn check_browser_state(state: &str, browser_state: Option<&str>) -> Result<(), AuthError> {
+    if browser_state == Some(state) {
+        Ok(())
+    } else {
+        Err(AuthError::new(
+            AuthErrorKind::Unauthorized,
+            "the login was not started in this browser",
+        ))
+    }
+}
+
+/// Send the user to the identity provider to log in.
+#[get("/users/oidc/login")]
+pub async fn oidc_login(
+    provider: &State<OidcProvider>,
+    auth_state: &State<AuthState>,
+    cookies: &CookieJar<'_>,
+) -> Result<Redirect, AuthError> {
+    let (state, url) = provider.start_login(auth_state).await?;
+    // Lax, since the provider sends the browser back from another site.
+    cookies.add_private(
+        Cookie::build(STATE_COOKIE, state)
+            .max_age(time::Duration::milliseconds(OIDC_LOGIN_MILLIS))
+            .same_site(SameSite::Lax)
+            .finish(),
+    );
+    Ok(Redirect::to(url))
+}
+
+/// Where the identity provider sends users back to, starting a session
+/// like a password login, or, for users with TOTP enabled, responding with
+/// a challenge for POST /users/login/totp.
+#[get("/users/oidc/callback?<code>&<state>&<error>")]
+pub async fn oidc_callback(
+    code: Option<String>,
+    state: String,
+    error: Option<String>,
+    client: ClientInfo,
+    provider: &State<OidcProvider>,
+    auth_state: &State<AuthState>,
+    cookies: &CookieJar<'_>,
+) -> Result<Json<LoginStep>, AuthError> {
+    let browser_state = cookies
+        .get_private(STATE_COOKIE)
+        .map(|cookie| cookie.value().to_string());
+    cookies.remove_private(Cookie::named(STATE_COOKIE));
+    let browser_state = browser_state.as_deref();
+    let username = match (code, error) {
+        (Some(code), None) => {
+            provider
+                .finish_login(auth_state, &code, &state, browser_state)
+                .await
+        }
+        (_, error) => {
+            check_browser_state(&state, browser_state)?;
+            auth_state.users().take_oidc_login(&state)?;
+            Err(AuthError::new(
+                AuthErrorKind::Unauthorized,
+                &format!(
+                    "the identity provider refused the login: {}",
+                    error.unwrap_or_default()
+                ),
+            ))
+        }
+    };
+    audit(
+        AuditAction::LoginOidc,
+        username.as_deref().unwrap_or_default(),
+        &client,
+        &username,
+    );
+    let username = username?;
+
+    let mut auth = auth_state.users();
+    if auth.has_totp(&username)? {
+        let (totp_challenge, expires) = auth.create_login_challenge(&username)?;
+        return Ok(Json(LoginStep::Totp {
+            totp_challenge,
+            expires,
+        }));
+    }
+    let token = auth.create_session(&username, &client)?;
+    let key = auth.auth_cookie(&token)?;
+    cookies.add_private(session_cookie(&token, key.expires));
+    Ok(Json(LoginStep::Session(key)))
+}
+
+#[cfg(test)]
+#[path = "./oidc_test.rs"]
+mod oidc_test;
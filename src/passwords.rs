@@ -0,0 +1,132 @@
+//! Password hashing with bcrypt or argon2id, under a policy set in config.
+//! Hashes made under an older policy still verify, and are flagged for
+//! re-hashing so stored passwords move to the current policy as users log in.
+use crate::auth::{AuthError, AuthErrorKind};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use rocket::serde::Deserialize;
+use std::convert::TryFrom;
+use std::fmt;
+
+static ARGON2ID_PREFIX: &str = "$argon2id$";
+static BCRYPT_PREFIX: &str = "$2";
+
+/// How new passwords are hashed.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+    Bcrypt,
+    Argon2id,
+}
+
+/// The algorithm and cost for hashing passwords.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PasswordPolicy {
+    pub algorithm: HashAlgorithm,
+    /// The bcrypt cost, i.e. log2 of the rounds.
+    pub bcrypt_cost: u32,
+    pub argon2_memory_kib: u32,
+    pub argon2_iterations: u32,
+    pub argon2_parallelism: u32,
+}
+
+impl Default for PasswordPolicy {
+    /// bcrypt's default cost, with argon2id settings following OWASP's
+    /// recommendation should it be chosen.
+    fn default() -> Self {
+        PasswordPolicy {
+            algorithm: HashAlgorithm::Bcrypt,
+            bcrypt_cost: bcrypt::DEFAULT_COST,
+            argon2_memory_kib: 19 * 1024,
+            argon2_iterations: 2,
+            argon2_parallelism: 1,
+        }
+    }
+}
+
+fn hash_error<E: fmt::Display>(e: E) -> AuthError {
+    AuthError::new(
+        AuthErrorKind::Storage,
+        &format!("cannot hash password: {}", e),
+    )
+}
+
+impl PasswordPolicy {
+    fn argon2(&self) -> Result<Argon2<'static>, AuthError> {
+        let params = Params::new(
+            self.argon2_memory_kib,
+            self.argon2_iterations,
+            self.argon2_parallelism,
+            None,
+        )
+        .map_err(hash_error)?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+
+    /// Check the costs are within what the algorithm accepts, e.g. at
+    /// startup rather than at the first login.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(4..=31).contains(&self.bcrypt_cost) {
+            return Err(format!(
+                "bcrypt_cost must be from 4 to 31, not {}",
+                self.bcrypt_cost
+            ));
+        }
+        self.argon2()
+            .map(|_| ())
+            .map_err(|e| format!("invalid argon2 settings: {}", e.msg))
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String, AuthError> {
+        match self.algorithm {
+            HashAlgorithm::Bcrypt => bcrypt::hash(password, self.bcrypt_cost).map_err(hash_error),
+            HashAlgorithm::Argon2id => self
+                .argon2()?
+                .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
+                .map(|hash| hash.to_string())
+                .map_err(hash_error),
+        }
+    }
+
+    /// Whether the stored hash was made with another algorithm or cost than
+    /// the policy's.
+    pub fn needs_rehash(&self, stored: &str) -> bool {
+        match self.algorithm {
+            HashAlgorithm::Bcrypt => {
+                !stored.starts_with(BCRYPT_PREFIX)
+                    || stored.get(4..6).and_then(|cost| cost.parse().ok()) != Some(self.bcrypt_cost)
+            }
+            HashAlgorithm::Argon2id => {
+                let params = PasswordHash::new(stored)
+                    .ok()
+                    .filter(|hash| {
+                        stored.starts_with(ARGON2ID_PREFIX)
+                            && hash.version == Some(Version::V0x13.into())
+                    })
+                    .and_then(|hash| Params::try_from(&hash).ok());
+                !matches!(params, Some(params)
+                    if params.m_cost() == self.argon2_memory_kib
+                        && params.t_cost() == self.argon2_iterations
+                        && params.p_cost() == self.argon2_parallelism)
+            }
+        }
+    }
+}
+
+/// Check the password against a bcrypt or argon2id hash, whatever the
+/// current policy.
+pub fn verify(password: &str, stored: &str) -> Result<bool, AuthError> {
+    if stored.starts_with(ARGON2ID_PREFIX) {
+        let hash = PasswordHash::new(stored).map_err(hash_error)?;
+        Ok(Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok())
+    } else {
+        bcrypt::verify(password, stored).map_err(hash_error)
+    }
+}
+
+#[cfg(test)]
+#[path = "./passwords_test.rs"]
+mod passwords_test;
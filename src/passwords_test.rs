@@ -0,0 +1,69 @@
+use super::*;
+
+fn fast_policy(algorithm: HashAlgorithm) -> PasswordPolicy {
+    PasswordPolicy {
+        algorithm,
+        bcrypt_cost: 4,
+        argon2_memory_kib: 64,
+        argon2_iterations: 1,
+        argon2_parallelism: 1,
+    }
+}
+
+#[test]
+fn hashes_and_verifies_passwords() {
+    for algorithm in &[HashAlgorithm::Bcrypt, HashAlgorithm::Argon2id] {
+        let policy = fast_policy(*algorithm);
+        let hashed = policy.hash("secret").unwrap();
+        assert_ne!(hashed, policy.hash("secret").unwrap());
+        assert!(verify("secret", &hashed).unwrap());
+        assert!(!verify("guess", &hashed).unwrap());
+        assert!(!policy.needs_rehash(&hashed));
+    }
+    assert!(verify("secret", "not a hash").is_err());
+}
+
+#[test]
+fn flags_outdated_hashes() {
+    let bcrypt = fast_policy(HashAlgorithm::Bcrypt);
+    let argon2 = fast_policy(HashAlgorithm::Argon2id);
+    let bcrypt_hash = bcrypt.hash("secret").unwrap();
+    let argon2_hash = argon2.hash("secret").unwrap();
+
+    assert!(bcrypt.needs_rehash(&argon2_hash));
+    assert!(argon2.needs_rehash(&bcrypt_hash));
+    assert!(PasswordPolicy {
+        bcrypt_cost: 5,
+        ..bcrypt
+    }
+    .needs_rehash(&bcrypt_hash));
+    assert!(PasswordPolicy {
+        argon2_memory_kib: 128,
+        ..argon2
+    }
+    .needs_rehash(&argon2_hash));
+    assert!(PasswordPolicy {
+        argon2_iterations: 2,
+        ..argon2
+    }
+    .needs_rehash(&argon2_hash));
+    // older htpasswd-style bcrypt prefixes still count as bcrypt
+    assert!(!bcrypt.needs_rehash(&bcrypt_hash.replace("$2b$", "$2y$")));
+}
+
+#[test]
+fn validates_costs() {
+    assert!(PasswordPolicy::default().validate().is_ok());
+    assert!(PasswordPolicy {
+        bcrypt_cost: 3,
+        ..PasswordPolicy::default()
+    }
+    .validate()
+    .is_err());
+    assert!(PasswordPolicy {
+        argon2_iterations: 0,
+        ..PasswordPolicy::default()
+    }
+    .validate()
+    .is_err());
+}
@@ -1,59 +1,792 @@
-use crate::boxchecker::{ActionId, ActivityId, AnnotationId, BoxChecker, BoxMaker, BoxSearcher};
-use normal::{IdPairs, Normal};
+use crate::boxchecker::{
+    ActionId, ActionLink, ActivityId, AnnotationId, BoxChecker, BoxCheckerError, BoxExport,
+    BoxMaker, BoxSearcher, ExportedAction, Goal, GoalKeeper, GoalProgress, GoalUnit, JournalEntry,
+    LogEntry, LoggedActivity, Note, Rollup, Streak,
+};
+use crate::calendar::{in_range, local_date, EARLIEST_MILLIS, LATEST_MILLIS};
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use normal::IdPairs;
+use sqlite::{Connection, State};
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 const ACTION_HIERARCHY_TAB: &str = "actionHierarchy";
 const ACTION_TAB: &str = "actions";
 const ACTIVITY_TAB: &str = "activities";
+const ARCHIVE_TAB: &str = "archivedActions";
+const DURATION_TAB: &str = "durations";
+const GOAL_TAB: &str = "goals";
 const NOTATIONS_TAB: &str = "notations";
+const NOTE_FIELDS_TAB: &str = "noteFields";
 const NOTE_TAB: &str = "notes";
 
 const ACTION_COL: &str = "actionName";
 const CHILD_COL: &str = "child";
+const END_COL: &str = "endTime";
+const NAME_COL: &str = "name";
 const NOTE_COL: &str = "note";
 const PARENT_COL: &str = "parent";
+const TARGET_COL: &str = "target";
 const TIME_COL: &str = "time";
+const UNIT_COL: &str = "unit";
+const VALUE_COL: &str = "value";
 
+const PAGE_SIZE: usize = 256;
+const MILLIS_PER_MINUTE: f64 = 60_000.0;
+
+const SQLITE_CONSTRAINT: isize = 19;
+
+/// Wrap errors from the normal crate, which only carry a message.
+macro_rules! storage_error {
+    ($normal_err:expr) => {
+        BoxCheckerError::Storage($normal_err.msg)
+    };
+}
+
+impl From<sqlite::Error> for BoxCheckerError {
+    fn from(e: sqlite::Error) -> Self {
+        let msg = e.message.unwrap_or_else(|| "???".to_string());
+        match e.code {
+            Some(SQLITE_CONSTRAINT) => BoxCheckerError::Conflict(msg),
+            _ => BoxCheckerError::Storage(msg),
+        }
+    }
+}
+
+/// A box of actions, activities and notes in one SQLite database.
+/// The action hierarchy, which only ever gains links, is kept by the normal
+/// crate. Actions, notes, activities and notations are edited in place, so
+/// they are kept on the box's own connection, where changes share
+/// transactions. Boxes created through the normal crate keep their tables,
+/// which have the same names and columns.
 pub struct SqliteBoxes<'a> {
     action_hierarchy: IdPairs<'a>,
-    actions: Normal<'a>,
-    activities: IdPairs<'a>,
-    notations: IdPairs<'a>,
-    notes: Normal<'a>,
+    conn: Connection,
 }
 
 impl<'a> SqliteBoxes<'a> {
     pub fn new(path: &str) -> Self {
+        let conn = sqlite::open(path).unwrap();
+        conn.execute(format!(
+            "
+                CREATE TABLE IF NOT EXISTS {} (id INTEGER PRIMARY KEY, {} TEXT UNIQUE);
+                CREATE TABLE IF NOT EXISTS {} (id INTEGER PRIMARY KEY, {} TEXT UNIQUE);
+                CREATE TABLE IF NOT EXISTS {} ({} INTEGER, {} INTEGER);
+                CREATE INDEX IF NOT EXISTS idx_activity_time ON {} ({});
+                CREATE TABLE IF NOT EXISTS {} ({} INTEGER, {} INTEGER);
+                CREATE INDEX IF NOT EXISTS idx_notation_time ON {} ({});
+                CREATE TABLE IF NOT EXISTS {} ({} INTEGER, {} INTEGER, {} INTEGER);
+                CREATE INDEX IF NOT EXISTS idx_duration_time ON {} ({});
+                CREATE TABLE IF NOT EXISTS {} ({} INTEGER UNIQUE, {} INTEGER, {} TEXT);
+                CREATE TABLE IF NOT EXISTS {} ({} INTEGER UNIQUE);
+                CREATE TABLE IF NOT EXISTS {} (
+                    {} INTEGER, {} INTEGER, {} TEXT, {} TEXT, UNIQUE ({}, {}, {}));
+            ",
+            ACTION_TAB,
+            ACTION_COL,
+            NOTE_TAB,
+            NOTE_COL,
+            ACTIVITY_TAB,
+            TIME_COL,
+            ACTION_COL,
+            ACTIVITY_TAB,
+            TIME_COL,
+            NOTATIONS_TAB,
+            TIME_COL,
+            NOTE_COL,
+            NOTATIONS_TAB,
+            TIME_COL,
+            DURATION_TAB,
+            TIME_COL,
+            ACTION_COL,
+            END_COL,
+            DURATION_TAB,
+            TIME_COL,
+            GOAL_TAB,
+            ACTION_COL,
+            TARGET_COL,
+            UNIT_COL,
+            ARCHIVE_TAB,
+            ACTION_COL,
+            NOTE_FIELDS_TAB,
+            TIME_COL,
+            NOTE_COL,
+            NAME_COL,
+            VALUE_COL,
+            TIME_COL,
+            NOTE_COL,
+            NAME_COL
+        ))
+        .unwrap();
+
         SqliteBoxes {
             action_hierarchy: IdPairs::new(path, ACTION_HIERARCHY_TAB, PARENT_COL, CHILD_COL)
                 .unwrap(),
-            actions: Normal::new(path, ACTION_TAB, ACTION_COL).unwrap(),
-            activities: IdPairs::new(path, ACTIVITY_TAB, TIME_COL, ACTION_COL).unwrap(),
-            notations: IdPairs::new(path, NOTATIONS_TAB, TIME_COL, NOTE_COL).unwrap(),
-            notes: Normal::new(path, NOTE_TAB, NOTE_COL).unwrap(),
+            conn,
         }
     }
-}
 
-impl<'a> BoxMaker for SqliteBoxes<'a> {
-    fn create_action(&mut self, action_name: &str) -> ActionId {
-        match self.actions.create(action_name) {
-            Ok(id) => id,
+    /// Look up the action name or note text with the id.
+    fn get_text(&self, tab: &str, col: &str, id: i64) -> Result<Option<String>, BoxCheckerError> {
+        let query = format!("SELECT {} FROM {} WHERE rowid = ?;", col, tab);
+        let mut stat = self.conn.prepare(query)?;
+        stat.bind(1, id)?;
+        match stat.next()? {
+            State::Row => Ok(Some(stat.read::<String>(0)?)),
+            State::Done => Ok(None),
+        }
+    }
+
+    /// Find the id of the action name or note text, adding it if new.
+    fn find_or_insert_text(
+        &self,
+        tab: &str,
+        col: &str,
+        text: &str,
+    ) -> Result<i64, BoxCheckerError> {
+        let query = format!("SELECT rowid FROM {} WHERE {} = ? LIMIT 1;", tab, col);
+        let mut stat = self.conn.prepare(query)?;
+        stat.bind(1, text)?;
+        if let State::Row = stat.next()? {
+            return Ok(stat.read::<i64>(0)?);
+        }
+
+        let query = format!("INSERT INTO {} ({}) VALUES (?);", tab, col);
+        let mut stat = self.conn.prepare(query)?;
+        stat.bind(1, text)?;
+        stat.next()?;
+        let mut stat = self.conn.prepare("SELECT last_insert_rowid();")?;
+        stat.next()?;
+        Ok(stat.read::<i64>(0)?)
+    }
+
+    /// Check that the action exists.
+    fn check_action(&self, action: ActionId) -> Result<(), BoxCheckerError> {
+        match self.get_text(ACTION_TAB, ACTION_COL, action)? {
+            Some(_) => Ok(()),
+            None => Err(BoxCheckerError::NotFound(format!("action {}", action))),
+        }
+    }
+
+    /// Refuse times outside the years that dates can be found for.
+    fn check_time(time_millis: i64) -> Result<(), BoxCheckerError> {
+        if in_range(time_millis) {
+            Ok(())
+        } else {
+            Err(BoxCheckerError::InvalidInput(format!(
+                "time {} out of range",
+                time_millis
+            )))
+        }
+    }
+
+    /// Check that the action exists and has not been archived.
+    fn check_active_action(&self, action: ActionId) -> Result<(), BoxCheckerError> {
+        self.check_action(action)?;
+        if self.is_archived(action)? {
+            return Err(BoxCheckerError::Conflict(format!(
+                "action {} is archived",
+                action
+            )));
+        }
+        Ok(())
+    }
+
+    fn is_archived(&self, action: ActionId) -> Result<bool, BoxCheckerError> {
+        let query = format!(
+            "SELECT COUNT(*) FROM {} WHERE {} = ?;",
+            ARCHIVE_TAB, ACTION_COL
+        );
+        Ok(self.count_rows(query, &[action])? > 0)
+    }
+
+    /// Run a query counting rows, binding the parameters in order.
+    fn count_rows(&self, query: String, params: &[i64]) -> Result<i64, BoxCheckerError> {
+        let mut stat = self.conn.prepare(query)?;
+        for (i, param) in params.iter().enumerate() {
+            stat.bind(i + 1, *param)?;
+        }
+        stat.next()?;
+        Ok(stat.read::<i64>(0)?)
+    }
+
+    /// Run a statement, binding the parameters in order.
+    fn execute_with(&self, query: String, params: &[i64]) -> Result<(), BoxCheckerError> {
+        let mut stat = self.conn.prepare(query)?;
+        for (i, param) in params.iter().enumerate() {
+            stat.bind(i + 1, *param)?;
+        }
+        while let State::Row = stat.next()? {}
+        Ok(())
+    }
+
+    /// Run the changes in a transaction, rolling back on failure.
+    /// Links in the action hierarchy, kept by the normal crate on its own
+    /// connection, are not covered.
+    fn in_transaction<T, F>(&self, changes: F) -> Result<T, BoxCheckerError>
+    where
+        F: FnOnce(&Self) -> Result<T, BoxCheckerError>,
+    {
+        self.conn.execute("BEGIN;")?;
+        match changes(self) {
+            Ok(result) => {
+                self.conn.execute("COMMIT;")?;
+                Ok(result)
+            }
             Err(e) => {
-                log::error!("create_action: {}", e.msg);
-                0
+                self.conn.execute("ROLLBACK;").ok();
+                Err(e)
             }
         }
     }
 
-    fn make_action_parent_of(&mut self, parent: ActionId, child: ActionId) {
-        match self.action_hierarchy.insert(parent, child) {
-            Ok(_) => (),
-            Err(e) => {
-                log::error!("make_action_parent_of: {}", e.msg)
+    /// Remove the note once no activity refers to it.
+    fn prune_note(&self, annotation: AnnotationId) -> Result<(), BoxCheckerError> {
+        let query = format!(
+            "SELECT COUNT(*) FROM {} WHERE {} = ?;",
+            NOTATIONS_TAB, NOTE_COL
+        );
+        if self.count_rows(query, &[annotation])? == 0 {
+            let query = format!("DELETE FROM {} WHERE rowid = ?;", NOTE_TAB);
+            self.execute_with(query, &[annotation])?;
+        }
+        Ok(())
+    }
+
+    /// Check that the note is attached to the activity.
+    fn check_notation(
+        &self,
+        activity: ActivityId,
+        annotation: AnnotationId,
+    ) -> Result<(), BoxCheckerError> {
+        let query = format!(
+            "SELECT COUNT(*) FROM {} WHERE {} = ? AND {} = ?;",
+            NOTATIONS_TAB, TIME_COL, NOTE_COL
+        );
+        if self.count_rows(query, &[activity, annotation])? == 0 {
+            return Err(BoxCheckerError::NotFound(format!(
+                "note {} on activity {}",
+                annotation, activity
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check that the action was logged at the time.
+    fn check_action_activity(
+        &self,
+        activity: ActivityId,
+        action: ActionId,
+    ) -> Result<(), BoxCheckerError> {
+        let query = format!(
+            "SELECT COUNT(*) FROM {} WHERE {} = ? AND {} = ?;",
+            ACTIVITY_TAB, TIME_COL, ACTION_COL
+        );
+        if self.count_rows(query, &[activity, action])? == 0 {
+            return Err(BoxCheckerError::NotFound(format!(
+                "activity {} of action {}",
+                activity, action
+            )));
+        }
+        Ok(())
+    }
+
+    /// Move notes to the new time, or drop them, once no activity remains
+    /// logged at the old time.
+    fn move_notations(&self, activity: ActivityId, to: Option<i64>) -> Result<(), BoxCheckerError> {
+        let query = format!(
+            "SELECT COUNT(*) FROM {} WHERE {} = ?;",
+            ACTIVITY_TAB, TIME_COL
+        );
+        if self.count_rows(query, &[activity])? > 0 {
+            return Ok(());
+        }
+        match to {
+            Some(time) => {
+                for tab in &[NOTATIONS_TAB, NOTE_FIELDS_TAB] {
+                    let query = format!(
+                        "UPDATE {} SET {} = ? WHERE {} = ?;",
+                        tab, TIME_COL, TIME_COL
+                    );
+                    self.execute_with(query, &[time, activity])?;
+                }
+                Ok(())
+            }
+            None => {
+                let mut notes = Vec::new();
+                let query = format!(
+                    "SELECT {} FROM {} WHERE {} = ?;",
+                    NOTE_COL, NOTATIONS_TAB, TIME_COL
+                );
+                let mut stat = self.conn.prepare(query)?;
+                stat.bind(1, activity)?;
+                while let State::Row = stat.next()? {
+                    notes.push(stat.read::<i64>(0)?);
+                }
+                for tab in &[NOTATIONS_TAB, NOTE_FIELDS_TAB] {
+                    let query = format!("DELETE FROM {} WHERE {} = ?;", tab, TIME_COL);
+                    self.execute_with(query, &[activity])?;
+                }
+                for note in notes {
+                    self.prune_note(note)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Look up the notes, with their fields, attached to the activity.
+    fn load_notes(
+        &self,
+        activity: ActivityId,
+        ids: &[AnnotationId],
+    ) -> Result<Vec<Note>, BoxCheckerError> {
+        let mut texts = vec![(0, "".to_string()); ids.len()];
+        let count = self.get_note_bulk(&ids.to_vec(), &mut texts)?;
+        let texts: BTreeMap<AnnotationId, String> = texts.into_iter().take(count).collect();
+
+        let mut notes = Vec::with_capacity(ids.len());
+        for id in ids {
+            let text = match texts.get(id) {
+                Some(text) => text.clone(),
+                None => continue,
+            };
+            let mut fields = vec![("".to_string(), "".to_string()); PAGE_SIZE];
+            let count = self.get_note_fields(activity, *id, &mut fields)?;
+            notes.push(Note {
+                id: *id,
+                activity,
+                text,
+                fields: fields.into_iter().take(count).collect(),
+            });
+        }
+        Ok(notes)
+    }
+
+    /// Check that the entry can be logged, returning its time.
+    /// Check the entry, returning its time and the end of its duration, if
+    /// any.
+    fn check_entry(
+        &self,
+        entry: &LogEntry,
+        now_millis: i64,
+    ) -> Result<(i64, Option<i64>), BoxCheckerError> {
+        let time_millis = entry.epoch_millis(now_millis)?;
+        Self::check_time(time_millis)?;
+        let end_millis = match entry.duration {
+            Some(duration) => Some(Self::end_time(time_millis, duration)?),
+            None => None,
+        };
+        if matches!(&entry.note, Some(note) if note.trim().is_empty()) {
+            return Err(BoxCheckerError::InvalidInput("empty note".to_string()));
+        }
+        self.check_active_action(entry.action)?;
+        Ok((time_millis, end_millis))
+    }
+
+    /// Compute when an activity lasting the duration ends, refusing negative
+    /// durations and ends out of range.
+    fn end_time(time_millis: i64, duration_millis: i64) -> Result<i64, BoxCheckerError> {
+        if duration_millis < 0 {
+            return Err(BoxCheckerError::InvalidInput(format!(
+                "negative duration {}",
+                duration_millis
+            )));
+        }
+        let end_millis = time_millis.checked_add(duration_millis).ok_or_else(|| {
+            BoxCheckerError::InvalidInput(format!("duration {} out of range", duration_millis))
+        })?;
+        Self::check_time(end_millis)?;
+        Ok(end_millis)
+    }
+
+    /// Check that at least one activity was logged at the time.
+    fn check_activity(&self, activity: ActivityId) -> Result<(), BoxCheckerError> {
+        let query = format!(
+            "SELECT COUNT(*) FROM {} WHERE {} = ?;",
+            ACTIVITY_TAB, TIME_COL
+        );
+        if self.count_rows(query, &[activity])? == 0 {
+            Err(BoxCheckerError::NotFound(format!("activity {}", activity)))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Collect all activities logged within the time range.
+    fn activities_between(
+        &self,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<(ActivityId, ActionId)>, BoxCheckerError> {
+        let query = format!(
+            "SELECT {}, {} FROM {} WHERE {} >= ? AND {} <= ? ORDER BY {}, rowid;",
+            TIME_COL, ACTION_COL, ACTIVITY_TAB, TIME_COL, TIME_COL, TIME_COL
+        );
+        let mut stat = self.conn.prepare(query)?;
+        stat.bind(1, from)?;
+        stat.bind(2, to)?;
+        let mut result = Vec::new();
+        while let State::Row = stat.next()? {
+            result.push((stat.read::<i64>(0)?, stat.read::<i64>(1)?));
+        }
+        Ok(result)
+    }
+
+    /// Collect up to the limit of activities within the time range, with
+    /// their durations and notes.
+    fn journal_between(
+        &self,
+        from: i64,
+        to: i64,
+        limit: usize,
+    ) -> Result<Vec<JournalEntry>, BoxCheckerError> {
+        let activities = self.activities_between(from, to)?;
+        let durations: BTreeMap<(ActivityId, ActionId), i64> = self
+            .durations_between(from, to)?
+            .into_iter()
+            .map(|(activity, action, millis)| ((activity, action), millis))
+            .collect();
+
+        let mut notes: BTreeMap<ActivityId, Vec<Note>> = BTreeMap::new();
+        let mut entries = Vec::new();
+        for (activity, action) in activities.into_iter().take(limit) {
+            let activity_notes = match notes.entry(activity) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let ids = self.notations_of(activity)?;
+                    entry.insert(self.load_notes(activity, &ids)?)
+                }
+            };
+            entries.push(JournalEntry {
+                activity,
+                action,
+                duration: durations.get(&(activity, action)).copied(),
+                notes: activity_notes.clone(),
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Collect the ids of all notes attached to the activity, in id order.
+    fn notations_of(&self, activity: ActivityId) -> Result<Vec<AnnotationId>, BoxCheckerError> {
+        let query = format!(
+            "SELECT {} FROM {} WHERE {} = ? ORDER BY {};",
+            NOTE_COL, NOTATIONS_TAB, TIME_COL, NOTE_COL
+        );
+        let mut stat = self.conn.prepare(query)?;
+        stat.bind(1, activity)?;
+        let mut result = Vec::new();
+        while let State::Row = stat.next()? {
+            result.push(stat.read::<i64>(0)?);
+        }
+        Ok(result)
+    }
+
+    /// Collect all (parent, child) links between actions.
+    fn hierarchy_links(&self) -> Result<Vec<(ActionId, ActionId)>, BoxCheckerError> {
+        page_all(&self.action_hierarchy, i64::MIN, i64::MAX)
+    }
+
+    /// Sum the durations, in milliseconds, of stopped activities for the
+    /// action started within the time range.
+    fn total_duration(&self, action: ActionId, from: i64, to: i64) -> Result<i64, BoxCheckerError> {
+        let query = format!(
+            "SELECT TOTAL({} - {}) FROM {} WHERE {} = ? AND {} >= ? AND {} <= ? AND {} IS NOT NULL;",
+            END_COL, TIME_COL, DURATION_TAB, ACTION_COL, TIME_COL, TIME_COL, END_COL
+        );
+        let mut stat = self.conn.prepare(query)?;
+        stat.bind(1, action)?;
+        stat.bind(2, from)?;
+        stat.bind(3, to)?;
+        stat.next()?;
+        Ok(stat.read::<f64>(0)? as i64)
+    }
+
+    /// Collect the durations, in milliseconds, of stopped activities started
+    /// within the time range.
+    fn durations_between(
+        &self,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<(ActivityId, ActionId, i64)>, BoxCheckerError> {
+        let query = format!(
+            "SELECT {}, {}, {} - {} FROM {} WHERE {} >= ? AND {} <= ? AND {} IS NOT NULL ORDER BY {};",
+            TIME_COL, ACTION_COL, END_COL, TIME_COL, DURATION_TAB, TIME_COL, TIME_COL, END_COL, TIME_COL
+        );
+        let mut stat = self.conn.prepare(query)?;
+        stat.bind(1, from)?;
+        stat.bind(2, to)?;
+        let mut result = Vec::new();
+        while let State::Row = stat.next()? {
+            result.push((
+                stat.read::<i64>(0)?,
+                stat.read::<i64>(1)?,
+                stat.read::<i64>(2)?,
+            ));
+        }
+        Ok(result)
+    }
+
+    /// Look up goals in action order, with a negative limit returning all.
+    fn load_goals(&self, limit: i64) -> Result<Vec<Goal>, BoxCheckerError> {
+        let query = format!(
+            "SELECT {}, {}, {} FROM {} ORDER BY {} LIMIT ?;",
+            ACTION_COL, TARGET_COL, UNIT_COL, GOAL_TAB, ACTION_COL
+        );
+        let mut stat = self.conn.prepare(query)?;
+        stat.bind(1, limit)?;
+        let mut result = Vec::new();
+        while let State::Row = stat.next()? {
+            result.push(Goal {
+                action: stat.read::<i64>(0)?,
+                target: stat.read::<i64>(1)?,
+                unit: stat.read::<String>(2)?.parse().unwrap_or(GoalUnit::Count),
+            });
+        }
+        Ok(result)
+    }
+
+    /// Record that the action was done at the time.
+    fn insert_activity(&self, action: ActionId, time_millis: i64) -> Result<(), BoxCheckerError> {
+        let query = format!(
+            "INSERT INTO {} ({}, {}) VALUES (?, ?);",
+            ACTIVITY_TAB, TIME_COL, ACTION_COL
+        );
+        self.execute_with(query, &[time_millis, action])
+    }
+
+    /// Record the start, and optionally the end, of a timed activity.
+    fn insert_duration(
+        &self,
+        action: ActionId,
+        start_millis: i64,
+        end_millis: Option<i64>,
+    ) -> Result<(), BoxCheckerError> {
+        let query = format!(
+            "INSERT INTO {} ({}, {}, {}) VALUES (?, ?, ?);",
+            DURATION_TAB, TIME_COL, ACTION_COL, END_COL
+        );
+        let mut stat = self.conn.prepare(query)?;
+        stat.bind(1, start_millis)?;
+        stat.bind(2, action)?;
+        match end_millis {
+            Some(end) => stat.bind(3, end)?,
+            None => stat.bind(3, ())?,
+        }
+        stat.next()?;
+        Ok(())
+    }
+
+    /// Close the action's open timed activity started at the given time,
+    /// returning the number of activities stopped.
+    fn close_duration(
+        &self,
+        activity: ActivityId,
+        action: ActionId,
+        end_millis: i64,
+    ) -> Result<usize, BoxCheckerError> {
+        let query = format!(
+            "SELECT COUNT(*) FROM {} WHERE {} = ? AND {} = ? AND {} IS NULL;",
+            DURATION_TAB, TIME_COL, ACTION_COL, END_COL
+        );
+        let mut stat = self.conn.prepare(query)?;
+        stat.bind(1, activity)?;
+        stat.bind(2, action)?;
+        stat.next()?;
+        let count = stat.read::<i64>(0)? as usize;
+        if count == 0 {
+            return Ok(0);
+        }
+
+        let query = format!(
+            "UPDATE {} SET {} = ? WHERE {} = ? AND {} = ? AND {} IS NULL;",
+            DURATION_TAB, END_COL, TIME_COL, ACTION_COL, END_COL
+        );
+        let mut stat = self.conn.prepare(query)?;
+        stat.bind(1, end_millis)?;
+        stat.bind(2, activity)?;
+        stat.bind(3, action)?;
+        stat.next()?;
+        Ok(count)
+    }
+}
+
+impl<'a> BoxMaker for SqliteBoxes<'a> {
+    fn archive_action(&mut self, action: ActionId, archived: bool) -> Result<(), BoxCheckerError> {
+        self.check_action(action)?;
+        let query = if archived {
+            format!(
+                "INSERT OR IGNORE INTO {} ({}) VALUES (?);",
+                ARCHIVE_TAB, ACTION_COL
+            )
+        } else {
+            format!("DELETE FROM {} WHERE {} = ?;", ARCHIVE_TAB, ACTION_COL)
+        };
+        self.execute_with(query, &[action])
+    }
+
+    fn create_action(&mut self, action_name: &str) -> Result<ActionId, BoxCheckerError> {
+        if action_name.trim().is_empty() {
+            return Err(BoxCheckerError::InvalidInput(
+                "empty action name".to_string(),
+            ));
+        }
+        self.find_or_insert_text(ACTION_TAB, ACTION_COL, action_name)
+    }
+
+    /// Link the actions, rejecting links that would create a cycle.
+    fn make_action_parent_of(
+        &mut self,
+        parent: ActionId,
+        child: ActionId,
+    ) -> Result<(), BoxCheckerError> {
+        if parent == child {
+            return Err(BoxCheckerError::InvalidInput(format!(
+                "action {} cannot be its own parent",
+                parent
+            )));
+        }
+        self.check_action(parent)?;
+        self.check_action(child)?;
+
+        let links = self.hierarchy_links()?;
+        if walk_links(&links, child, true).contains(&parent) {
+            return Err(BoxCheckerError::Conflict(format!(
+                "action {} is a descendant of {}",
+                parent, child
+            )));
+        }
+        if links.contains(&(parent, child)) {
+            return Ok(());
+        }
+        self.action_hierarchy
+            .insert(parent, child)
+            .map_err(|e| storage_error!(e))
+    }
+
+    /// Rename the action, rejecting names already used by another action.
+    fn rename_action(
+        &mut self,
+        action: ActionId,
+        action_name: &str,
+    ) -> Result<(), BoxCheckerError> {
+        if action_name.trim().is_empty() {
+            return Err(BoxCheckerError::InvalidInput(
+                "empty action name".to_string(),
+            ));
+        }
+        self.check_action(action)?;
+        let query = format!(
+            "UPDATE {} SET {} = ? WHERE rowid = ?;",
+            ACTION_TAB, ACTION_COL
+        );
+        let mut stat = self.conn.prepare(query)?;
+        stat.bind(1, action_name)?;
+        stat.bind(2, action)?;
+        stat.next()?;
+        Ok(())
+    }
+}
+
+/// Collect all pairs with left ids within the range, paging through the table
+/// so that pairs sharing a left id are not split across pages.
+fn page_all(pairs: &IdPairs, from: i64, to: i64) -> Result<Vec<(i64, i64)>, BoxCheckerError> {
+    let mut result = Vec::new();
+    let mut start = from;
+    let mut page_size = PAGE_SIZE;
+    loop {
+        let mut page = vec![(0, 0); page_size];
+        let count = pairs
+            .page_left(start, to, &mut page)
+            .map_err(|e| storage_error!(e))?;
+        if count < page_size {
+            result.extend_from_slice(&page[..count]);
+            return Ok(result);
+        }
+
+        let last_left = page[count - 1].0;
+        let complete = page
+            .iter()
+            .take_while(|(left, _)| *left < last_left)
+            .count();
+        if complete == 0 {
+            page_size *= 2;
+        } else {
+            result.extend_from_slice(&page[..complete]);
+            start = last_left;
+        }
+    }
+}
+
+/// Walk the links breadth first from the action, following parent-to-child
+/// links when downward is set, or child-to-parent links otherwise.
+/// The action itself is excluded from the result.
+fn walk_links(links: &[(ActionId, ActionId)], action: ActionId, downward: bool) -> Vec<ActionId> {
+    let mut visited = BTreeSet::new();
+    visited.insert(action);
+    let mut result = Vec::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(action);
+    while let Some(next) = queue.pop_front() {
+        for (parent, child) in links.iter() {
+            let (from, to) = if downward {
+                (parent, child)
+            } else {
+                (child, parent)
+            };
+            if *from == next && visited.insert(*to) {
+                result.push(*to);
+                queue.push_back(*to);
             }
         }
     }
+    result
+}
+
+/// Copy as many results as fit into the destination, returning the count.
+fn copy_into<T: Clone>(results: Vec<T>, dest: &mut [T]) -> usize {
+    let count = results.len().min(dest.len());
+    dest[..count].clone_from_slice(&results[..count]);
+    count
+}
+
+/// Summarize runs of consecutive completed days up to today.
+fn get_streak(action: ActionId, completed: &BTreeSet<NaiveDate>, today: NaiveDate) -> Streak {
+    let mut longest = 0;
+    let mut run = 0;
+    let mut previous: Option<NaiveDate> = None;
+    for day in completed.iter() {
+        run = match previous {
+            Some(p) if p.succ_opt() == Some(*day) => run + 1,
+            _ => 1,
+        };
+        longest = longest.max(run);
+        previous = Some(*day);
+    }
+
+    let mut current = 0;
+    let mut day = if completed.contains(&today) {
+        Some(today)
+    } else {
+        today.pred_opt()
+    };
+    while let Some(d) = day.filter(|d| completed.contains(d)) {
+        current += 1;
+        day = d.pred_opt();
+    }
+
+    Streak {
+        action,
+        current,
+        longest,
+        last_completed: completed
+            .iter()
+            .next_back()
+            .map(|d| d.format("%Y-%m-%d").to_string()),
+    }
 }
 
 fn get_time() -> i64 {
@@ -64,115 +797,697 @@ fn get_time() -> i64 {
 }
 
 impl<'a> BoxChecker for SqliteBoxes<'a> {
-    fn annotate_activity(&mut self, activity: ActivityId, text: &str) -> AnnotationId {
-        match self.notes.create(text) {
-            Ok(note_id) => {
-                match self.notations.insert(activity, note_id) {
-                    Ok(_) => note_id,
-                    Err(e) => {
-                        log::error!("cannot notate activity {}: {}", activity, e.msg);
-                        0 // recover?
-                    }
-                }
+    fn annotate_activity(
+        &mut self,
+        activity: ActivityId,
+        text: &str,
+    ) -> Result<AnnotationId, BoxCheckerError> {
+        self.annotate_activity_with_fields(activity, text, &BTreeMap::new())
+    }
+
+    /// Attach the note, along with named fields kept for this activity.
+    fn annotate_activity_with_fields(
+        &mut self,
+        activity: ActivityId,
+        text: &str,
+        fields: &BTreeMap<String, String>,
+    ) -> Result<AnnotationId, BoxCheckerError> {
+        if fields.keys().any(|name| name.trim().is_empty()) {
+            return Err(BoxCheckerError::InvalidInput(
+                "empty field name".to_string(),
+            ));
+        }
+        self.check_activity(activity)?;
+        self.in_transaction(|boxer| {
+            let note_id = boxer.find_or_insert_text(NOTE_TAB, NOTE_COL, text)?;
+            let query = format!(
+                "INSERT INTO {} ({}, {}) VALUES (?, ?);",
+                NOTATIONS_TAB, TIME_COL, NOTE_COL
+            );
+            boxer.execute_with(query, &[activity, note_id])?;
+            let query = format!(
+                "INSERT OR REPLACE INTO {} ({}, {}, {}, {}) VALUES (?, ?, ?, ?);",
+                NOTE_FIELDS_TAB, TIME_COL, NOTE_COL, NAME_COL, VALUE_COL
+            );
+            for (name, value) in fields.iter() {
+                let mut stat = boxer.conn.prepare(&query)?;
+                stat.bind(1, activity)?;
+                stat.bind(2, note_id)?;
+                stat.bind(3, name.as_str())?;
+                stat.bind(4, value.as_str())?;
+                stat.next()?;
             }
-            Err(e) => {
-                log::error!("cannot create note for activity {}: {}", activity, e.msg);
-                0
+            Ok(note_id)
+        })
+    }
+
+    /// Detach the note from the activity, removing it once unused.
+    fn delete_annotation(
+        &mut self,
+        activity: ActivityId,
+        annotation: AnnotationId,
+    ) -> Result<(), BoxCheckerError> {
+        self.check_notation(activity, annotation)?;
+        self.in_transaction(|boxer| {
+            let query = format!(
+                "DELETE FROM {} WHERE {} = ? AND {} = ?;",
+                NOTATIONS_TAB, TIME_COL, NOTE_COL
+            );
+            boxer.execute_with(query, &[activity, annotation])?;
+            let query = format!(
+                "DELETE FROM {} WHERE {} = ? AND {} = ?;",
+                NOTE_FIELDS_TAB, TIME_COL, NOTE_COL
+            );
+            boxer.execute_with(query, &[activity, annotation])?;
+            boxer.prune_note(annotation)
+        })
+    }
+
+    /// Attach a note with the new text in place of the old one.
+    /// Notes with identical text may be shared between activities, so the
+    /// old note is only removed once unused.
+    fn edit_annotation(
+        &mut self,
+        activity: ActivityId,
+        annotation: AnnotationId,
+        text: &str,
+    ) -> Result<AnnotationId, BoxCheckerError> {
+        self.check_notation(activity, annotation)?;
+        self.in_transaction(|boxer| {
+            let note_id = boxer.find_or_insert_text(NOTE_TAB, NOTE_COL, text)?;
+            if note_id == annotation {
+                return Ok(note_id);
             }
-        }
+            let query = format!(
+                "UPDATE {} SET {} = ? WHERE {} = ? AND {} = ?;",
+                NOTATIONS_TAB, NOTE_COL, TIME_COL, NOTE_COL
+            );
+            boxer.execute_with(query, &[note_id, activity, annotation])?;
+            let query = format!(
+                "UPDATE {} SET {} = ? WHERE {} = ? AND {} = ?;",
+                NOTE_FIELDS_TAB, NOTE_COL, TIME_COL, NOTE_COL
+            );
+            boxer.execute_with(query, &[note_id, activity, annotation])?;
+            boxer.prune_note(annotation)?;
+            Ok(note_id)
+        })
+    }
+
+    /// Remove the action's activity, along with its duration, and its notes
+    /// if no other action was logged at the same time.
+    fn delete_activity(
+        &mut self,
+        activity: ActivityId,
+        action: ActionId,
+    ) -> Result<(), BoxCheckerError> {
+        self.check_action_activity(activity, action)?;
+        self.in_transaction(|boxer| {
+            for tab in &[ACTIVITY_TAB, DURATION_TAB] {
+                let query = format!(
+                    "DELETE FROM {} WHERE {} = ? AND {} = ?;",
+                    tab, TIME_COL, ACTION_COL
+                );
+                boxer.execute_with(query, &[activity, action])?;
+            }
+            boxer.move_notations(activity, None)
+        })
     }
 
-    fn log_activities(&mut self, actions: &Vec<ActionId>) -> ActivityId {
+    /// Log the actions at the same time, checking that every action exists
+    /// before logging any of them.
+    fn log_activities(&mut self, actions: &Vec<ActionId>) -> Result<ActivityId, BoxCheckerError> {
         let time_millis = get_time();
-        for a in actions {
-            self.log_activity_at_time(*a, time_millis);
-        }
-        time_millis
+        let entries: Vec<LogEntry> = actions
+            .iter()
+            .map(|action| LogEntry {
+                action: *action,
+                time: Some(time_millis),
+                ..Default::default()
+            })
+            .collect();
+        self.log_entries(&entries)?;
+        Ok(time_millis)
     }
 
-    fn log_activity(&mut self, action: ActionId) -> ActivityId {
+    fn log_activity(&mut self, action: ActionId) -> Result<ActivityId, BoxCheckerError> {
         let time_millis = get_time();
         self.log_activity_at_time(action, time_millis)
     }
 
-    fn log_activity_at_time(&mut self, action: ActionId, time_millis: i64) -> ActivityId {
-        match self.activities.insert(time_millis, action) {
-            Ok(_) => time_millis,
-            Err(e) => {
-                log::error!("log_activity_at_time: {}", e.msg);
-                0
+    fn log_activity_at_time(
+        &mut self,
+        action: ActionId,
+        time_millis: i64,
+    ) -> Result<ActivityId, BoxCheckerError> {
+        Self::check_time(time_millis)?;
+        self.check_active_action(action)?;
+        self.insert_activity(action, time_millis)?;
+        Ok(time_millis)
+    }
+
+    fn log_entry(&mut self, entry: &LogEntry) -> Result<LoggedActivity, BoxCheckerError> {
+        let mut logged = self.log_entries(std::slice::from_ref(entry))?;
+        Ok(logged.remove(0))
+    }
+
+    /// Validate every entry, then record them all in one transaction.
+    /// Entries without a time are logged at the same time, sharing any
+    /// notes.
+    fn log_entries(
+        &mut self,
+        entries: &[LogEntry],
+    ) -> Result<Vec<LoggedActivity>, BoxCheckerError> {
+        if entries.is_empty() {
+            return Err(BoxCheckerError::InvalidInput("no entries".to_string()));
+        }
+        let now = get_time();
+        let mut times = Vec::with_capacity(entries.len());
+        for entry in entries {
+            times.push(self.check_entry(entry, now)?);
+        }
+
+        self.in_transaction(|boxer| {
+            let mut logged = Vec::with_capacity(entries.len());
+            for (entry, (time_millis, end_millis)) in entries.iter().zip(times) {
+                boxer.insert_activity(entry.action, time_millis)?;
+                if end_millis.is_some() {
+                    boxer.insert_duration(entry.action, time_millis, end_millis)?;
+                }
+                let note = match &entry.note {
+                    Some(text) => {
+                        let note_id = boxer.find_or_insert_text(NOTE_TAB, NOTE_COL, text)?;
+                        let query = format!(
+                            "INSERT INTO {} ({}, {}) VALUES (?, ?);",
+                            NOTATIONS_TAB, TIME_COL, NOTE_COL
+                        );
+                        boxer.execute_with(query, &[time_millis, note_id])?;
+                        Some(note_id)
+                    }
+                    None => None,
+                };
+                logged.push(LoggedActivity {
+                    activity: time_millis,
+                    note,
+                });
+            }
+            Ok(logged)
+        })
+    }
+
+    /// Record an activity that has already finished.
+    fn log_activity_with_duration(
+        &mut self,
+        action: ActionId,
+        time_millis: i64,
+        duration_millis: i64,
+    ) -> Result<ActivityId, BoxCheckerError> {
+        Self::check_time(time_millis)?;
+        let end_millis = Self::end_time(time_millis, duration_millis)?;
+        self.check_active_action(action)?;
+        self.in_transaction(|boxer| {
+            boxer.insert_activity(action, time_millis)?;
+            boxer.insert_duration(action, time_millis, Some(end_millis))?;
+            Ok(time_millis)
+        })
+    }
+
+    fn start_activity(&mut self, action: ActionId) -> Result<ActivityId, BoxCheckerError> {
+        let time_millis = get_time();
+        self.start_activity_at_time(action, time_millis)
+    }
+
+    /// Log an activity and leave it open until stop_activity is called.
+    fn start_activity_at_time(
+        &mut self,
+        action: ActionId,
+        time_millis: i64,
+    ) -> Result<ActivityId, BoxCheckerError> {
+        Self::check_time(time_millis)?;
+        self.check_active_action(action)?;
+        self.in_transaction(|boxer| {
+            boxer.insert_activity(action, time_millis)?;
+            boxer.insert_duration(action, time_millis, None)?;
+            Ok(time_millis)
+        })
+    }
+
+    fn stop_activity(
+        &mut self,
+        activity: ActivityId,
+        action: ActionId,
+    ) -> Result<i64, BoxCheckerError> {
+        let time_millis = get_time();
+        self.stop_activity_at_time(activity, action, time_millis)
+    }
+
+    /// Close an open activity, returning its duration in milliseconds.
+    fn stop_activity_at_time(
+        &mut self,
+        activity: ActivityId,
+        action: ActionId,
+        time_millis: i64,
+    ) -> Result<i64, BoxCheckerError> {
+        Self::check_time(time_millis)?;
+        if time_millis < activity {
+            return Err(BoxCheckerError::InvalidInput(format!(
+                "activity {} stops before it starts at {}",
+                activity, time_millis
+            )));
+        }
+        match self.close_duration(activity, action, time_millis)? {
+            0 => Err(BoxCheckerError::NotFound(format!(
+                "open activity {} of action {}",
+                activity, action
+            ))),
+            _ => Ok(time_millis - activity),
+        }
+    }
+
+    /// Move the action's activity, keeping the length of its duration, and
+    /// carrying its notes along if no other action was logged at the same
+    /// time.
+    fn update_activity(
+        &mut self,
+        activity: ActivityId,
+        action: ActionId,
+        time_millis: i64,
+        new_action: ActionId,
+    ) -> Result<ActivityId, BoxCheckerError> {
+        Self::check_time(time_millis)?;
+        self.check_action_activity(activity, action)?;
+        if new_action != action {
+            self.check_active_action(new_action)?;
+        }
+        self.in_transaction(|boxer| {
+            let query = format!(
+                "UPDATE {} SET {} = ?, {} = ? WHERE {} = ? AND {} = ?;",
+                ACTIVITY_TAB, TIME_COL, ACTION_COL, TIME_COL, ACTION_COL
+            );
+            boxer.execute_with(query, &[time_millis, new_action, activity, action])?;
+            let query = format!(
+                "UPDATE {} SET {} = {} + (? - {}), {} = ?, {} = ? WHERE {} = ? AND {} = ?;",
+                DURATION_TAB,
+                END_COL,
+                END_COL,
+                TIME_COL,
+                TIME_COL,
+                ACTION_COL,
+                TIME_COL,
+                ACTION_COL
+            );
+            boxer.execute_with(
+                query,
+                &[time_millis, time_millis, new_action, activity, action],
+            )?;
+            if time_millis != activity {
+                boxer.move_notations(activity, Some(time_millis))?;
             }
+            Ok(time_millis)
+        })
+    }
+}
+
+impl<'a> GoalKeeper for SqliteBoxes<'a> {
+    fn clear_goal(&mut self, action: ActionId) -> Result<(), BoxCheckerError> {
+        let query = format!(
+            "SELECT COUNT(*) FROM {} WHERE {} = ?;",
+            GOAL_TAB, ACTION_COL
+        );
+        let mut stat = self.conn.prepare(query)?;
+        stat.bind(1, action)?;
+        stat.next()?;
+        if stat.read::<i64>(0)? == 0 {
+            return Err(BoxCheckerError::NotFound(format!(
+                "goal for action {}",
+                action
+            )));
+        }
+
+        let query = format!("DELETE FROM {} WHERE {} = ?;", GOAL_TAB, ACTION_COL);
+        let mut stat = self.conn.prepare(query)?;
+        stat.bind(1, action)?;
+        stat.next()?;
+        Ok(())
+    }
+
+    /// Set, or replace, the daily target for an action.
+    fn set_goal(
+        &mut self,
+        action: ActionId,
+        target: i64,
+        unit: GoalUnit,
+    ) -> Result<(), BoxCheckerError> {
+        if target <= 0 {
+            return Err(BoxCheckerError::InvalidInput(format!(
+                "non-positive target {}",
+                target
+            )));
+        }
+        self.check_action(action)?;
+
+        let query = format!(
+            "INSERT OR REPLACE INTO {} ({}, {}, {}) VALUES (?, ?, ?);",
+            GOAL_TAB, ACTION_COL, TARGET_COL, UNIT_COL
+        );
+        let unit_str = match unit {
+            GoalUnit::Count => "count",
+            GoalUnit::Minutes => "minutes",
+        };
+        let mut stat = self.conn.prepare(query)?;
+        stat.bind(1, action)?;
+        stat.bind(2, target)?;
+        stat.bind(3, unit_str)?;
+        stat.next()?;
+        Ok(())
+    }
+
+    fn get_goals(&self, dest: &mut Vec<Goal>) -> Result<usize, BoxCheckerError> {
+        let goals = self.load_goals(dest.len() as i64)?;
+        Ok(copy_into(goals, dest))
+    }
+
+    /// Report progress towards each goal over the time range.
+    fn get_goal_progress(
+        &self,
+        from: usize,
+        to: usize,
+        dest: &mut Vec<GoalProgress>,
+    ) -> Result<usize, BoxCheckerError> {
+        let goals = self.load_goals(dest.len() as i64)?;
+        let activities = if goals.iter().any(|g| g.unit == GoalUnit::Count) {
+            self.activities_between(from as i64, to as i64)?
+        } else {
+            Vec::new()
+        };
+
+        for (i, goal) in goals.iter().enumerate() {
+            let completed = match goal.unit {
+                GoalUnit::Count => activities
+                    .iter()
+                    .filter(|(_, action)| *action == goal.action)
+                    .count() as f64,
+                GoalUnit::Minutes => {
+                    self.total_duration(goal.action, from as i64, to as i64)? as f64
+                        / MILLIS_PER_MINUTE
+                }
+            };
+            dest[i] = GoalProgress {
+                action: goal.action,
+                target: goal.target,
+                unit: goal.unit,
+                completed,
+                percent: 100.0 * completed / goal.target as f64,
+            };
         }
+        Ok(goals.len())
     }
 }
 
 impl<'a> BoxSearcher<'a> for SqliteBoxes<'a> {
-    /// Wrap call to action lookup name, logging an error and returning ""
-    /// if necessary.
-    fn get_action_name(&self, action: ActionId) -> String {
-        match self.actions.get(action) {
-            Ok(name) => name,
-            Err(e) => {
-                log::error!("get_notations: {}", e.msg);
-                "".to_string()
-            }
+    fn get_action_name(&self, action: ActionId) -> Result<String, BoxCheckerError> {
+        self.get_text(ACTION_TAB, ACTION_COL, action)?
+            .ok_or_else(|| BoxCheckerError::NotFound(format!("action {}", action)))
+    }
+
+    /// Look up all ancestors of the action, nearest first.
+    fn get_action_ancestors(
+        &self,
+        action: ActionId,
+        dest: &mut Vec<ActionId>,
+    ) -> Result<usize, BoxCheckerError> {
+        let links = self.hierarchy_links()?;
+        Ok(copy_into(walk_links(&links, action, false), dest))
+    }
+
+    /// Wrap call to lookup direct children of the action.
+    fn get_action_children(
+        &self,
+        action: ActionId,
+        last_idx: ActionId,
+        dest: &mut Vec<ActionId>,
+    ) -> Result<usize, BoxCheckerError> {
+        self.action_hierarchy
+            .get_page(action, last_idx, dest)
+            .map_err(|e| storage_error!(e))
+    }
+
+    /// Look up all descendants of the action, nearest first.
+    fn get_action_subtree(
+        &self,
+        action: ActionId,
+        dest: &mut Vec<ActionId>,
+    ) -> Result<usize, BoxCheckerError> {
+        let links = self.hierarchy_links()?;
+        Ok(copy_into(walk_links(&links, action, true), dest))
+    }
+
+    fn get_archived_actions(&self, dest: &mut Vec<ActionId>) -> Result<usize, BoxCheckerError> {
+        let query = format!(
+            "SELECT {} FROM {} ORDER BY {} LIMIT ?;",
+            ACTION_COL, ARCHIVE_TAB, ACTION_COL
+        );
+        let mut stat = self.conn.prepare(query)?;
+        stat.bind(1, dest.len() as i64)?;
+        let mut count = 0;
+        while let State::Row = stat.next()? {
+            dest[count] = stat.read::<i64>(0)?;
+            count += 1;
         }
+        Ok(count)
     }
 
-    /// Wrap call to lookup associated notations, logging an error and
-    /// returning 0 when necessary.
+    /// Look up activities that have been started, but not stopped.
+    fn get_open_activities(
+        &self,
+        dest: &mut Vec<(ActivityId, ActionId)>,
+    ) -> Result<usize, BoxCheckerError> {
+        let query = format!(
+            "SELECT {}, {} FROM {} WHERE {} IS NULL ORDER BY {} LIMIT ?;",
+            TIME_COL, ACTION_COL, DURATION_TAB, END_COL, TIME_COL
+        );
+        let mut stat = self.conn.prepare(query)?;
+        stat.bind(1, dest.len() as i64)?;
+        let mut count = 0;
+        while let State::Row = stat.next()? {
+            dest[count] = (stat.read::<i64>(0)?, stat.read::<i64>(1)?);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Page through the ids of notes attached to the activity.
     fn get_notations(
         &self,
         activity: ActivityId,
         last_idx: AnnotationId,
         dest: &mut Vec<AnnotationId>,
-    ) -> usize {
-        match self.notations.get_page(activity, last_idx, dest) {
-            Ok(count) => count,
-            Err(e) => {
-                log::error!("get_notations: {}", e.msg);
-                0
-            }
+    ) -> Result<usize, BoxCheckerError> {
+        let query = format!(
+            "SELECT {} FROM {} WHERE {} = ? AND {} > ? ORDER BY {} LIMIT ?;",
+            NOTE_COL, NOTATIONS_TAB, TIME_COL, NOTE_COL, NOTE_COL
+        );
+        let mut stat = self.conn.prepare(query)?;
+        stat.bind(1, activity)?;
+        stat.bind(2, last_idx)?;
+        stat.bind(3, dest.len() as i64)?;
+        let mut count = 0;
+        while let State::Row = stat.next()? {
+            dest[count] = stat.read::<i64>(0)?;
+            count += 1;
         }
+        Ok(count)
     }
 
-    fn get_note(&self, annotation: AnnotationId) -> String {
-        match self.notes.get(annotation) {
-            Ok(note) => note,
-            Err(e) => {
-                log::error!("get_note: {}", e.msg);
-                "".to_string()
-            }
+    fn get_note(&self, annotation: AnnotationId) -> Result<String, BoxCheckerError> {
+        self.get_text(NOTE_TAB, NOTE_COL, annotation)?
+            .ok_or_else(|| BoxCheckerError::NotFound(format!("note {}", annotation)))
+    }
+
+    /// Look up notes attached to the activity, with their fields, in id order.
+    fn get_notes(
+        &self,
+        activity: ActivityId,
+        last_idx: AnnotationId,
+        dest: &mut Vec<Note>,
+    ) -> Result<usize, BoxCheckerError> {
+        let mut ids = vec![0; dest.len()];
+        let count = self.get_notations(activity, last_idx, &mut ids)?;
+        ids.truncate(count);
+        let notes = self.load_notes(activity, &ids)?;
+        Ok(copy_into(notes, dest))
+    }
+
+    /// Look up the named fields attached with the note to the activity.
+    fn get_note_fields(
+        &self,
+        activity: ActivityId,
+        annotation: AnnotationId,
+        dest: &mut Vec<(String, String)>,
+    ) -> Result<usize, BoxCheckerError> {
+        let query = format!(
+            "SELECT {}, {} FROM {} WHERE {} = ? AND {} = ? ORDER BY {} LIMIT ?;",
+            NAME_COL, VALUE_COL, NOTE_FIELDS_TAB, TIME_COL, NOTE_COL, NAME_COL
+        );
+        let mut stat = self.conn.prepare(query)?;
+        stat.bind(1, activity)?;
+        stat.bind(2, annotation)?;
+        stat.bind(3, dest.len() as i64)?;
+        let mut count = 0;
+        while let State::Row = stat.next()? {
+            dest[count] = (stat.read::<String>(0)?, stat.read::<String>(1)?);
+            count += 1;
         }
+        Ok(count)
     }
 
     fn get_note_bulk(
         &self,
         ids: &Vec<AnnotationId>,
         dest: &mut Vec<(AnnotationId, String)>,
-    ) -> usize {
-        match self.notes.get_bulk(ids, dest) {
-            Ok(count) => count,
-            Err(e) => {
-                log::error!("get_note_bulk: {}", e.msg);
-                0
+    ) -> Result<usize, BoxCheckerError> {
+        let mut count = 0;
+        for id in ids {
+            if count == dest.len() {
+                break;
+            }
+            if let Some(text) = self.get_text(NOTE_TAB, NOTE_COL, *id)? {
+                dest[count] = (*id, text);
+                count += 1;
             }
         }
+        Ok(count)
     }
 
+    /// Page through action names containing the substring, skipping
+    /// archived actions.
     fn search_action_names(
         &self,
         substr: &str,
         last_id: ActionId,
         dest: &mut Vec<(ActionId, String)>,
-    ) -> usize {
-        match self.actions.search_page(substr, last_id, dest) {
-            Ok(count) => count,
-            Err(e) => {
-                log::error!("search_action_names: {}", e.msg);
-                0
+    ) -> Result<usize, BoxCheckerError> {
+        let query = format!(
+            "SELECT rowid, {} FROM {}
+                WHERE {} LIKE '%' || ? || '%' AND rowid > ?
+                    AND rowid NOT IN (SELECT {} FROM {})
+                ORDER BY rowid LIMIT ?;",
+            ACTION_COL, ACTION_TAB, ACTION_COL, ACTION_COL, ARCHIVE_TAB
+        );
+        let mut stat = self.conn.prepare(query)?;
+        stat.bind(1, substr)?;
+        stat.bind(2, last_id)?;
+        stat.bind(3, dest.len() as i64)?;
+        let mut count = 0;
+        while let State::Row = stat.next()? {
+            dest[count] = (stat.read::<i64>(0)?, stat.read::<String>(1)?);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Compute daily streaks for every action with a goal or a logged activity,
+    /// splitting days at local midnight in the timezone.
+    fn get_streaks(
+        &self,
+        tz: &Tz,
+        now_millis: i64,
+        dest: &mut Vec<Streak>,
+    ) -> Result<usize, BoxCheckerError> {
+        let goals = self.load_goals(-1)?;
+        let activities = self.activities_between(0, now_millis)?;
+        let durations = self.durations_between(0, now_millis)?;
+
+        let today = local_date(tz, now_millis).ok_or_else(|| {
+            BoxCheckerError::InvalidInput(format!("time {} out of range", now_millis))
+        })?;
+
+        // Tally activity counts and milliseconds for each action and day.
+        // Times are range checked when logged, but older boxes may hold
+        // times without a date, which are skipped.
+        let mut days: BTreeMap<ActionId, BTreeMap<NaiveDate, (i64, i64)>> = BTreeMap::new();
+        for goal in goals.iter() {
+            days.entry(goal.action).or_default();
+        }
+        for (time, action) in activities {
+            let tallies = days.entry(action).or_default();
+            if let Some(day) = local_date(tz, time) {
+                tallies.entry(day).or_default().0 += 1;
+            }
+        }
+        for (time, action, millis) in durations {
+            let tallies = days.entry(action).or_default();
+            if let Some(day) = local_date(tz, time) {
+                tallies.entry(day).or_default().1 += millis;
+            }
+        }
+
+        let mut count = 0;
+        for (action, tallies) in days.iter().take(dest.len()) {
+            let goal = goals.iter().find(|g| g.action == *action);
+            let completed: BTreeSet<NaiveDate> = tallies
+                .iter()
+                .filter(|(_, (activities, millis))| match goal {
+                    Some(Goal {
+                        unit: GoalUnit::Count,
+                        target,
+                        ..
+                    }) => activities >= target,
+                    Some(Goal {
+                        unit: GoalUnit::Minutes,
+                        target,
+                        ..
+                    }) => *millis as f64 >= *target as f64 * MILLIS_PER_MINUTE,
+                    None => *activities > 0,
+                })
+                .map(|(day, _)| *day)
+                .collect();
+            dest[count] = get_streak(*action, &completed, today);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Total the time spent on each action started within the time range,
+    /// counting time spent on descendants towards their ancestors.
+    fn get_rollup_by_time(
+        &self,
+        from: usize,
+        to: usize,
+        dest: &mut Vec<Rollup>,
+    ) -> Result<usize, BoxCheckerError> {
+        let links = self.hierarchy_links()?;
+        let durations = self.durations_between(from as i64, to as i64)?;
+
+        let mut own: BTreeMap<ActionId, i64> = BTreeMap::new();
+        for (_, action, millis) in durations {
+            *own.entry(action).or_default() += millis;
+        }
+        let mut totals: BTreeMap<ActionId, Rollup> = BTreeMap::new();
+        for (action, millis) in own.iter() {
+            totals.entry(*action).or_default().own_millis = *millis;
+            for a in std::iter::once(*action).chain(walk_links(&links, *action, false)) {
+                let rollup = totals.entry(a).or_default();
+                rollup.action = a;
+                rollup.total_millis += millis;
             }
         }
+        Ok(copy_into(totals.into_values().collect(), dest))
+    }
+
+    /// Collect activities logged within the time range, with durations and
+    /// notes, in time order.
+    fn get_journal(
+        &self,
+        from: usize,
+        to: usize,
+        dest: &mut Vec<JournalEntry>,
+    ) -> Result<usize, BoxCheckerError> {
+        let entries = self.journal_between(from as i64, to as i64, dest.len())?;
+        let count = entries.len();
+        for (slot, entry) in dest.iter_mut().zip(entries) {
+            *slot = entry;
+        }
+        Ok(count)
     }
 
     fn search_activity_by_time(
@@ -180,14 +1495,79 @@ impl<'a> BoxSearcher<'a> for SqliteBoxes<'a> {
         from: usize,
         to: usize,
         dest: &mut Vec<(ActivityId, ActionId)>,
-    ) -> usize {
-        match self.activities.page_left(from as i64, to as i64, dest) {
-            Ok(count) => count,
-            Err(e) => {
-                log::error!("search_activity_by_time: {}", e.msg);
-                0
-            }
+    ) -> Result<usize, BoxCheckerError> {
+        let query = format!(
+            "SELECT {}, {} FROM {} WHERE {} >= ? AND {} <= ? ORDER BY {}, rowid LIMIT ?;",
+            TIME_COL, ACTION_COL, ACTIVITY_TAB, TIME_COL, TIME_COL, TIME_COL
+        );
+        let mut stat = self.conn.prepare(query)?;
+        stat.bind(1, from as i64)?;
+        stat.bind(2, to as i64)?;
+        stat.bind(3, dest.len() as i64)?;
+        let mut count = 0;
+        while let State::Row = stat.next()? {
+            dest[count] = (stat.read::<i64>(0)?, stat.read::<i64>(1)?);
+            count += 1;
         }
+        Ok(count)
+    }
+
+    /// Look up the durations, in milliseconds, of stopped activities started
+    /// within the time range.
+    fn search_durations_by_time(
+        &self,
+        from: usize,
+        to: usize,
+        dest: &mut Vec<(ActivityId, ActionId, i64)>,
+    ) -> Result<usize, BoxCheckerError> {
+        let query = format!(
+            "SELECT {}, {}, {} - {} FROM {} WHERE {} >= ? AND {} <= ? AND {} IS NOT NULL ORDER BY {} LIMIT ?;",
+            TIME_COL, ACTION_COL, END_COL, TIME_COL, DURATION_TAB, TIME_COL, TIME_COL, END_COL, TIME_COL
+        );
+        let mut stat = self.conn.prepare(query)?;
+        stat.bind(1, from as i64)?;
+        stat.bind(2, to as i64)?;
+        stat.bind(3, dest.len() as i64)?;
+        let mut count = 0;
+        while let State::Row = stat.next()? {
+            dest[count] = (
+                stat.read::<i64>(0)?,
+                stat.read::<i64>(1)?,
+                stat.read::<i64>(2)?,
+            );
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Collect every action, archived or not, link, activity and goal.
+    fn export(&self) -> Result<BoxExport, BoxCheckerError> {
+        let query = format!(
+            "SELECT rowid, {}, rowid IN (SELECT {} FROM {}) FROM {} ORDER BY rowid;",
+            ACTION_COL, ACTION_COL, ARCHIVE_TAB, ACTION_TAB
+        );
+        let mut stat = self.conn.prepare(query)?;
+        let mut actions = Vec::new();
+        while let State::Row = stat.next()? {
+            actions.push(ExportedAction {
+                id: stat.read::<i64>(0)?,
+                name: stat.read::<String>(1)?,
+                archived: stat.read::<i64>(2)? != 0,
+            });
+        }
+
+        let hierarchy = self
+            .hierarchy_links()?
+            .into_iter()
+            .map(|(parent, child)| ActionLink { parent, child })
+            .collect();
+
+        Ok(BoxExport {
+            actions,
+            hierarchy,
+            activities: self.journal_between(EARLIEST_MILLIS, LATEST_MILLIS, usize::MAX)?,
+            goals: self.load_goals(-1)?,
+        })
     }
 }
 
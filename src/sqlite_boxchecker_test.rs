@@ -8,8 +8,8 @@ fn creates_instance() {
 #[test]
 fn logs_activity() {
     let mut boxer = SqliteBoxes::new(":memory:");
-    let action = boxer.create_action("unit testing");
-    let activity = boxer.log_activity(action);
+    let action = boxer.create_action("unit testing").unwrap();
+    let activity = boxer.log_activity(action).unwrap();
     assert!(activity > 0);
 }
 
@@ -17,42 +17,712 @@ fn logs_activity() {
 fn logs_activities() {
     let mut boxer = SqliteBoxes::new(":memory:");
     let actions = vec![
-        boxer.create_action("unit testing"),
-        boxer.create_action("linting"),
+        boxer.create_action("unit testing").unwrap(),
+        boxer.create_action("linting").unwrap(),
     ];
-    let activity = boxer.log_activities(&actions);
+    let activity = boxer.log_activities(&actions).unwrap();
     assert!(activity > 0);
 }
 
 #[test]
 fn annotates_activities() {
     let mut boxer = SqliteBoxes::new(":memory:");
-    let action = boxer.create_action("unit testing");
-    let activity = boxer.log_activity(action);
-    let note = boxer.annotate_activity(activity, "this one passes");
+    let action = boxer.create_action("unit testing").unwrap();
+    let activity = boxer.log_activity(action).unwrap();
+    let note = boxer
+        .annotate_activity(activity, "this one passes")
+        .unwrap();
     assert!(note > 0);
 }
 
 #[test]
 fn retrieves_action_name() {
     let mut boxer = SqliteBoxes::new(":memory:");
-    let action = boxer.create_action("unit testing");
-    let name = boxer.get_action_name(action);
+    let action = boxer.create_action("unit testing").unwrap();
+    let name = boxer.get_action_name(action).unwrap();
     assert_eq!(name, "unit testing");
 }
 
 #[test]
 fn retrieves_notations() {
     let mut boxer = SqliteBoxes::new(":memory:");
-    let action = boxer.create_action("unit testing");
-    let activity = boxer.log_activity(action);
-    let note = boxer.annotate_activity(activity, "this one passes");
+    let action = boxer.create_action("unit testing").unwrap();
+    let activity = boxer.log_activity(action).unwrap();
+    let note = boxer
+        .annotate_activity(activity, "this one passes")
+        .unwrap();
 
     let mut notations = vec![0; 2];
     let mut notes = vec![(0, "".to_string()); 2];
-    assert_eq!(boxer.get_notations(activity, 0, &mut notations), 1);
+    assert_eq!(boxer.get_notations(activity, 0, &mut notations), Ok(1));
     assert_eq!(notations[0], note);
-    assert_eq!(boxer.get_note(note), "this one passes");
-    assert_eq!(boxer.get_note_bulk(&vec![note], &mut notes), 1);
+    assert_eq!(boxer.get_note(note), Ok("this one passes".to_string()));
+    assert_eq!(boxer.get_note_bulk(&vec![note], &mut notes), Ok(1));
     assert_eq!(notes[0], (1, "this one passes".to_string()));
 }
+
+#[test]
+fn starts_and_stops_activity() {
+    let mut boxer = SqliteBoxes::new(":memory:");
+    let action = boxer.create_action("unit testing").unwrap();
+    let activity = boxer.start_activity_at_time(action, 1000).unwrap();
+    assert_eq!(activity, 1000);
+
+    let mut open = vec![(0, 0); 2];
+    assert_eq!(boxer.get_open_activities(&mut open), Ok(1));
+    assert_eq!(open[0], (activity, action));
+
+    assert_eq!(
+        boxer.stop_activity_at_time(activity, action, 4000),
+        Ok(3000)
+    );
+    assert_eq!(boxer.get_open_activities(&mut open), Ok(0));
+    assert!(matches!(
+        boxer.stop_activity_at_time(activity, action, 5000),
+        Err(BoxCheckerError::NotFound(_))
+    ));
+
+    let mut durations = vec![(0, 0, 0); 2];
+    assert_eq!(
+        boxer.search_durations_by_time(0, 2000, &mut durations),
+        Ok(1)
+    );
+    assert_eq!(durations[0], (activity, action, 3000));
+}
+
+#[test]
+fn stops_only_the_given_action() {
+    let mut boxer = SqliteBoxes::new(":memory:");
+    let scales = boxer.create_action("scales").unwrap();
+    let pieces = boxer.create_action("pieces").unwrap();
+    let activity = boxer.start_activity_at_time(scales, 1000).unwrap();
+    assert_eq!(boxer.start_activity_at_time(pieces, 1000), Ok(activity));
+
+    assert_eq!(
+        boxer.stop_activity_at_time(activity, scales, 4000),
+        Ok(3000)
+    );
+    let mut open = vec![(0, 0); 2];
+    assert_eq!(boxer.get_open_activities(&mut open), Ok(1));
+    assert_eq!(open[0], (activity, pieces));
+    assert_eq!(
+        boxer.stop_activity_at_time(activity, pieces, 6000),
+        Ok(5000)
+    );
+}
+
+#[test]
+fn logs_activity_with_duration() {
+    let mut boxer = SqliteBoxes::new(":memory:");
+    let action = boxer.create_action("unit testing").unwrap();
+    let activity = boxer.log_activity_with_duration(action, 1000, 500).unwrap();
+    assert_eq!(activity, 1000);
+    assert!(matches!(
+        boxer.log_activity_with_duration(action, 2000, -1),
+        Err(BoxCheckerError::InvalidInput(_))
+    ));
+    assert!(matches!(
+        boxer.log_activity_with_duration(action, 2000, i64::MAX),
+        Err(BoxCheckerError::InvalidInput(_))
+    ));
+
+    let mut durations = vec![(0, 0, 0); 2];
+    assert_eq!(
+        boxer.search_durations_by_time(0, 3000, &mut durations),
+        Ok(1)
+    );
+    assert_eq!(durations[0], (activity, action, 500));
+}
+
+#[test]
+fn rejects_stopping_before_start() {
+    let mut boxer = SqliteBoxes::new(":memory:");
+    let action = boxer.create_action("unit testing").unwrap();
+    let activity = boxer.start_activity_at_time(action, 1000).unwrap();
+    assert!(matches!(
+        boxer.stop_activity_at_time(activity, action, 999),
+        Err(BoxCheckerError::InvalidInput(_))
+    ));
+
+    let mut open = vec![(0, 0); 2];
+    assert_eq!(boxer.get_open_activities(&mut open), Ok(1));
+}
+
+#[test]
+fn sets_and_clears_goals() {
+    let mut boxer = SqliteBoxes::new(":memory:");
+    let action = boxer.create_action("scales").unwrap();
+    assert!(matches!(
+        boxer.set_goal(action, 0, GoalUnit::Minutes),
+        Err(BoxCheckerError::InvalidInput(_))
+    ));
+    boxer.set_goal(action, 20, GoalUnit::Minutes).unwrap();
+    boxer.set_goal(action, 30, GoalUnit::Minutes).unwrap();
+
+    let mut goals = vec![Goal::default(); 2];
+    assert_eq!(boxer.get_goals(&mut goals), Ok(1));
+    assert_eq!(
+        goals[0],
+        Goal {
+            action,
+            target: 30,
+            unit: GoalUnit::Minutes
+        }
+    );
+
+    boxer.clear_goal(action).unwrap();
+    assert_eq!(boxer.get_goals(&mut goals), Ok(0));
+}
+
+#[test]
+fn reports_goal_progress() {
+    let mut boxer = SqliteBoxes::new(":memory:");
+    let scales = boxer.create_action("scales").unwrap();
+    let sits = boxer.create_action("meditation").unwrap();
+    boxer.set_goal(scales, 30, GoalUnit::Minutes).unwrap();
+    boxer.set_goal(sits, 3, GoalUnit::Count).unwrap();
+
+    boxer
+        .log_activity_with_duration(scales, 1000, 15 * 60 * 1000)
+        .unwrap();
+    boxer.log_activity_at_time(sits, 2000).unwrap();
+    boxer.log_activity_at_time(sits, 3000).unwrap();
+    boxer.log_activity_at_time(sits, 5000).unwrap();
+
+    let mut progress = vec![GoalProgress::default(); 3];
+    assert_eq!(boxer.get_goal_progress(0, 4000, &mut progress), Ok(2));
+    assert_eq!(progress[0].action, scales);
+    assert_eq!(progress[0].completed, 15.0);
+    assert_eq!(progress[0].percent, 50.0);
+    assert_eq!(progress[1].action, sits);
+    assert_eq!(progress[1].completed, 2.0);
+    assert_eq!(progress[1].target, 3);
+}
+
+#[test]
+fn computes_streaks() {
+    let mut boxer = SqliteBoxes::new(":memory:");
+    let scales = boxer.create_action("scales").unwrap();
+    let sits = boxer.create_action("meditation").unwrap();
+    boxer.set_goal(sits, 2, GoalUnit::Count).unwrap();
+
+    // Days 0, 1, 2, then 4 and 5, with today being day 6.
+    let day = 24 * 60 * 60 * 1000;
+    for d in &[0, 1, 2, 4, 5] {
+        boxer.log_activity_at_time(scales, d * day + 1000).unwrap();
+    }
+    // Only day 5 meets the meditation goal.
+    boxer.log_activity_at_time(sits, 4 * day + 1000).unwrap();
+    boxer.log_activity_at_time(sits, 5 * day + 1000).unwrap();
+    boxer.log_activity_at_time(sits, 5 * day + 2000).unwrap();
+
+    let mut streaks = vec![Streak::default(); 3];
+    assert_eq!(
+        boxer.get_streaks(&Tz::UTC, 6 * day + 1000, &mut streaks),
+        Ok(2)
+    );
+    assert_eq!(
+        streaks[0],
+        Streak {
+            action: scales,
+            current: 2,
+            longest: 3,
+            last_completed: Some("1970-01-06".to_string())
+        }
+    );
+    assert_eq!(streaks[1].current, 1);
+    assert_eq!(streaks[1].longest, 1);
+
+    // A missed day breaks the current streak.
+    assert_eq!(
+        boxer.get_streaks(&Tz::UTC, 7 * day + 1000, &mut streaks),
+        Ok(2)
+    );
+    assert_eq!(streaks[0].current, 0);
+    assert_eq!(streaks[0].longest, 3);
+
+    // Times without a date are refused rather than breaking streaks.
+    assert!(matches!(
+        boxer.log_activity_at_time(scales, i64::MAX),
+        Err(BoxCheckerError::InvalidInput(_))
+    ));
+    let entry = LogEntry {
+        action: scales,
+        time: Some(i64::MIN),
+        ..LogEntry::default()
+    };
+    assert!(matches!(
+        boxer.log_entry(&entry),
+        Err(BoxCheckerError::InvalidInput(_))
+    ));
+    assert!(matches!(
+        boxer.update_activity(day + 1000, scales, i64::MAX, scales),
+        Err(BoxCheckerError::InvalidInput(_))
+    ));
+    assert!(matches!(
+        boxer.get_streaks(&Tz::UTC, i64::MAX, &mut streaks),
+        Err(BoxCheckerError::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn computes_streaks_in_timezone() {
+    let mut boxer = SqliteBoxes::new(":memory:");
+    let scales = boxer.create_action("scales").unwrap();
+    let hour = 60 * 60 * 1000;
+
+    // 23:00 and 01:00 UTC on consecutive days both fall on the same day
+    // in Chicago.
+    boxer.log_activity_at_time(scales, 23 * hour).unwrap();
+    boxer.log_activity_at_time(scales, 25 * hour).unwrap();
+
+    let mut streaks = vec![Streak::default(); 1];
+    boxer
+        .get_streaks(&Tz::UTC, 26 * hour, &mut streaks)
+        .unwrap();
+    assert_eq!(streaks[0].longest, 2);
+    boxer
+        .get_streaks(&Tz::America__Chicago, 26 * hour, &mut streaks)
+        .unwrap();
+    assert_eq!(streaks[0].longest, 1);
+    assert_eq!(streaks[0].last_completed, Some("1970-01-01".to_string()));
+}
+
+#[test]
+fn queries_action_hierarchy() {
+    let mut boxer = SqliteBoxes::new(":memory:");
+    let music = boxer.create_action("music").unwrap();
+    let piano = boxer.create_action("piano").unwrap();
+    let scales = boxer.create_action("scales").unwrap();
+    let pieces = boxer.create_action("pieces").unwrap();
+    boxer.make_action_parent_of(music, piano).unwrap();
+    boxer.make_action_parent_of(piano, scales).unwrap();
+    boxer.make_action_parent_of(piano, pieces).unwrap();
+
+    let mut dest = vec![0; 4];
+    assert_eq!(boxer.get_action_children(piano, 0, &mut dest), Ok(2));
+    assert_eq!(dest[..2], [scales, pieces]);
+    assert_eq!(boxer.get_action_ancestors(scales, &mut dest), Ok(2));
+    assert_eq!(dest[..2], [piano, music]);
+    assert_eq!(boxer.get_action_subtree(music, &mut dest), Ok(3));
+    assert_eq!(dest[..3], [piano, scales, pieces]);
+}
+
+#[test]
+fn rejects_action_hierarchy_cycles() {
+    let mut boxer = SqliteBoxes::new(":memory:");
+    let music = boxer.create_action("music").unwrap();
+    let piano = boxer.create_action("piano").unwrap();
+    let scales = boxer.create_action("scales").unwrap();
+    boxer.make_action_parent_of(music, piano).unwrap();
+    boxer.make_action_parent_of(piano, scales).unwrap();
+
+    assert!(matches!(
+        boxer.make_action_parent_of(scales, music),
+        Err(BoxCheckerError::Conflict(_))
+    ));
+    assert!(matches!(
+        boxer.make_action_parent_of(piano, piano),
+        Err(BoxCheckerError::InvalidInput(_))
+    ));
+    let mut dest = vec![0; 4];
+    assert_eq!(boxer.get_action_children(scales, 0, &mut dest), Ok(0));
+}
+
+#[test]
+fn rolls_up_time_through_hierarchy() {
+    let mut boxer = SqliteBoxes::new(":memory:");
+    let music = boxer.create_action("music").unwrap();
+    let piano = boxer.create_action("piano").unwrap();
+    let scales = boxer.create_action("scales").unwrap();
+    let running = boxer.create_action("running").unwrap();
+    boxer.make_action_parent_of(music, piano).unwrap();
+    boxer.make_action_parent_of(piano, scales).unwrap();
+
+    boxer.log_activity_with_duration(scales, 1000, 300).unwrap();
+    boxer.log_activity_with_duration(piano, 2000, 200).unwrap();
+    boxer
+        .log_activity_with_duration(running, 3000, 100)
+        .unwrap();
+    boxer
+        .log_activity_with_duration(scales, 9000, 1000)
+        .unwrap();
+
+    let mut dest = vec![Rollup::default(); 5];
+    assert_eq!(boxer.get_rollup_by_time(0, 5000, &mut dest), Ok(4));
+    let total = |action| dest.iter().find(|r| r.action == action).unwrap().clone();
+    assert_eq!(total(scales).total_millis, 300);
+    assert_eq!(total(piano).own_millis, 200);
+    assert_eq!(total(piano).total_millis, 500);
+    assert_eq!(total(music).own_millis, 0);
+    assert_eq!(total(music).total_millis, 500);
+    assert_eq!(total(running).total_millis, 100);
+}
+
+#[test]
+fn reports_missing_records() {
+    let mut boxer = SqliteBoxes::new(":memory:");
+    assert!(matches!(
+        boxer.get_action_name(1),
+        Err(BoxCheckerError::NotFound(_))
+    ));
+    assert!(matches!(
+        boxer.log_activity(1),
+        Err(BoxCheckerError::NotFound(_))
+    ));
+    assert!(matches!(
+        boxer.clear_goal(1),
+        Err(BoxCheckerError::NotFound(_))
+    ));
+    assert!(matches!(
+        boxer.create_action(""),
+        Err(BoxCheckerError::InvalidInput(_))
+    ));
+}
+
+/// Changes spanning tables need a file, as each table keeps its own
+/// connection.
+#[test]
+fn renames_and_archives_actions() {
+    let mut boxer = SqliteBoxes::new(":memory:");
+    let scales = boxer.create_action("scals").unwrap();
+    let pieces = boxer.create_action("pieces").unwrap();
+    boxer.rename_action(scales, "scales").unwrap();
+    assert_eq!(boxer.get_action_name(scales), Ok("scales".to_string()));
+    assert!(matches!(
+        boxer.rename_action(scales, "pieces"),
+        Err(BoxCheckerError::Conflict(_))
+    ));
+
+    boxer.archive_action(pieces, true).unwrap();
+    let mut names = vec![(0, "".to_string()); 2];
+    assert_eq!(boxer.search_action_names("%", 0, &mut names), Ok(1));
+    assert_eq!(names[0], (scales, "scales".to_string()));
+    let mut archived = vec![0; 2];
+    assert_eq!(boxer.get_archived_actions(&mut archived), Ok(1));
+    assert_eq!(archived[0], pieces);
+    assert!(matches!(
+        boxer.log_activity_at_time(pieces, 1000),
+        Err(BoxCheckerError::Conflict(_))
+    ));
+
+    boxer.archive_action(pieces, false).unwrap();
+    assert_eq!(boxer.log_activity_at_time(pieces, 1000), Ok(1000));
+}
+
+#[test]
+fn edits_and_deletes_activities() {
+    let mut boxer = SqliteBoxes::new(":memory:");
+    let scales = boxer.create_action("scales").unwrap();
+    let pieces = boxer.create_action("pieces").unwrap();
+    let activity = boxer.log_activity_with_duration(scales, 1000, 500).unwrap();
+    let note = boxer.annotate_activity(activity, "too fast").unwrap();
+
+    assert_eq!(
+        boxer.update_activity(activity, scales, 3000, pieces),
+        Ok(3000)
+    );
+    let mut durations = vec![(0, 0, 0); 2];
+    assert_eq!(
+        boxer.search_durations_by_time(0, 5000, &mut durations),
+        Ok(1)
+    );
+    assert_eq!(durations[0], (3000, pieces, 500));
+    let mut notations = vec![0; 2];
+    assert_eq!(boxer.get_notations(3000, 0, &mut notations), Ok(1));
+    assert_eq!(notations[0], note);
+    assert!(matches!(
+        boxer.update_activity(activity, scales, 3000, pieces),
+        Err(BoxCheckerError::NotFound(_))
+    ));
+
+    boxer.delete_activity(3000, pieces).unwrap();
+    let mut activities = vec![(0, 0); 2];
+    assert_eq!(
+        boxer.search_activity_by_time(0, 5000, &mut activities),
+        Ok(0)
+    );
+    assert_eq!(
+        boxer.search_durations_by_time(0, 5000, &mut durations),
+        Ok(0)
+    );
+    assert_eq!(boxer.get_notations(3000, 0, &mut notations), Ok(0));
+    assert!(matches!(
+        boxer.get_note(note),
+        Err(BoxCheckerError::NotFound(_))
+    ));
+}
+
+#[test]
+fn edits_and_deletes_annotations() {
+    let mut boxer = SqliteBoxes::new(":memory:");
+    let action = boxer.create_action("scales").unwrap();
+    let activity = boxer.log_activity_at_time(action, 1000).unwrap();
+    let note = boxer.annotate_activity(activity, "to fast").unwrap();
+
+    let edited = boxer.edit_annotation(activity, note, "too fast").unwrap();
+    assert_eq!(boxer.get_note(edited), Ok("too fast".to_string()));
+    let mut notations = vec![0; 2];
+    assert_eq!(boxer.get_notations(activity, 0, &mut notations), Ok(1));
+    assert_eq!(notations[0], edited);
+
+    assert!(matches!(
+        boxer.delete_annotation(activity, note),
+        Err(BoxCheckerError::NotFound(_))
+    ));
+    boxer.delete_annotation(activity, edited).unwrap();
+    assert_eq!(boxer.get_notations(activity, 0, &mut notations), Ok(0));
+}
+
+#[test]
+fn logs_entries_after_the_fact() {
+    let mut boxer = SqliteBoxes::new(":memory:");
+    let action = boxer.create_action("scales").unwrap();
+    let logged = boxer
+        .log_entry(&LogEntry {
+            action,
+            local_time: Some("2021-10-01T07:30".to_string()),
+            tz: Some("America/Chicago".to_string()),
+            duration: Some(1800000),
+            note: Some("metronome at 80".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(logged.activity, 1633091400000);
+    assert_eq!(
+        boxer.get_note(logged.note.unwrap()),
+        Ok("metronome at 80".to_string())
+    );
+    let mut durations = vec![(0, 0, 0); 2];
+    assert_eq!(
+        boxer.search_durations_by_time(0, 1633091400000, &mut durations),
+        Ok(1)
+    );
+    assert_eq!(durations[0], (logged.activity, action, 1800000));
+
+    let entry = LogEntry {
+        action,
+        time: Some(1000),
+        ..Default::default()
+    };
+    assert_eq!(
+        boxer.log_entry(&entry),
+        Ok(LoggedActivity {
+            activity: 1000,
+            note: None
+        })
+    );
+    for bad in &[
+        LogEntry {
+            local_time: Some("2021-10-01T07:30".to_string()),
+            ..entry.clone()
+        },
+        LogEntry {
+            duration: Some(-1),
+            ..entry.clone()
+        },
+        LogEntry {
+            duration: Some(i64::MAX),
+            ..entry.clone()
+        },
+        LogEntry {
+            time: None,
+            local_time: Some("2021-10-01T07:30".to_string()),
+            tz: Some("Mars/Olympus_Mons".to_string()),
+            ..entry.clone()
+        },
+    ] {
+        assert!(matches!(
+            boxer.log_entry(bad),
+            Err(BoxCheckerError::InvalidInput(_))
+        ));
+    }
+    let mut activities = vec![(0, 0); 3];
+    assert_eq!(
+        boxer.search_activity_by_time(0, i64::MAX as usize, &mut activities),
+        Ok(2)
+    );
+}
+
+#[test]
+fn annotates_with_fields() {
+    let mut boxer = SqliteBoxes::new(":memory:");
+    let action = boxer.create_action("scales").unwrap();
+    let activity = boxer.log_activity_at_time(action, 1000).unwrap();
+    let mut fields = BTreeMap::new();
+    fields.insert("tempo".to_string(), "80".to_string());
+    fields.insert("key".to_string(), "B flat".to_string());
+    let note = boxer
+        .annotate_activity_with_fields(activity, "rushed / uneven?", &fields)
+        .unwrap();
+    assert_eq!(boxer.get_note(note), Ok("rushed / uneven?".to_string()));
+
+    let mut dest = vec![("".to_string(), "".to_string()); 3];
+    assert_eq!(boxer.get_note_fields(activity, note, &mut dest), Ok(2));
+    assert_eq!(dest[0], ("key".to_string(), "B flat".to_string()));
+    assert_eq!(dest[1], ("tempo".to_string(), "80".to_string()));
+
+    let edited = boxer.edit_annotation(activity, note, "rushed").unwrap();
+    assert_eq!(boxer.get_note_fields(activity, edited, &mut dest), Ok(2));
+    boxer.delete_annotation(activity, edited).unwrap();
+    assert_eq!(boxer.get_note_fields(activity, edited, &mut dest), Ok(0));
+
+    fields.insert(" ".to_string(), "blank".to_string());
+    assert!(matches!(
+        boxer.annotate_activity_with_fields(activity, "rushed", &fields),
+        Err(BoxCheckerError::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn logs_entry_batches_atomically() {
+    let mut boxer = SqliteBoxes::new(":memory:");
+    let scales = boxer.create_action("scales").unwrap();
+    let pieces = boxer.create_action("pieces").unwrap();
+    let logged = boxer
+        .log_entries(&[
+            LogEntry {
+                action: scales,
+                time: Some(1000),
+                duration: Some(600),
+                ..Default::default()
+            },
+            LogEntry {
+                action: pieces,
+                time: Some(2000),
+                note: Some("sight reading".to_string()),
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+    assert_eq!(logged.len(), 2);
+    assert_eq!(logged[0].activity, 1000);
+    assert_eq!(
+        boxer.get_note(logged[1].note.unwrap()),
+        Ok("sight reading".to_string())
+    );
+    let mut notations = vec![0; 2];
+    assert_eq!(boxer.get_notations(2000, 0, &mut notations), Ok(1));
+
+    let missing = LogEntry {
+        action: pieces + 1,
+        time: Some(4000),
+        ..Default::default()
+    };
+    let valid = LogEntry {
+        action: scales,
+        time: Some(3000),
+        ..Default::default()
+    };
+    assert!(matches!(
+        boxer.log_entries(&[valid, missing]),
+        Err(BoxCheckerError::NotFound(_))
+    ));
+    let mut activities = vec![(0, 0); 4];
+    assert_eq!(
+        boxer.search_activity_by_time(0, 5000, &mut activities),
+        Ok(2)
+    );
+    assert!(matches!(
+        boxer.log_entries(&[]),
+        Err(BoxCheckerError::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn reads_notes_and_journal() {
+    let mut boxer = SqliteBoxes::new(":memory:");
+    let scales = boxer.create_action("scales").unwrap();
+    let pieces = boxer.create_action("pieces").unwrap();
+    let mut fields = BTreeMap::new();
+    fields.insert("tempo".to_string(), "80".to_string());
+    let timed = boxer.log_activity_with_duration(scales, 1000, 600).unwrap();
+    let rushed = boxer
+        .annotate_activity_with_fields(timed, "rushed", &fields)
+        .unwrap();
+    let even = boxer.annotate_activity(timed, "left hand even").unwrap();
+    boxer.log_activity_at_time(pieces, 2000).unwrap();
+    boxer.log_activity_at_time(pieces, 9000).unwrap();
+
+    let mut notes = vec![Note::default(); 3];
+    assert_eq!(boxer.get_notes(timed, 0, &mut notes), Ok(2));
+    assert_eq!(
+        notes[0],
+        Note {
+            id: rushed,
+            activity: timed,
+            text: "rushed".to_string(),
+            fields: fields.clone(),
+        }
+    );
+    assert_eq!(notes[1].text, "left hand even");
+    assert_eq!(boxer.get_notes(timed, rushed, &mut notes), Ok(1));
+    assert_eq!(notes[0].id, even);
+
+    let mut journal = vec![JournalEntry::default(); 3];
+    assert_eq!(boxer.get_journal(0, 5000, &mut journal), Ok(2));
+    assert_eq!(journal[0].action, scales);
+    assert_eq!(journal[0].duration, Some(600));
+    assert_eq!(journal[0].notes.len(), 2);
+    assert_eq!(journal[0].notes[0].fields, fields);
+    assert_eq!(
+        journal[1],
+        JournalEntry {
+            activity: 2000,
+            action: pieces,
+            duration: None,
+            notes: vec![],
+        }
+    );
+}
+
+#[test]
+fn exports_everything() {
+    let mut boxer = SqliteBoxes::new(":memory:");
+    let music = boxer.create_action("music").unwrap();
+    let scales = boxer.create_action("scales").unwrap();
+    let old = boxer.create_action("old").unwrap();
+    boxer.make_action_parent_of(music, scales).unwrap();
+    boxer.archive_action(old, true).unwrap();
+    boxer.set_goal(scales, 30, GoalUnit::Minutes).unwrap();
+    let timed = boxer.log_activity_with_duration(scales, 1000, 600).unwrap();
+    boxer.annotate_activity(timed, "rushed").unwrap();
+    boxer.log_activity_at_time(music, 2000).unwrap();
+    boxer.log_activity_at_time(music, -1000).unwrap();
+
+    let export = boxer.export().unwrap();
+    assert_eq!(
+        export.actions,
+        vec![
+            ExportedAction {
+                id: music,
+                name: "music".to_string(),
+                archived: false,
+            },
+            ExportedAction {
+                id: scales,
+                name: "scales".to_string(),
+                archived: false,
+            },
+            ExportedAction {
+                id: old,
+                name: "old".to_string(),
+                archived: true,
+            },
+        ]
+    );
+    assert_eq!(
+        export.hierarchy,
+        vec![ActionLink {
+            parent: music,
+            child: scales,
+        }]
+    );
+    assert_eq!(export.activities.len(), 3);
+    assert_eq!(export.activities[0].activity, -1000);
+    assert_eq!(export.activities[1].duration, Some(600));
+    assert_eq!(export.activities[1].notes[0].text, "rushed");
+    assert_eq!(export.activities[2].action, music);
+    assert_eq!(export.goals.len(), 1);
+    assert_eq!(export.goals[0].target, 30);
+}
@@ -0,0 +1,199 @@
+use crate::auth::{Auth, AuthError, AuthErrorKind};
+use crate::boxchecker::BoxCheckerError;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+
+static BOXES_DIR: &str = "boxes";
+static LEGACY_BOX_PREFIX: &str = "user_";
+static BOX_SUFFIX: &str = ".sqlite";
+static DELETED_SUFFIX: &str = "deleted";
+static PURGE_PAGE: usize = 64;
+
+/// Maps user ids to their box databases under the data directory.
+pub struct BoxPaths {
+    dir: PathBuf,
+}
+
+impl BoxPaths {
+    pub fn new(data_dir: &str) -> Self {
+        BoxPaths {
+            dir: Path::new(data_dir).join(BOXES_DIR),
+        }
+    }
+
+    /// Find the database file for the user id, refusing ids that would name
+    /// anything other than a file directly inside the boxes directory.
+    pub fn resolve(&self, user_id: &str) -> Result<PathBuf, BoxCheckerError> {
+        let invalid = || BoxCheckerError::InvalidInput(format!("invalid user id '{}'", user_id));
+        if user_id.is_empty() || !user_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(invalid());
+        }
+        let path = self.dir.join(format!("{}{}", user_id, BOX_SUFFIX));
+        let mut components = path
+            .strip_prefix(&self.dir)
+            .map_err(|_| invalid())?
+            .components();
+        if !matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        ) {
+            return Err(invalid());
+        }
+
+        fs::create_dir_all(&self.dir).map_err(|e| {
+            BoxCheckerError::Storage(format!("cannot create {}: {}", self.dir.display(), e))
+        })?;
+        // Guard against symlinks leading out of the boxes directory.
+        if path.exists() {
+            let dir = self.dir.canonicalize().map_err(|_| invalid())?;
+            let file = path.canonicalize().map_err(|_| invalid())?;
+            if file.parent() != Some(dir.as_path()) {
+                return Err(invalid());
+            }
+        }
+        Ok(path)
+    }
+
+    /// Bytes used by the user's database, or 0 if they have none yet.
+    pub fn size(&self, user_id: &str) -> Result<u64, BoxCheckerError> {
+        let path = self.resolve(user_id)?;
+        match fs::metadata(&path) {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(BoxCheckerError::Storage(format!(
+                "cannot read {}: {}",
+                path.display(),
+                e
+            ))),
+        }
+    }
+
+    /// Delete the user's database, returning whether there was one.
+    pub fn remove(&self, user_id: &str) -> Result<bool, BoxCheckerError> {
+        let path = self.resolve(user_id)?;
+        match fs::remove_file(&path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(BoxCheckerError::Storage(format!(
+                "cannot remove {}: {}",
+                path.display(),
+                e
+            ))),
+        }
+    }
+
+    /// Delete the user's credentials, sessions, tokens and boxes.
+    /// The boxes are moved aside first, and put back if the user cannot be
+    /// deleted, so that a failure never leaves boxes without an owner or an
+    /// owner whose boxes are half gone.
+    pub fn delete_account(&self, auth: &mut dyn Auth, username: &str) -> Result<(), AuthError> {
+        let storage_error =
+            |e: BoxCheckerError| AuthError::new(AuthErrorKind::Storage, &e.to_string());
+        let user_id = auth.get_user_id(username)?;
+        let path = self.resolve(&user_id).map_err(storage_error)?;
+        let aside = path.with_extension(DELETED_SUFFIX);
+        let moved = match fs::rename(&path, &aside) {
+            Ok(()) => true,
+            Err(e) if e.kind() == ErrorKind::NotFound => false,
+            Err(e) => {
+                return Err(storage_error(BoxCheckerError::Storage(format!(
+                    "cannot move {}: {}",
+                    path.display(),
+                    e
+                ))))
+            }
+        };
+
+        if let Err(e) = auth.delete_user(username) {
+            if moved {
+                if let Err(e) = fs::rename(&aside, &path) {
+                    log::error!("cannot restore {}: {}", path.display(), e);
+                }
+            }
+            return Err(e);
+        }
+        if moved {
+            fs::remove_file(&aside).map_err(|e| {
+                storage_error(BoxCheckerError::Storage(format!(
+                    "cannot remove {}: {}",
+                    aside.display(),
+                    e
+                )))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Delete the accounts whose deletion grace period is over at the time,
+    /// returning how many were deleted.
+    pub fn purge(&self, auth: &mut dyn Auth, now_millis: i64) -> Result<usize, AuthError> {
+        let mut purged = 0;
+        loop {
+            let mut due = vec!["".to_string(); PURGE_PAGE];
+            let count = auth.get_due_deletions(now_millis, &mut due)?;
+            for username in due.iter().take(count) {
+                match self.delete_account(auth, username) {
+                    Ok(()) => {
+                        log::info!("purged account {}", username);
+                        purged += 1;
+                    }
+                    Err(e) if e.kind == AuthErrorKind::NotFound => {
+                        auth.cancel_deletion(username)?;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            if count < PURGE_PAGE {
+                return Ok(purged);
+            }
+        }
+    }
+
+    /// Move databases from the old data/user_<name>.sqlite layout to their
+    /// owners' ids, returning how many moved.
+    /// Files without a matching user are left in place.
+    pub fn migrate(&self, auth: &dyn Auth) -> Result<usize, BoxCheckerError> {
+        let data_dir = match self.dir.parent() {
+            Some(dir) if dir.is_dir() => dir,
+            _ => return Ok(0),
+        };
+        let entries = fs::read_dir(data_dir).map_err(|e| {
+            BoxCheckerError::Storage(format!("cannot read {}: {}", data_dir.display(), e))
+        })?;
+
+        let mut moved = 0;
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let username = match file_name
+                .strip_prefix(LEGACY_BOX_PREFIX)
+                .and_then(|name| name.strip_suffix(BOX_SUFFIX))
+            {
+                Some(username) => username,
+                None => continue,
+            };
+            let user_id = match auth.get_user_id(username) {
+                Ok(user_id) => user_id,
+                Err(e) => {
+                    log::warn!("not migrating {}: {}", file_name, e.msg);
+                    continue;
+                }
+            };
+            let path = self.resolve(&user_id)?;
+            if path.exists() {
+                log::warn!("not migrating {}: {} exists", file_name, path.display());
+                continue;
+            }
+            fs::rename(entry.path(), &path).map_err(|e| {
+                BoxCheckerError::Storage(format!("cannot move {}: {}", file_name, e))
+            })?;
+            log::info!("migrated {} to {}", file_name, path.display());
+            moved += 1;
+        }
+        Ok(moved)
+    }
+}
+
+#[cfg(test)]
+#[path = "./storage_test.rs"]
+mod storage_test;
@@ -0,0 +1,92 @@
+use super::*;
+use crate::auth::{LoginInfo, SqliteAuth};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("okra_{}_{}", name, std::process::id()));
+    fs::remove_dir_all(&path).ok();
+    fs::create_dir_all(&path).unwrap();
+    path
+}
+
+#[test]
+fn resolves_user_ids_inside_data_dir() {
+    let dir = temp_dir("resolves_user_ids");
+    let paths = BoxPaths::new(dir.to_str().unwrap());
+    assert_eq!(
+        paths.resolve("0123abcd").unwrap(),
+        dir.join("boxes").join("0123abcd.sqlite")
+    );
+    for user_id in &["", "../users", "..", "a/b", "/etc/passwd", "bob.sqlite"] {
+        assert!(matches!(
+            paths.resolve(user_id),
+            Err(BoxCheckerError::InvalidInput(_))
+        ));
+    }
+}
+
+#[test]
+fn migrates_legacy_user_files() {
+    let dir = temp_dir("migrates_legacy_user_files");
+    let mut auth = SqliteAuth::new(dir.join("users.sqlite").to_str().unwrap()).unwrap();
+    auth.add_user(&LoginInfo {
+        username: "bob",
+        password: "secret",
+    })
+    .unwrap();
+    fs::write(dir.join("user_bob.sqlite"), "bob's boxes").unwrap();
+    fs::write(dir.join("user_alice.sqlite"), "alice's boxes").unwrap();
+
+    let paths = BoxPaths::new(dir.to_str().unwrap());
+    assert_eq!(paths.migrate(&auth).unwrap(), 1);
+    let path = paths.resolve(&auth.get_user_id("bob").unwrap()).unwrap();
+    assert_eq!(fs::read_to_string(path).unwrap(), "bob's boxes");
+    assert!(!dir.join("user_bob.sqlite").exists());
+    assert!(dir.join("user_alice.sqlite").exists());
+    assert_eq!(paths.migrate(&auth).unwrap(), 0);
+}
+
+#[test]
+fn sizes_and_removes_boxes() {
+    let dir = temp_dir("sizes_and_removes_boxes");
+    let paths = BoxPaths::new(dir.to_str().unwrap());
+    assert_eq!(paths.size("0123abcd").unwrap(), 0);
+    assert!(!paths.remove("0123abcd").unwrap());
+    fs::write(paths.resolve("0123abcd").unwrap(), "bob's boxes").unwrap();
+    assert_eq!(paths.size("0123abcd").unwrap(), 11);
+    assert!(paths.remove("0123abcd").unwrap());
+    assert!(!paths.resolve("0123abcd").unwrap().exists());
+    assert!(paths.size("../users").is_err());
+}
+
+#[test]
+fn deletes_and_purges_accounts() {
+    let dir = temp_dir("deletes_and_purges_accounts");
+    let mut auth = SqliteAuth::new(dir.join("users.sqlite").to_str().unwrap()).unwrap();
+    let paths = BoxPaths::new(dir.to_str().unwrap());
+    let mut boxes = Vec::new();
+    for username in &["bob", "alice", "carol"] {
+        auth.add_user(&LoginInfo {
+            username,
+            password: "secret",
+        })
+        .unwrap();
+        let path = paths.resolve(&auth.get_user_id(username).unwrap()).unwrap();
+        fs::write(&path, "boxes").unwrap();
+        boxes.push(path);
+    }
+
+    paths.delete_account(&mut auth, "carol").unwrap();
+    assert!(auth.get_user_id("carol").is_err());
+    assert!(!boxes[2].exists());
+    assert!(!boxes[2].with_extension("deleted").exists());
+    assert!(paths.delete_account(&mut auth, "carol").is_err());
+
+    auth.schedule_deletion("bob", 1000).unwrap();
+    auth.schedule_deletion("alice", 5000).unwrap();
+    assert_eq!(paths.purge(&mut auth, 2000).unwrap(), 1);
+    assert!(auth.get_user_id("bob").is_err());
+    assert!(!boxes[0].exists());
+    assert!(auth.get_user_id("alice").is_ok());
+    assert!(boxes[1].exists());
+    assert_eq!(paths.purge(&mut auth, 2000).unwrap(), 0);
+}
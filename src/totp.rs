@@ -0,0 +1,88 @@
+//! Time-based one-time passwords as in RFC 6238, using HMAC-SHA1, 30 second
+//! steps and six digits, as authenticator apps expect.
+use hmac::{Hmac, Mac, NewMac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha1::Sha1;
+
+static DIGITS: u32 = 6;
+static SECRET_BYTES: usize = 20;
+static STEP_SECS: i64 = 30;
+
+/// Accept codes from this many steps before or after the current one, to
+/// allow for clock drift and typing time.
+static SKEW_STEPS: i64 = 1;
+
+pub fn new_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; SECRET_BYTES];
+    OsRng.fill_bytes(&mut secret);
+    secret
+}
+
+pub fn encode_secret(secret: &[u8]) -> String {
+    base32::encode(base32::Alphabet::RFC4648 { padding: false }, secret)
+}
+
+pub fn decode_secret(secret: &str) -> Option<Vec<u8>> {
+    base32::decode(base32::Alphabet::RFC4648 { padding: false }, secret)
+}
+
+/// The time step containing the epoch time in milliseconds.
+pub fn step(epoch_millis: i64) -> i64 {
+    epoch_millis.div_euclid(1000 * STEP_SECS)
+}
+
+/// The code for the time step, per RFC 4226 dynamic truncation.
+pub fn code_at_step(secret: &[u8], step: i64, digits: u32) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    binary % 10u32.pow(digits)
+}
+
+/// Find the step, near the time, at which the code is valid, if any.
+pub fn verify(secret: &[u8], code: &str, epoch_millis: i64) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let now = step(epoch_millis);
+    (now - SKEW_STEPS..=now + SKEW_STEPS).find(|s| code_at_step(secret, *s, DIGITS) == code)
+}
+
+/// Describe the secret for authenticator apps, usually shown as a QR code.
+pub fn provisioning_uri(issuer: &str, account: &str, secret: &[u8]) -> String {
+    let issuer = url_escape(issuer);
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        issuer,
+        url_escape(account),
+        encode_secret(secret),
+        issuer,
+        DIGITS,
+        STEP_SECS
+    )
+}
+
+fn url_escape(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+#[path = "./totp_test.rs"]
+mod totp_test;
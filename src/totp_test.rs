@@ -0,0 +1,40 @@
+use super::*;
+
+static RFC_SECRET: &[u8] = b"12345678901234567890";
+
+#[test]
+fn matches_rfc_6238_sha1_vectors() {
+    let vectors = [
+        (59, 94287082),
+        (1111111109, 7081804),
+        (1111111111, 14050471),
+        (1234567890, 89005924),
+        (2000000000, 69279037),
+        (20000000000, 65353130),
+    ];
+    for (secs, expected) in vectors.iter() {
+        assert_eq!(code_at_step(RFC_SECRET, step(secs * 1000), 8), *expected);
+    }
+}
+
+#[test]
+fn verifies_codes_near_the_time() {
+    let now = 1111111111000;
+    assert_eq!(verify(RFC_SECRET, "050471", now), Some(step(now)));
+    assert_eq!(verify(RFC_SECRET, " 050471 ", now + 30000), Some(step(now)));
+    assert_eq!(verify(RFC_SECRET, "050471", now - 30000), Some(step(now)));
+    assert_eq!(verify(RFC_SECRET, "050471", now + 90000), None);
+    assert_eq!(verify(RFC_SECRET, "050472", now), None);
+    assert_eq!(verify(RFC_SECRET, "50471", now), None);
+}
+
+#[test]
+fn describes_secrets_for_apps() {
+    let secret = decode_secret(&encode_secret(RFC_SECRET)).unwrap();
+    assert_eq!(secret, RFC_SECRET);
+    assert_eq!(
+        provisioning_uri("Okra", "bob smith", RFC_SECRET),
+        "otpauth://totp/Okra:bob%20smith?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\
+            &issuer=Okra&algorithm=SHA1&digits=6&period=30"
+    );
+}
@@ -1,10 +1,15 @@
-use okra::auth::{Auth, LoginInfo, SqliteAuth};
+use okra::auth::{check_username, Auth, LoginInfo, Role, SqliteAuth};
+use okra::config::OkraConfig;
 use std::path::PathBuf;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "add-user", about = "Insert a new user into the database.")]
 struct Opt {
+    /// Let the user administer others.
+    #[structopt(long)]
+    admin: bool,
+
     #[structopt(parse(from_os_str))]
     file: PathBuf,
 
@@ -17,10 +22,18 @@ struct Opt {
 
 fn main() {
     let opt = Opt::from_args();
-    let mut auth = SqliteAuth::new(opt.file.as_os_str().to_str().unwrap()).unwrap();
+    if let Err(e) = check_username(&opt.username) {
+        eprintln!("cannot add user: {}", e.msg);
+        std::process::exit(1);
+    }
+    let config = OkraConfig::from_figment(&rocket::Config::figment()).unwrap();
+    let mut auth = SqliteAuth::new(opt.file.as_os_str().to_str().unwrap())
+        .unwrap()
+        .with_password_policy(config.password_policy());
     let login = LoginInfo {
         username: &opt.username,
         password: &opt.password,
     };
-    auth.add_user(&login).unwrap();
+    let role = if opt.admin { Role::Admin } else { Role::User };
+    auth.add_user_with_role(&login, role).unwrap();
 }
@@ -19,6 +19,11 @@ struct Opt {
 fn main() {
     let opt = Opt::from_args();
     let mut boxer = SqliteBoxes::new(opt.file.as_os_str().to_str().unwrap());
-    let id = boxer.create_action(&opt.action_name);
-    println!("{}", id);
+    match boxer.create_action(&opt.action_name) {
+        Ok(id) => println!("{}", id),
+        Err(e) => {
+            eprintln!("cannot create action: {}", e);
+            std::process::exit(1);
+        }
+    }
 }
@@ -22,5 +22,11 @@ struct Opt {
 fn main() {
     let opt = Opt::from_args();
     let mut boxer = SqliteBoxes::new(opt.file.as_os_str().to_str().unwrap());
-    boxer.make_action_parent_of(opt.parent_action, opt.child_action);
+    if let Err(e) = boxer.make_action_parent_of(opt.parent_action, opt.child_action) {
+        eprintln!(
+            "cannot make {} parent of {}: {}",
+            opt.parent_action, opt.child_action, e
+        );
+        std::process::exit(1);
+    }
 }
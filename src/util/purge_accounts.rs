@@ -0,0 +1,34 @@
+use okra::auth::SqliteAuth;
+use okra::auth_backends::USERS_DB_NAME;
+use okra::storage::BoxPaths;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "purge-accounts",
+    about = "Delete accounts whose deletion grace period is over."
+)]
+struct Opt {
+    /// The data directory holding users.sqlite and the boxes.
+    #[structopt(parse(from_os_str))]
+    data_dir: PathBuf,
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    let users = opt.data_dir.join(USERS_DB_NAME);
+    let mut auth = SqliteAuth::new(users.to_str().unwrap()).unwrap();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64;
+    match BoxPaths::new(opt.data_dir.to_str().unwrap()).purge(&mut auth, now) {
+        Ok(purged) => println!("purged {} accounts", purged),
+        Err(e) => {
+            eprintln!("cannot purge accounts: {}", e.msg);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use okra::auth::{Auth, SqliteAuth};
+use okra::config::OkraConfig;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "reset-password",
+    about = "Set a user's password and end their sessions."
+)]
+struct Opt {
+    #[structopt(parse(from_os_str))]
+    file: PathBuf,
+
+    #[structopt(short, long)]
+    password: String,
+
+    #[structopt(short, long)]
+    username: String,
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    let config = OkraConfig::from_figment(&rocket::Config::figment()).unwrap();
+    let mut auth = SqliteAuth::new(opt.file.as_os_str().to_str().unwrap())
+        .unwrap()
+        .with_password_policy(config.password_policy());
+    if let Err(e) = auth
+        .set_password(&opt.username, &opt.password)
+        .and_then(|_| auth.revoke_sessions(&opt.username))
+    {
+        eprintln!("cannot reset password: {}", e.msg);
+        std::process::exit(1);
+    }
+}